@@ -0,0 +1,29 @@
+CREATE TABLE topics (
+  name TEXT NOT NULL,
+  description TEXT NOT NULL,
+  created_at timestamptz NOT NULL,
+  PRIMARY KEY(name)
+);
+
+-- Subscribers receive every topic, unless they opted out of it
+CREATE TABLE topic_opt_outs (
+  subscriber_id uuid NOT NULL REFERENCES subscriptions(id),
+  topic TEXT NOT NULL REFERENCES topics(name),
+  PRIMARY KEY(subscriber_id, topic)
+);
+
+-- Issues without a topic go out to every confirmed subscriber
+ALTER TABLE newsletter_issues ADD COLUMN topic TEXT NULL REFERENCES topics(name);
+
+-- Set when the token confirms a change of email address rather than a new subscriber
+ALTER TABLE subscription_tokens ADD COLUMN new_email TEXT NULL;
+
+CREATE TABLE subscriber_preference_changes (
+  change_id uuid NOT NULL,
+  subscriber_id uuid NOT NULL REFERENCES subscriptions(id),
+  field TEXT NOT NULL,
+  old_value TEXT NOT NULL,
+  new_value TEXT NOT NULL,
+  changed_at timestamptz NOT NULL,
+  PRIMARY KEY(change_id)
+);
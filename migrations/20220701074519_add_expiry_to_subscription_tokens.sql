@@ -0,0 +1,6 @@
+-- Tokens issued so far get a full day from now
+ALTER TABLE subscription_tokens ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
+ALTER TABLE subscription_tokens ADD COLUMN expires_at timestamptz NOT NULL DEFAULT now() + interval '1 day';
+ALTER TABLE subscription_tokens ALTER COLUMN created_at DROP DEFAULT;
+ALTER TABLE subscription_tokens ALTER COLUMN expires_at DROP DEFAULT;
+ALTER TABLE subscription_tokens ADD COLUMN consumed_at timestamptz NULL;
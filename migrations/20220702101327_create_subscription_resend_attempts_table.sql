@@ -0,0 +1,9 @@
+CREATE TABLE subscription_resend_attempts (
+  attempt_id uuid NOT NULL,
+  email TEXT NOT NULL,
+  ip TEXT NOT NULL,
+  requested_at timestamptz NOT NULL,
+  PRIMARY KEY(attempt_id)
+);
+CREATE INDEX subscription_resend_attempts_email_idx ON subscription_resend_attempts (email, requested_at);
+CREATE INDEX subscription_resend_attempts_ip_idx ON subscription_resend_attempts (ip, requested_at);
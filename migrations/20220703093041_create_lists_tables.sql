@@ -0,0 +1,43 @@
+-- Each list is a newsletter of its own, with its own audience and sender.
+-- Issues of lists without sender details go out from the configured sender.
+CREATE TABLE lists (
+  list_id uuid NOT NULL,
+  slug TEXT NOT NULL UNIQUE,
+  name TEXT NOT NULL,
+  sender_name TEXT NULL,
+  sender_email TEXT NULL,
+  created_at timestamptz NOT NULL,
+  PRIMARY KEY(list_id)
+);
+
+-- Memberships are confirmed along with the subscriber, through double opt-in
+CREATE TABLE list_subscriptions (
+  list_id uuid NOT NULL REFERENCES lists(list_id),
+  subscriber_id uuid NOT NULL REFERENCES subscriptions(id),
+  status TEXT NOT NULL,
+  subscribed_at timestamptz NOT NULL,
+  PRIMARY KEY(list_id, subscriber_id)
+);
+
+CREATE TABLE newsletter_issue_lists (
+  newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues(newsletter_issue_id),
+  list_id uuid NOT NULL REFERENCES lists(list_id),
+  PRIMARY KEY(newsletter_issue_id, list_id)
+);
+
+-- The list a delivery goes out on behalf of, which picks the sender
+ALTER TABLE issue_delivery_queue ADD COLUMN list_id uuid NULL REFERENCES lists(list_id);
+ALTER TABLE issue_delivery_failures ADD COLUMN list_id uuid NULL REFERENCES lists(list_id);
+
+-- Everyone who was subscribed so far ends up in the default list
+INSERT INTO lists (list_id, slug, name, created_at)
+VALUES ('8c3b8f0e-3e7a-4f43-9a6e-2f1d6c0b7a51', 'default', 'Newsletter', now());
+
+INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+SELECT '8c3b8f0e-3e7a-4f43-9a6e-2f1d6c0b7a51', id, status, subscribed_at
+FROM subscriptions
+WHERE status IN ('confirmed', 'pending_confirmation');
+
+INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+SELECT newsletter_issue_id, '8c3b8f0e-3e7a-4f43-9a6e-2f1d6c0b7a51'
+FROM newsletter_issues;
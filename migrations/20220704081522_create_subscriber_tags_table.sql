@@ -0,0 +1,10 @@
+CREATE TABLE subscriber_tags (
+  subscriber_id uuid NOT NULL REFERENCES subscriptions(id),
+  tag TEXT NOT NULL,
+  tagged_at timestamptz NOT NULL,
+  PRIMARY KEY(subscriber_id, tag)
+);
+CREATE INDEX subscriber_tags_tag_idx ON subscriber_tags (tag);
+
+-- The tag filter the issue was sent with, if any
+ALTER TABLE newsletter_issues ADD COLUMN segment TEXT NULL;
@@ -0,0 +1,2 @@
+-- Free-form fields issues can be personalised with, e.g. `{"company": "Acme"}`
+ALTER TABLE subscriptions ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,7 @@
+-- Subscribers who had their data erased, by a keyed hash of their email address,
+-- so that they aren't imported again without storing the address itself
+CREATE TABLE erased_subscribers (
+  email_hash TEXT NOT NULL,
+  erased_at timestamptz NOT NULL,
+  PRIMARY KEY(email_hash)
+);
@@ -0,0 +1,8 @@
+-- Addresses that must never be emailed again, whoever subscribes them.
+-- Erased subscribers are suppressed too, through `erased_subscribers`.
+CREATE TABLE suppressions (
+  email TEXT NOT NULL,
+  reason TEXT NOT NULL,
+  suppressed_at timestamptz NOT NULL,
+  PRIMARY KEY(email)
+);
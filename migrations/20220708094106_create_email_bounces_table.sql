@@ -0,0 +1,12 @@
+-- Bounces and spam complaints reported by the email provider.
+-- The provider's id makes replayed webhooks count only once.
+CREATE TABLE email_bounces (
+  provider_bounce_id TEXT NOT NULL,
+  email TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  provider_message_id TEXT NULL,
+  description TEXT NOT NULL,
+  bounced_at timestamptz NOT NULL,
+  PRIMARY KEY(provider_bounce_id)
+);
+CREATE INDEX email_bounces_email_idx ON email_bounces (email, kind);
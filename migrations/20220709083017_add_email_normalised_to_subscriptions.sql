@@ -0,0 +1,179 @@
+-- Addresses are unique in their normalised form, so that `Foo@Example.com` and
+-- `foo@example.com` are the same subscriber. `email` keeps the address as it was
+-- typed, which is the one we send to.
+--
+-- PostgreSQL can't convert internationalised domains to punycode, so they are only
+-- lower-cased here: `subscribers normalise-emails` finishes the job.
+ALTER TABLE subscriptions ADD COLUMN email_normalised TEXT NULL;
+UPDATE subscriptions SET email_normalised = lower(btrim(email));
+
+-- Each set of duplicates is merged into one subscriber: the confirmed one if any,
+-- otherwise the one who subscribed first.
+CREATE TEMPORARY TABLE merged_subscribers AS
+SELECT id AS duplicate_id, survivor_id
+FROM (
+  SELECT
+    id,
+    first_value(id) OVER (
+      PARTITION BY email_normalised
+      ORDER BY
+        CASE status
+          WHEN 'confirmed' THEN 0
+          WHEN 'pending_confirmation' THEN 1
+          ELSE 2
+        END,
+        subscribed_at,
+        id
+    ) AS survivor_id
+  FROM subscriptions
+) ranked
+WHERE id <> survivor_id;
+
+-- Memberships, tags and opt-outs add up, confirmed memberships win over pending ones
+INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+SELECT DISTINCT ON (merged.survivor_id, list_subscriptions.list_id)
+  list_subscriptions.list_id,
+  merged.survivor_id,
+  list_subscriptions.status,
+  list_subscriptions.subscribed_at
+FROM list_subscriptions
+JOIN merged_subscribers merged ON merged.duplicate_id = list_subscriptions.subscriber_id
+ORDER BY
+  merged.survivor_id,
+  list_subscriptions.list_id,
+  list_subscriptions.status = 'confirmed' DESC,
+  list_subscriptions.subscribed_at
+ON CONFLICT (list_id, subscriber_id) DO UPDATE
+SET status = EXCLUDED.status
+WHERE list_subscriptions.status <> 'confirmed' AND EXCLUDED.status = 'confirmed';
+
+INSERT INTO subscriber_tags (subscriber_id, tag, tagged_at)
+SELECT merged.survivor_id, subscriber_tags.tag, subscriber_tags.tagged_at
+FROM subscriber_tags
+JOIN merged_subscribers merged ON merged.duplicate_id = subscriber_tags.subscriber_id
+ON CONFLICT (subscriber_id, tag) DO NOTHING;
+
+INSERT INTO topic_opt_outs (subscriber_id, topic)
+SELECT merged.survivor_id, topic_opt_outs.topic
+FROM topic_opt_outs
+JOIN merged_subscribers merged ON merged.duplicate_id = topic_opt_outs.subscriber_id
+ON CONFLICT (subscriber_id, topic) DO NOTHING;
+
+-- Attributes of the survivor win over those of its duplicates
+UPDATE subscriptions
+SET attributes = duplicates.attributes || subscriptions.attributes
+FROM (
+  SELECT merged.survivor_id, jsonb_object_agg(attribute.key, attribute.value) AS attributes
+  FROM merged_subscribers merged
+  JOIN subscriptions duplicate ON duplicate.id = merged.duplicate_id
+  CROSS JOIN jsonb_each(duplicate.attributes) attribute
+  GROUP BY merged.survivor_id
+) duplicates
+WHERE subscriptions.id = duplicates.survivor_id;
+
+UPDATE subscription_tokens
+SET subscriber_id = merged.survivor_id
+FROM merged_subscribers merged
+WHERE subscription_tokens.subscriber_id = merged.duplicate_id;
+
+UPDATE subscriber_preference_changes
+SET subscriber_id = merged.survivor_id
+FROM merged_subscribers merged
+WHERE subscriber_preference_changes.subscriber_id = merged.duplicate_id;
+
+-- Deliveries are keyed by address: those of duplicates move over to the survivor's,
+-- keeping a single one per issue, the survivor's own if it has one
+CREATE TEMPORARY TABLE merged_addresses AS
+SELECT duplicate.email AS duplicate_email, survivor.email AS survivor_email
+FROM merged_subscribers merged
+JOIN subscriptions duplicate ON duplicate.id = merged.duplicate_id
+JOIN subscriptions survivor ON survivor.id = merged.survivor_id;
+
+DELETE FROM issue_delivery_queue
+WHERE ctid IN (
+  SELECT ctid
+  FROM (
+    SELECT
+      issue_delivery_queue.ctid,
+      row_number() OVER (
+        PARTITION BY
+          issue_delivery_queue.newsletter_issue_id,
+          COALESCE(merged.survivor_email, issue_delivery_queue.subscriber_email)
+        ORDER BY merged.survivor_email IS NOT NULL, issue_delivery_queue.subscriber_email
+      ) AS n
+    FROM issue_delivery_queue
+    LEFT JOIN merged_addresses merged ON merged.duplicate_email = issue_delivery_queue.subscriber_email
+  ) ranked
+  WHERE n > 1
+);
+UPDATE issue_delivery_queue
+SET subscriber_email = merged.survivor_email
+FROM merged_addresses merged
+WHERE issue_delivery_queue.subscriber_email = merged.duplicate_email;
+
+DELETE FROM issue_delivery_failures
+WHERE ctid IN (
+  SELECT ctid
+  FROM (
+    SELECT
+      issue_delivery_failures.ctid,
+      row_number() OVER (
+        PARTITION BY
+          issue_delivery_failures.newsletter_issue_id,
+          COALESCE(merged.survivor_email, issue_delivery_failures.subscriber_email)
+        ORDER BY merged.survivor_email IS NOT NULL, issue_delivery_failures.subscriber_email
+      ) AS n
+    FROM issue_delivery_failures
+    LEFT JOIN merged_addresses merged ON merged.duplicate_email = issue_delivery_failures.subscriber_email
+  ) ranked
+  WHERE n > 1
+);
+UPDATE issue_delivery_failures
+SET subscriber_email = merged.survivor_email
+FROM merged_addresses merged
+WHERE issue_delivery_failures.subscriber_email = merged.duplicate_email;
+
+DELETE FROM newsletter_issue_deliveries
+WHERE ctid IN (
+  SELECT ctid
+  FROM (
+    SELECT
+      newsletter_issue_deliveries.ctid,
+      row_number() OVER (
+        PARTITION BY
+          newsletter_issue_deliveries.newsletter_issue_id,
+          COALESCE(merged.survivor_email, newsletter_issue_deliveries.subscriber_email)
+        ORDER BY merged.survivor_email IS NOT NULL, newsletter_issue_deliveries.subscriber_email
+      ) AS n
+    FROM newsletter_issue_deliveries
+    LEFT JOIN merged_addresses merged ON merged.duplicate_email = newsletter_issue_deliveries.subscriber_email
+  ) ranked
+  WHERE n > 1
+);
+UPDATE newsletter_issue_deliveries
+SET subscriber_email = merged.survivor_email
+FROM merged_addresses merged
+WHERE newsletter_issue_deliveries.subscriber_email = merged.duplicate_email;
+
+DELETE FROM list_subscriptions
+USING merged_subscribers merged
+WHERE list_subscriptions.subscriber_id = merged.duplicate_id;
+DELETE FROM subscriber_tags
+USING merged_subscribers merged
+WHERE subscriber_tags.subscriber_id = merged.duplicate_id;
+DELETE FROM topic_opt_outs
+USING merged_subscribers merged
+WHERE topic_opt_outs.subscriber_id = merged.duplicate_id;
+DELETE FROM subscriptions
+USING merged_subscribers merged
+WHERE subscriptions.id = merged.duplicate_id;
+
+DROP TABLE merged_addresses;
+DROP TABLE merged_subscribers;
+
+-- Rate limits count attempts by normalised address too
+UPDATE subscription_resend_attempts SET email = lower(btrim(email));
+
+ALTER TABLE subscriptions ALTER COLUMN email_normalised SET NOT NULL;
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_email_key;
+CREATE UNIQUE INDEX subscriptions_email_normalised_idx ON subscriptions (email_normalised);
@@ -1,5 +1,7 @@
+mod basic;
 mod middleware;
 mod password;
 
+pub use basic::*;
 pub use middleware::*;
 pub use password::*;
@@ -0,0 +1,375 @@
+use {
+    anyhow::Context,
+    futures::StreamExt,
+    mailcrab::{
+        configuration::{get_config, Settings},
+        domain::normalise_email,
+        routes::{get_list_by_slug, List, DEFAULT_LIST},
+        startup::get_db_pool,
+        subscriber_export::{export_subscribers, ExportFilter, ExportFormat},
+        subscriber_import::{ColumnMapping, ImportStatus, SubscriberImport},
+        telemetry::{get_subscriber, init_subscriber},
+        utils::is_unique_violation,
+    },
+    sqlx::PgPool,
+    std::io::{BufWriter, Write},
+};
+
+const USAGE: &str = "\
+Usage:
+    subscribers import <CSV file> [options]
+    subscribers export [options]
+    subscribers normalise-emails
+
+Import options:
+    --email-column <header>          Column holding email addresses [default: Email Address]
+    --name-columns <headers>         Columns holding names, separated by commas
+                                     [default: First Name, Last Name]
+    --attribute-columns <mapping>    Attributes to import, e.g. company=Company,plan=Plan
+    --list <slug>                    List to add subscribers to [default: default]
+    --confirmed                      Import subscribers as confirmed,
+                                     rather than sending them a confirmation email
+
+Export options:
+    --format <format>                csv or ndjson [default: csv]
+    --status <status>                Only subscribers with the status
+    --from <YYYY-MM-DD>              Only subscribers who subscribed on or after the day
+    --until <YYYY-MM-DD>             Only subscribers who subscribed on or before the day
+    --list <slug>                    Only members of the list
+
+normalise-emails recomputes the normalised addresses that the database couldn't,
+i.e. those with an internationalised domain, of subscribers, suppressions, bounces
+and resend attempts.";
+
+struct ImportArguments {
+    path: String,
+    email_column: String,
+    name_columns: String,
+    attribute_columns: String,
+    list: String,
+    status: ImportStatus,
+}
+
+struct ExportArguments {
+    format: ExportFormat,
+    status: Option<String>,
+    from: Option<String>,
+    until: Option<String>,
+    list: Option<String>,
+}
+
+enum Command {
+    Import(ImportArguments),
+    Export(ExportArguments),
+    NormaliseEmails,
+}
+
+fn parse_arguments(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    match args.next().as_deref() {
+        Some("import") => parse_import_arguments(args).map(Command::Import),
+        Some("export") => parse_export_arguments(args).map(Command::Export),
+        Some("normalise-emails") => match args.next() {
+            Some(arg) => Err(format!("Unexpected argument {}.", arg)),
+            None => Ok(Command::NormaliseEmails),
+        },
+        Some(command) => Err(format!("Unknown command {}.", command)),
+        None => Err("The command is missing.".into()),
+    }
+}
+
+fn parse_import_arguments(
+    mut args: impl Iterator<Item = String>,
+) -> Result<ImportArguments, String> {
+    let mut path = None;
+    let mut arguments = ImportArguments {
+        path: String::new(),
+        email_column: "Email Address".into(),
+        name_columns: "First Name, Last Name".into(),
+        attribute_columns: String::new(),
+        list: DEFAULT_LIST.into(),
+        status: ImportStatus::PendingConfirmation,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value.", arg));
+        match arg.as_str() {
+            "--email-column" => arguments.email_column = value()?,
+            "--name-columns" => arguments.name_columns = value()?,
+            "--attribute-columns" => arguments.attribute_columns = value()?,
+            "--list" => arguments.list = value()?,
+            "--confirmed" => arguments.status = ImportStatus::Confirmed,
+            _ if arg.starts_with("--") || path.is_some() => {
+                return Err(format!("Unexpected argument {}.", arg))
+            }
+            _ => path = Some(arg),
+        }
+    }
+    arguments.path = path.ok_or("The CSV file is missing.")?;
+
+    Ok(arguments)
+}
+
+fn parse_export_arguments(
+    mut args: impl Iterator<Item = String>,
+) -> Result<ExportArguments, String> {
+    let mut arguments = ExportArguments {
+        format: ExportFormat::Csv,
+        status: None,
+        from: None,
+        until: None,
+        list: None,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value.", arg));
+        match arg.as_str() {
+            "--format" => arguments.format = ExportFormat::parse(&value()?)?,
+            "--status" => arguments.status = Some(value()?),
+            "--from" => arguments.from = Some(value()?),
+            "--until" => arguments.until = Some(value()?),
+            "--list" => arguments.list = Some(value()?),
+            _ => return Err(format!("Unexpected argument {}.", arg)),
+        }
+    }
+
+    Ok(arguments)
+}
+
+/// Import subscribers from a CSV export, e.g. to migrate from another newsletter platform,
+/// export them to hand them over or move them to another environment,
+/// or finish normalising their addresses after a migration
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let command = match parse_arguments(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    // The export is written to stdout, logs go to stderr
+    let subscriber = get_subscriber("subscribers".into(), "info".into(), std::io::stderr);
+    init_subscriber(subscriber);
+
+    let app_config = get_config().expect("Failed to read configuration");
+    match command {
+        Command::Import(arguments) => import(app_config, arguments).await,
+        Command::Export(arguments) => export(app_config, arguments).await,
+        Command::NormaliseEmails => normalise_emails(app_config).await,
+    }
+}
+
+async fn import(app_config: Settings, arguments: ImportArguments) -> anyhow::Result<()> {
+    let csv = std::fs::read_to_string(&arguments.path)
+        .with_context(|| format!("Failed to read {}", arguments.path))?;
+    let mapping = ColumnMapping::parse(
+        &arguments.email_column,
+        &arguments.name_columns,
+        &arguments.attribute_columns,
+    )
+    .map_err(anyhow::Error::msg)?;
+    let import = SubscriberImport::parse(&csv, &mapping).map_err(anyhow::Error::msg)?;
+
+    let pool = get_db_pool(&app_config.database);
+    let list = get_list(&pool, &arguments.list).await?;
+    let report = import
+        .run(
+            &pool,
+            &app_config.email_client.client(),
+            &app_config.application.base_url,
+            app_config.subscriptions.confirmation_token_expires_at(),
+            &list,
+            arguments.status,
+            &app_config.application.hmac_secret,
+        )
+        .await?;
+
+    println!("Imported {} subscribers.", report.n_imported);
+    println!("Skipped {} rows:", report.skipped.len());
+    for row in &report.skipped {
+        println!("    line {}: {}", row.line, row.message);
+    }
+    println!("{} rows have errors:", report.errors.len());
+    for row in &report.errors {
+        println!("    line {}: {}", row.line, row.message);
+    }
+
+    Ok(())
+}
+
+async fn export(app_config: Settings, arguments: ExportArguments) -> anyhow::Result<()> {
+    let pool = get_db_pool(&app_config.database);
+    let list_id = match &arguments.list {
+        Some(slug) => Some(get_list(&pool, slug).await?.list_id),
+        None => None,
+    };
+    let filter = ExportFilter::parse(
+        arguments.status.as_deref(),
+        arguments.from.as_deref(),
+        arguments.until.as_deref(),
+        list_id,
+    )
+    .map_err(anyhow::Error::msg)?;
+
+    let mut rows = export_subscribers(pool, filter, arguments.format);
+    let stdout = std::io::stdout();
+    let mut stdout = BufWriter::new(stdout.lock());
+    while let Some(row) = rows.next().await {
+        stdout.write_all(row?.as_bytes())?;
+    }
+    stdout.flush()?;
+
+    Ok(())
+}
+
+async fn normalise_emails(app_config: Settings) -> anyhow::Result<()> {
+    let pool = get_db_pool(&app_config.database);
+    let subscribers = sqlx::query!("SELECT id, email, email_normalised FROM subscriptions")
+        .fetch_all(&pool)
+        .await
+        .context("Failed to fetch subscribers")?;
+
+    let mut n_updated = 0;
+    let mut duplicates = Vec::new();
+    for subscriber in subscribers {
+        let email_normalised = normalise_email(&subscriber.email);
+        if email_normalised == subscriber.email_normalised {
+            continue;
+        }
+        let result = sqlx::query!(
+            "UPDATE subscriptions SET email_normalised = $2 WHERE id = $1",
+            subscriber.id,
+            email_normalised
+        )
+        .execute(&pool)
+        .await;
+        match result {
+            Ok(_) => n_updated += 1,
+            // Another subscriber already has the address, in another spelling
+            Err(e) if is_unique_violation(&e) => duplicates.push(subscriber.email),
+            Err(e) => return Err(e).context("Failed to update a normalised address"),
+        }
+    }
+
+    // Suppressions, bounces and rate limits are keyed by normalised address too
+    let n_suppressions = normalise_suppressions(&pool).await?;
+    let n_bounces = normalise_bounces(&pool).await?;
+    let n_attempts = normalise_resend_attempts(&pool).await?;
+
+    println!("Normalised {} addresses.", n_updated);
+    println!(
+        "{} addresses are duplicates, to merge by hand:",
+        duplicates.len()
+    );
+    for email in &duplicates {
+        println!("    {}", email);
+    }
+    println!(
+        "Normalised {} suppressed addresses, {} bounced addresses \
+         and {} addresses of resend attempts.",
+        n_suppressions, n_bounces, n_attempts
+    );
+
+    Ok(())
+}
+
+/// An address suppressed in both spellings keeps its first suppression
+async fn normalise_suppressions(pool: &PgPool) -> anyhow::Result<usize> {
+    let emails = sqlx::query!("SELECT email FROM suppressions")
+        .fetch_all(pool)
+        .await
+        .context("Failed to fetch suppressed addresses")?;
+
+    let mut n_updated = 0;
+    for email in emails.into_iter().map(|r| r.email) {
+        let email_normalised = normalise_email(&email);
+        if email_normalised == email {
+            continue;
+        }
+        sqlx::query!(
+            r#"
+            WITH moved AS (
+                DELETE FROM suppressions
+                WHERE email = $1
+                RETURNING reason, suppressed_at
+            )
+            INSERT INTO suppressions (email, reason, suppressed_at)
+            SELECT $2, reason, suppressed_at
+            FROM moved
+            ON CONFLICT (email) DO UPDATE
+            SET
+                reason = EXCLUDED.reason,
+                suppressed_at = EXCLUDED.suppressed_at
+            WHERE suppressions.suppressed_at > EXCLUDED.suppressed_at
+            "#,
+            email,
+            email_normalised
+        )
+        .execute(pool)
+        .await
+        .context("Failed to update a suppressed address")?;
+        n_updated += 1;
+    }
+
+    Ok(n_updated)
+}
+
+async fn normalise_bounces(pool: &PgPool) -> anyhow::Result<usize> {
+    let emails = sqlx::query!("SELECT DISTINCT email FROM email_bounces")
+        .fetch_all(pool)
+        .await
+        .context("Failed to fetch bounced addresses")?;
+
+    let mut n_updated = 0;
+    for email in emails.into_iter().map(|r| r.email) {
+        let email_normalised = normalise_email(&email);
+        if email_normalised == email {
+            continue;
+        }
+        sqlx::query!(
+            "UPDATE email_bounces SET email = $2 WHERE email = $1",
+            email,
+            email_normalised
+        )
+        .execute(pool)
+        .await
+        .context("Failed to update a bounced address")?;
+        n_updated += 1;
+    }
+
+    Ok(n_updated)
+}
+
+async fn normalise_resend_attempts(pool: &PgPool) -> anyhow::Result<usize> {
+    let emails = sqlx::query!("SELECT DISTINCT email FROM subscription_resend_attempts")
+        .fetch_all(pool)
+        .await
+        .context("Failed to fetch the addresses of resend attempts")?;
+
+    let mut n_updated = 0;
+    for email in emails.into_iter().map(|r| r.email) {
+        let email_normalised = normalise_email(&email);
+        if email_normalised == email {
+            continue;
+        }
+        sqlx::query!(
+            "UPDATE subscription_resend_attempts SET email = $2 WHERE email = $1",
+            email,
+            email_normalised
+        )
+        .execute(pool)
+        .await
+        .context("Failed to update the address of resend attempts")?;
+        n_updated += 1;
+    }
+
+    Ok(n_updated)
+}
+
+async fn get_list(pool: &PgPool, slug: &str) -> anyhow::Result<List> {
+    let mut transaction = pool.begin().await?;
+    let list = get_list_by_slug(&mut transaction, slug)
+        .await?
+        .with_context(|| format!("There is no list {}.", slug))?;
+    transaction.rollback().await?;
+
+    Ok(list)
+}
@@ -0,0 +1,196 @@
+use {
+    crate::{
+        domain::normalise_email,
+        issue_delivery_worker::DeliveryStatus,
+        suppressions::{suppress, SuppressionReason},
+    },
+    anyhow::Context,
+    chrono::{DateTime, Utc},
+    sqlx::{PgPool, Postgres, Transaction},
+};
+
+/// What the email provider reported about an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BounceKind {
+    /// The address doesn't exist or permanently rejects our emails
+    Hard,
+    /// The address couldn't take the email for now, e.g. because its mailbox is full
+    Soft,
+    /// The recipient marked the email as spam
+    SpamComplaint,
+}
+
+impl BounceKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BounceKind::Hard => "hard",
+            BounceKind::Soft => "soft",
+            BounceKind::SpamComplaint => "spam_complaint",
+        }
+    }
+
+    /// Status of the delivery that bounced, in the delivery ledger
+    fn delivery_status(&self) -> DeliveryStatus {
+        match self {
+            BounceKind::Hard => DeliveryStatus::Bounced,
+            BounceKind::Soft => DeliveryStatus::SoftBounced,
+            BounceKind::SpamComplaint => DeliveryStatus::Complained,
+        }
+    }
+}
+
+/// A bounce or spam complaint, as reported by the email provider
+#[derive(Debug)]
+pub struct Bounce {
+    /// Identifies the report, so that it is only counted once
+    pub provider_bounce_id: String,
+    pub email: String,
+    pub kind: BounceKind,
+    /// The message that bounced, as returned by the provider when it was sent
+    pub provider_message_id: Option<String>,
+    pub description: String,
+    pub bounced_at: DateTime<Utc>,
+}
+
+/// What recording a bounce led to
+#[derive(Debug, PartialEq, Eq)]
+pub enum BounceOutcome {
+    /// The report was received before
+    AlreadyRecorded,
+    /// The address is suppressed, or already was
+    Suppressed,
+    /// A soft bounce that didn't reach the threshold, yet
+    Counted { n_soft_bounces: i64 },
+}
+
+/// Record a bounce, update the delivery that bounced, and suppress the address
+/// on hard bounces, spam complaints, or once its soft bounces reach `soft_bounce_threshold`.
+///
+/// Soft bounces are counted until the suppression of the address is lifted.
+#[tracing::instrument(skip(pool))]
+pub async fn record_bounce(
+    pool: &PgPool,
+    bounce: &Bounce,
+    soft_bounce_threshold: u32,
+) -> Result<BounceOutcome, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    if !insert_bounce(&mut transaction, bounce)
+        .await
+        .context("Failed to record the bounce.")?
+    {
+        return Ok(BounceOutcome::AlreadyRecorded);
+    }
+    if let Some(provider_message_id) = &bounce.provider_message_id {
+        update_delivery(&mut transaction, provider_message_id, bounce.kind)
+            .await
+            .context("Failed to update the delivery that bounced.")?;
+    }
+
+    let reason = match bounce.kind {
+        BounceKind::Hard => Some(SuppressionReason::HardBounce),
+        BounceKind::SpamComplaint => Some(SuppressionReason::SpamComplaint),
+        BounceKind::Soft => {
+            let n_soft_bounces = count_soft_bounces(&mut transaction, &bounce.email)
+                .await
+                .context("Failed to count the soft bounces of the address.")?;
+            if n_soft_bounces < i64::from(soft_bounce_threshold) {
+                transaction
+                    .commit()
+                    .await
+                    .context("Failed to commit SQL transaction to record a bounce.")?;
+                return Ok(BounceOutcome::Counted { n_soft_bounces });
+            }
+            Some(SuppressionReason::SoftBounces)
+        }
+    };
+    if let Some(reason) = reason {
+        suppress(&mut transaction, &bounce.email, reason)
+            .await
+            .context("Failed to suppress the address that bounced.")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to record a bounce.")?;
+
+    Ok(BounceOutcome::Suppressed)
+}
+
+/// Returns whether the bounce wasn't recorded before
+#[tracing::instrument(skip(transaction))]
+async fn insert_bounce(
+    transaction: &mut Transaction<'_, Postgres>,
+    bounce: &Bounce,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO email_bounces (
+            provider_bounce_id,
+            email,
+            kind,
+            provider_message_id,
+            description,
+            bounced_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (provider_bounce_id) DO NOTHING
+        "#,
+        bounce.provider_bounce_id,
+        normalise_email(&bounce.email),
+        bounce.kind.as_str(),
+        bounce.provider_message_id,
+        bounce.description,
+        bounce.bounced_at
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn update_delivery(
+    transaction: &mut Transaction<'_, Postgres>,
+    provider_message_id: &str,
+    kind: BounceKind,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issue_deliveries
+        SET
+            status = $2,
+            updated_at = now()
+        WHERE provider_message_id = $1
+        "#,
+        provider_message_id,
+        kind.delivery_status().as_str()
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn count_soft_bounces(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+) -> Result<i64, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "n_soft_bounces!"
+        FROM email_bounces
+        WHERE
+            email = $1 AND
+            kind = 'soft'
+        "#,
+        normalise_email(email)
+    )
+    .fetch_one(transaction)
+    .await?;
+
+    Ok(row.n_soft_bounces)
+}
@@ -1,5 +1,10 @@
 use {
-    crate::{domain::SubscriberEmail, email_client::EmailClient},
+    crate::{
+        domain::SubscriberEmail,
+        email_client::{
+            EmailClient, EmailTransport, FileTransport, PostmarkTransport, SmtpTransport,
+        },
+    },
     secrecy::{ExposeSecret, Secret},
     serde::Deserialize,
     serde_aux::field_attributes::deserialize_number_from_string,
@@ -7,6 +12,7 @@ use {
         postgres::{PgConnectOptions, PgSslMode},
         ConnectOptions,
     },
+    std::path::PathBuf,
 };
 
 #[derive(Deserialize, Clone)]
@@ -15,6 +21,9 @@ pub struct Settings {
     pub database: DatabaseSettings,
     pub email_client: EmailClientSettings,
     pub redis_uri: Secret<String>,
+    pub subscriptions: SubscriptionSettings,
+    pub worker: WorkerSettings,
+    pub webhooks: WebhookSettings,
 }
 
 #[derive(Deserialize, Clone)]
@@ -24,6 +33,11 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    /// Where to send visitors at the end of the subscription flows, instead of
+    /// rendering our own pages, e.g. when the site is served by a separate frontend.
+    /// The outcome is passed along in the `status` query parameter.
+    #[serde(default)]
+    pub confirmation_redirect_url: Option<String>,
 }
 
 #[derive(Deserialize, Clone)]
@@ -62,17 +76,102 @@ impl DatabaseSettings {
 
 #[derive(Deserialize, Clone)]
 pub struct EmailClientSettings {
-    pub base_url: String,
     pub sender_email: String,
-    pub auth_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    /// The provider emails are sent through while it is healthy
+    #[serde(flatten)]
+    pub provider: EmailProviderSettings,
+    /// Providers to fall over to, in order, when the ones before them keep failing
+    #[serde(default)]
+    pub fallback_providers: Vec<EmailProviderSettings>,
+    /// How many failures in a row make a provider be skipped
+    pub circuit_breaker_threshold: u32,
+    /// How long a failing provider is skipped before it is tried again
+    pub circuit_breaker_cooldown_milliseconds: u64,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct EmailProviderSettings {
+    /// Which transport emails are sent through
+    pub kind: EmailTransportKind,
+    /// Required when `kind` is `postmark`
+    pub base_url: Option<String>,
+    /// Required when `kind` is `postmark`
+    pub auth_token: Option<Secret<String>>,
+    /// Required when `kind` is `smtp`
+    pub smtp: Option<SmtpSettings>,
+    /// Required when `kind` is `file`
+    pub file: Option<FileSettings>,
+}
+
+impl EmailProviderSettings {
+    pub fn transport(self, timeout: std::time::Duration) -> Box<dyn EmailTransport> {
+        match self.kind {
+            EmailTransportKind::Postmark => {
+                let base_url = self
+                    .base_url
+                    .expect("Missing `base_url` for the Postmark transport.");
+                let auth_token = self
+                    .auth_token
+                    .expect("Missing `auth_token` for the Postmark transport.");
+                Box::new(PostmarkTransport::new(base_url, auth_token, timeout))
+            }
+            EmailTransportKind::Smtp => {
+                let smtp = self
+                    .smtp
+                    .expect("Missing `smtp` settings for the SMTP transport.");
+                Box::new(SmtpTransport::new(smtp, timeout))
+            }
+            EmailTransportKind::File => {
+                let file = self
+                    .file
+                    .expect("Missing `file` settings for the file transport.");
+                Box::new(FileTransport::new(file.directory))
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailTransportKind {
+    Postmark,
+    Smtp,
+    File,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+    /// Upgrade the connection with `STARTTLS` before authenticating
+    pub starttls: bool,
+    /// How many connections are kept open to the server, at most
+    pub max_connections: usize,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct FileSettings {
+    /// Where `.eml` files are written to
+    pub directory: PathBuf,
 }
 
 impl EmailClientSettings {
     pub fn client(self) -> EmailClient {
         let sender_email = self.sender().expect("Invalid sender email address.");
         let timeout = self.timeout();
-        EmailClient::new(self.base_url, sender_email, self.auth_token, timeout)
+        let cooldown = std::time::Duration::from_millis(self.circuit_breaker_cooldown_milliseconds);
+
+        let mut client = EmailClient::new(sender_email, self.provider.transport(timeout))
+            .with_circuit_breaker(self.circuit_breaker_threshold, cooldown);
+        for provider in self.fallback_providers {
+            client = client.with_fallback(provider.transport(timeout));
+        }
+
+        client
     }
 
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
@@ -84,6 +183,81 @@ impl EmailClientSettings {
     }
 }
 
+#[derive(Deserialize, Clone)]
+pub struct SubscriptionSettings {
+    /// How long a confirmation link can be used for
+    pub confirmation_token_lifetime_milliseconds: u64,
+    /// How long subscribers who never confirmed are kept around
+    /// after their last confirmation link was sent
+    pub pending_retention_milliseconds: u64,
+    /// How often stale pending subscribers are purged
+    pub sweep_interval_milliseconds: u64,
+    /// How many confirmation emails can be resent to an address, or from an IP,
+    /// within `resend_window_milliseconds`
+    pub resend_max_attempts: u32,
+    pub resend_window_milliseconds: u64,
+    /// How long a link to download or erase a subscriber's data can be used for
+    pub data_request_lifetime_milliseconds: u64,
+}
+
+impl SubscriptionSettings {
+    /// When a confirmation link sent right now stops working
+    pub fn confirmation_token_expires_at(&self) -> chrono::DateTime<chrono::Utc> {
+        chrono::Utc::now()
+            + chrono::Duration::milliseconds(self.confirmation_token_lifetime_milliseconds as i64)
+    }
+
+    /// When a link to a subscriber's data sent right now stops working
+    pub fn data_request_expires_at(&self) -> chrono::DateTime<chrono::Utc> {
+        chrono::Utc::now()
+            + chrono::Duration::milliseconds(self.data_request_lifetime_milliseconds as i64)
+    }
+
+    pub fn pending_retention(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.pending_retention_milliseconds)
+    }
+
+    pub fn sweep_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.sweep_interval_milliseconds)
+    }
+
+    pub fn resend_window(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.resend_window_milliseconds)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct WorkerSettings {
+    /// How many times a delivery is attempted before it is given up on
+    pub max_attempts: i32,
+    pub backoff_base_milliseconds: u64,
+    pub backoff_max_milliseconds: u64,
+    /// How often idle workers check the queue when they can't listen for notifications
+    pub fallback_poll_interval_milliseconds: u64,
+    /// How many tasks are executed concurrently
+    pub concurrency: usize,
+    /// Upper bound on the emails sent per second by all the concurrent tasks
+    pub max_sends_per_second: Option<u32>,
+    /// How many tasks are sent together through Postmark's batch endpoint,
+    /// `1` sends them one by one
+    pub batch_size: usize,
+}
+
+impl WorkerSettings {
+    pub fn fallback_poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.fallback_poll_interval_milliseconds)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct WebhookSettings {
+    /// Credentials the email provider authenticates its webhooks with, through basic auth
+    pub username: String,
+    pub password: Secret<String>,
+    /// How many soft bounces make an address be suppressed
+    pub soft_bounce_threshold: u32,
+}
+
 pub fn get_config() -> Result<Settings, config::ConfigError> {
     // Init config reader
     let mut settings = config::Config::default();
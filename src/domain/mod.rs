@@ -1,7 +1,16 @@
 mod new_subscriber;
+mod segment;
 mod subscriber_email;
 mod subscriber_name;
+mod subscriber_tag;
+mod subscriber_token;
+mod template;
 
 pub use new_subscriber::NewSubscriber;
-pub use subscriber_email::SubscriberEmail;
+pub use segment::Segment;
+pub use subscriber_email::{normalise_email, SubscriberEmail};
 pub use subscriber_name::SubscriberName;
+pub use subscriber_tag::SubscriberTag;
+pub use subscriber_token::{SubscriberToken, TokenPurpose};
+pub(crate) use template::is_identifier;
+pub use template::Template;
@@ -0,0 +1,261 @@
+use crate::domain::{subscriber_tag::is_tag_char, SubscriberTag};
+
+/// A set of subscribers, described as a boolean filter on their tags,
+/// e.g. `beta and (region:eu or region:uk) and not paid`.
+///
+/// `not` binds tighter than `and`, which binds tighter than `or`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    /// Subscribers with the tag
+    Tag(SubscriberTag),
+    Not(Box<Segment>),
+    And(Box<Segment>, Box<Segment>),
+    Or(Box<Segment>, Box<Segment>),
+}
+
+/// Longest expression accepted, which also bounds how deep parsing recurses
+const MAX_LENGTH: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Tag(String),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl Segment {
+    pub fn parse(expression: &str) -> Result<Segment, String> {
+        if expression.len() > MAX_LENGTH {
+            return Err(format!(
+                "Segments can't be longer than {} chars.",
+                MAX_LENGTH
+            ));
+        }
+        let tokens = tokenize(expression)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+        };
+        let segment = parser.or()?;
+        match parser.peek() {
+            None => Ok(segment),
+            Some(_) => Err(format!("Unexpected {} in segment.", parser.describe_next())),
+        }
+    }
+
+    /// Tags the segment refers to, each once, in order of appearance
+    pub fn tags(&self) -> Vec<&SubscriberTag> {
+        let mut tags = Vec::new();
+        self.collect_tags(&mut tags);
+        tags
+    }
+
+    fn collect_tags<'a>(&'a self, tags: &mut Vec<&'a SubscriberTag>) {
+        match self {
+            Segment::Tag(tag) => {
+                if !tags.contains(&tag) {
+                    tags.push(tag)
+                }
+            }
+            Segment::Not(segment) => segment.collect_tags(tags),
+            Segment::And(left, right) | Segment::Or(left, right) => {
+                left.collect_tags(tags);
+                right.collect_tags(tags);
+            }
+        }
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            c if is_tag_char(c.to_ascii_lowercase()) => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !is_tag_char(c.to_ascii_lowercase()) {
+                        break;
+                    }
+                    word.push(c.to_ascii_lowercase());
+                    chars.next();
+                }
+                tokens.push(match word.as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Tag(word),
+                });
+            }
+            c => return Err(format!("Unexpected '{}' in segment.", c)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent parser, one method per precedence level
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn describe_next(&self) -> String {
+        match self.peek() {
+            None => "end".into(),
+            Some(Token::Tag(tag)) => format!("tag {}", tag),
+            Some(Token::And) => "'and'".into(),
+            Some(Token::Or) => "'or'".into(),
+            Some(Token::Not) => "'not'".into(),
+            Some(Token::Open) => "'('".into(),
+            Some(Token::Close) => "')'".into(),
+        }
+    }
+
+    fn or(&mut self) -> Result<Segment, String> {
+        let mut segment = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            segment = Segment::Or(Box::new(segment), Box::new(self.and()?));
+        }
+        Ok(segment)
+    }
+
+    fn and(&mut self) -> Result<Segment, String> {
+        let mut segment = self.not()?;
+        while self.peek() == Some(&Token::And) {
+            self.position += 1;
+            segment = Segment::And(Box::new(segment), Box::new(self.not()?));
+        }
+        Ok(segment)
+    }
+
+    fn not(&mut self) -> Result<Segment, String> {
+        if self.peek() == Some(&Token::Not) {
+            self.position += 1;
+            return Ok(Segment::Not(Box::new(self.not()?)));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Segment, String> {
+        match self.peek() {
+            Some(Token::Tag(tag)) => {
+                let tag = SubscriberTag::parse(tag)?;
+                self.position += 1;
+                Ok(Segment::Tag(tag))
+            }
+            Some(Token::Open) => {
+                self.position += 1;
+                let segment = self.or()?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(format!(
+                        "Expected ')' in segment, found {}.",
+                        self.describe_next()
+                    ));
+                }
+                self.position += 1;
+                Ok(segment)
+            }
+            _ => Err(format!(
+                "Expected a tag in segment, found {}.",
+                self.describe_next()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::domain::{Segment, SubscriberTag},
+        claim::assert_err,
+    };
+
+    fn tag(tag: &str) -> Box<Segment> {
+        Box::new(Segment::Tag(SubscriberTag::parse(tag).unwrap()))
+    }
+
+    #[test]
+    fn a_single_tag_is_a_segment() {
+        assert_eq!(Segment::parse("region:eu").unwrap(), *tag("region:eu"));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            Segment::parse("beta or paid and region:eu").unwrap(),
+            Segment::Or(
+                tag("beta"),
+                Box::new(Segment::And(tag("paid"), tag("region:eu")))
+            )
+        );
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and() {
+        assert_eq!(
+            Segment::parse("NOT beta AND paid").unwrap(),
+            Segment::And(Box::new(Segment::Not(tag("beta"))), tag("paid"))
+        );
+    }
+
+    #[test]
+    fn parentheses_group_expressions() {
+        assert_eq!(
+            Segment::parse("(beta or paid) and not(region:eu)").unwrap(),
+            Segment::And(
+                Box::new(Segment::Or(tag("beta"), tag("paid"))),
+                Box::new(Segment::Not(tag("region:eu")))
+            )
+        );
+    }
+
+    #[test]
+    fn tags_are_listed_once() {
+        let segment = Segment::parse("beta or (paid and not beta)").unwrap();
+        let tags: Vec<_> = segment.tags().into_iter().map(AsRef::as_ref).collect();
+        assert_eq!(tags, vec!["beta", "paid"]);
+    }
+
+    #[test]
+    fn malformed_segments_are_rejected() {
+        for expression in [
+            "",
+            "beta and",
+            "or beta",
+            "(beta",
+            "beta)",
+            "beta paid",
+            "beta & paid",
+            "not",
+        ] {
+            assert_err!(Segment::parse(expression), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn overly_long_segments_are_rejected() {
+        let expression = format!("{}beta{}", "(".repeat(600), ")".repeat(600));
+        assert_err!(Segment::parse(&expression));
+    }
+}
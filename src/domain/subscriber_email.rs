@@ -1,34 +1,65 @@
 use validator::validate_email;
 
 #[derive(Debug)]
-pub struct SubscriberEmail(String);
+pub struct SubscriberEmail {
+    /// As the subscriber typed it, which is the address we email
+    email: String,
+    /// What addresses are compared by
+    normalised: String,
+}
 
 impl std::fmt::Display for SubscriberEmail {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+        self.email.fmt(f)
     }
 }
 
 impl SubscriberEmail {
     pub fn parse(email: String) -> Result<SubscriberEmail, String> {
-        if validate_email(&email) {
-            Ok(Self(email))
+        let email = email.trim();
+        if validate_email(email) {
+            Ok(Self {
+                email: email.to_string(),
+                normalised: normalise_email(email),
+            })
         } else {
             Err(format!("{} is not a valid subscriber email.", email))
         }
     }
+
+    /// The address in the form it is compared by, see `normalise_email`
+    pub fn normalised(&self) -> &str {
+        &self.normalised
+    }
 }
 
 impl AsRef<str> for SubscriberEmail {
     fn as_ref(&self) -> &str {
-        &self.0
+        &self.email
+    }
+}
+
+/// The form addresses are compared by, so that `Foo@Example.com` and `foo@example.com`
+/// are the same subscriber: without surrounding whitespace, lower-cased, and with
+/// internationalised domains in their ASCII (punycode) form.
+///
+/// Local parts are case-sensitive in theory, but mail providers treat them as
+/// case-insensitive. Domains that can't be converted are only lower-cased.
+pub fn normalise_email(email: &str) -> String {
+    let email = email.trim().to_lowercase();
+    match email.rsplit_once('@') {
+        Some((local_part, domain)) => match idna::domain_to_ascii(domain) {
+            Ok(domain) => format!("{}@{}", local_part, domain),
+            Err(_) => email,
+        },
+        None => email,
     }
 }
 
 #[cfg(test)]
 mod tests {
     use {
-        super::SubscriberEmail,
+        super::{normalise_email, SubscriberEmail},
         claim::assert_err,
         fake::{faker::internet::en::SafeEmail, Fake},
     };
@@ -51,6 +82,37 @@ mod tests {
         assert_err!(SubscriberEmail::parse(email));
     }
 
+    #[test]
+    fn surrounding_whitespace_is_trimmed() {
+        let email = SubscriberEmail::parse(" ursula@example.com\n".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "ursula@example.com");
+    }
+
+    #[test]
+    fn the_display_form_is_kept_beside_the_normalised_one() {
+        let email = SubscriberEmail::parse("Ursula@Example.COM".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "Ursula@Example.COM");
+        assert_eq!(email.normalised(), "ursula@example.com");
+    }
+
+    #[test]
+    fn addresses_are_normalised_to_lower_case_without_surrounding_spaces() {
+        assert_eq!(
+            normalise_email(" Ursula@Example.COM\n"),
+            "ursula@example.com"
+        );
+    }
+
+    #[test]
+    fn internationalised_domains_are_normalised_to_punycode() {
+        assert_eq!(
+            normalise_email("Ursula@Bücher.Example"),
+            "ursula@xn--bcher-kva.example"
+        );
+        let email = SubscriberEmail::parse("ursula@bücher.example".to_string()).unwrap();
+        assert_eq!(email.normalised(), "ursula@xn--bcher-kva.example");
+    }
+
     // Prop testing random valid emails
     #[derive(Debug, Clone)]
     struct ValidEmailFixture(pub String);
@@ -0,0 +1,74 @@
+/// A label put on subscribers, e.g. `beta`, `paid` or `region:eu`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubscriberTag(String);
+
+impl SubscriberTag {
+    /// Tags are made of 1 to 64 lowercase letters, digits, `:`, `-`, `_` or `.`.
+    /// Upper case letters are lowered.
+    pub fn parse(tag: &str) -> Result<SubscriberTag, String> {
+        let tag = tag.trim().to_lowercase();
+        let is_valid = !tag.is_empty()
+            && tag.len() <= 64
+            && tag.chars().all(is_tag_char)
+            && !["and", "or", "not"].contains(&tag.as_str());
+
+        if is_valid {
+            Ok(Self(tag))
+        } else {
+            Err(format!("{} is not a valid tag.", tag))
+        }
+    }
+}
+
+/// Whether the char may be part of a tag
+pub(crate) fn is_tag_char(c: char) -> bool {
+    c.is_ascii_lowercase() || c.is_ascii_digit() || [':', '-', '_', '.'].contains(&c)
+}
+
+impl AsRef<str> for SubscriberTag {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::domain::SubscriberTag,
+        claim::{assert_err, assert_ok},
+    };
+
+    #[test]
+    fn tags_with_a_namespace_are_valid() {
+        assert_ok!(SubscriberTag::parse("region:eu"));
+    }
+
+    #[test]
+    fn tags_are_lowered() {
+        assert_eq!(SubscriberTag::parse(" Beta ").unwrap().as_ref(), "beta");
+    }
+
+    #[test]
+    fn empty_tags_are_rejected() {
+        assert_err!(SubscriberTag::parse(" "));
+    }
+
+    #[test]
+    fn tags_longer_than_64_chars_are_rejected() {
+        assert_err!(SubscriberTag::parse(&"a".repeat(65)));
+    }
+
+    #[test]
+    fn tags_with_spaces_or_parentheses_are_rejected() {
+        for tag in ["two words", "(beta)", "paid!"] {
+            assert_err!(SubscriberTag::parse(tag));
+        }
+    }
+
+    #[test]
+    fn segment_operators_are_not_valid_tags() {
+        for tag in ["and", "OR", "not"] {
+            assert_err!(SubscriberTag::parse(tag));
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use {
+    chrono::{DateTime, TimeZone, Utc},
+    hmac::{Hmac, Mac},
+    secrecy::{ExposeSecret, Secret},
+    sha2::Sha256,
+    uuid::Uuid,
+};
+
+/// What a `SubscriberToken` lets its holder do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenPurpose {
+    Unsubscribe,
+    ManagePreferences,
+    /// Download or erase everything stored about the subscriber
+    ManageData,
+}
+
+impl TokenPurpose {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TokenPurpose::Unsubscribe => "unsubscribe",
+            TokenPurpose::ManagePreferences => "preferences",
+            TokenPurpose::ManageData => "data",
+        }
+    }
+}
+
+/// Proof that whoever holds it was sent emails for a given subscriber,
+/// so that links in those emails work without logging in
+#[derive(Debug)]
+pub struct SubscriberToken(String);
+
+impl SubscriberToken {
+    /// Sign the subscriber's id with the application's secret
+    pub fn generate(purpose: TokenPurpose, subscriber_id: Uuid, secret: &Secret<String>) -> Self {
+        let signature = hex::encode(
+            mac(purpose, subscriber_id, None, secret)
+                .finalize()
+                .into_bytes(),
+        );
+        Self(format!("{}.{}", subscriber_id, signature))
+    }
+
+    /// Sign the subscriber's id along with a list, for links that only concern that list
+    pub fn generate_for_list(
+        purpose: TokenPurpose,
+        subscriber_id: Uuid,
+        list_id: Uuid,
+        secret: &Secret<String>,
+    ) -> Self {
+        let signature = hex::encode(
+            mac(purpose, subscriber_id, Some(list_id.as_bytes()), secret)
+                .finalize()
+                .into_bytes(),
+        );
+        Self(format!("{}.{}.{}", subscriber_id, list_id, signature))
+    }
+
+    /// Sign the subscriber's id along with when the token stops working,
+    /// for links that give access to more than the newsletter itself
+    pub fn generate_expiring(
+        purpose: TokenPurpose,
+        subscriber_id: Uuid,
+        expires_at: DateTime<Utc>,
+        secret: &Secret<String>,
+    ) -> Self {
+        let expires_at = expires_at.timestamp();
+        let signature = hex::encode(
+            mac(
+                purpose,
+                subscriber_id,
+                Some(&expires_at.to_be_bytes()),
+                secret,
+            )
+            .finalize()
+            .into_bytes(),
+        );
+        Self(format!("{}.{}.{}", subscriber_id, expires_at, signature))
+    }
+
+    /// Check the signature of a token, returning the id of the subscriber it was made for
+    pub fn verify(
+        purpose: TokenPurpose,
+        token: &str,
+        secret: &Secret<String>,
+    ) -> Result<Uuid, String> {
+        let invalid = || format!("{} is not a valid {} token.", token, purpose.as_str());
+        let (subscriber_id, signature) = token.split_once('.').ok_or_else(invalid)?;
+        let subscriber_id = Uuid::parse_str(subscriber_id).map_err(|_| invalid())?;
+        let signature = hex::decode(signature).map_err(|_| invalid())?;
+        mac(purpose, subscriber_id, None, secret)
+            .verify_slice(&signature)
+            .map_err(|_| invalid())?;
+
+        Ok(subscriber_id)
+    }
+
+    /// Check the signature and the expiry of a token made by `generate_expiring`
+    pub fn verify_expiring(
+        purpose: TokenPurpose,
+        token: &str,
+        secret: &Secret<String>,
+    ) -> Result<Uuid, String> {
+        let invalid = || format!("{} is not a valid {} token.", token, purpose.as_str());
+        let mut parts = token.splitn(3, '.');
+        let (subscriber_id, expires_at, signature) =
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(subscriber_id), Some(expires_at), Some(signature)) => {
+                    (subscriber_id, expires_at, signature)
+                }
+                _ => return Err(invalid()),
+            };
+        let subscriber_id = Uuid::parse_str(subscriber_id).map_err(|_| invalid())?;
+        let expires_at: i64 = expires_at.parse().map_err(|_| invalid())?;
+        let signature = hex::decode(signature).map_err(|_| invalid())?;
+        mac(
+            purpose,
+            subscriber_id,
+            Some(&expires_at.to_be_bytes()),
+            secret,
+        )
+        .verify_slice(&signature)
+        .map_err(|_| invalid())?;
+        if Utc.timestamp(expires_at, 0) <= Utc::now() {
+            return Err(format!("This {} token has expired.", purpose.as_str()));
+        }
+
+        Ok(subscriber_id)
+    }
+
+    /// Check the signature of a token made by `generate_for_list`,
+    /// returning the ids of the subscriber and of the list it was made for
+    pub fn verify_for_list(
+        purpose: TokenPurpose,
+        token: &str,
+        secret: &Secret<String>,
+    ) -> Result<(Uuid, Uuid), String> {
+        let invalid = || format!("{} is not a valid {} token.", token, purpose.as_str());
+        let mut parts = token.splitn(3, '.');
+        let (subscriber_id, list_id, signature) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(subscriber_id), Some(list_id), Some(signature)) => {
+                (subscriber_id, list_id, signature)
+            }
+            _ => return Err(invalid()),
+        };
+        let subscriber_id = Uuid::parse_str(subscriber_id).map_err(|_| invalid())?;
+        let list_id = Uuid::parse_str(list_id).map_err(|_| invalid())?;
+        let signature = hex::decode(signature).map_err(|_| invalid())?;
+        mac(purpose, subscriber_id, Some(list_id.as_bytes()), secret)
+            .verify_slice(&signature)
+            .map_err(|_| invalid())?;
+
+        Ok((subscriber_id, list_id))
+    }
+}
+
+impl AsRef<str> for SubscriberToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// `scope` narrows down what the token is valid for, e.g. until when or for which list
+fn mac(
+    purpose: TokenPurpose,
+    subscriber_id: Uuid,
+    scope: Option<&[u8]>,
+    secret: &Secret<String>,
+) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    // Tokens signed for one purpose can't be used for another
+    mac.update(purpose.as_str().as_bytes());
+    mac.update(b":");
+    mac.update(subscriber_id.as_bytes());
+    if let Some(scope) = scope {
+        mac.update(b":");
+        mac.update(scope);
+    }
+
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::domain::{SubscriberToken, TokenPurpose},
+        chrono::{Duration, Utc},
+        claim::{assert_err, assert_ok_eq},
+        secrecy::Secret,
+        uuid::Uuid,
+    };
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-secret-key".into())
+    }
+
+    #[test]
+    fn a_generated_token_is_valid() {
+        let subscriber_id = Uuid::new_v4();
+        let token = SubscriberToken::generate(TokenPurpose::Unsubscribe, subscriber_id, &secret());
+        assert_ok_eq!(
+            SubscriberToken::verify(TokenPurpose::Unsubscribe, token.as_ref(), &secret()),
+            subscriber_id
+        );
+    }
+
+    #[test]
+    fn a_token_for_another_subscriber_is_rejected() {
+        let token = SubscriberToken::generate(TokenPurpose::Unsubscribe, Uuid::new_v4(), &secret());
+        let signature = token.as_ref().split_once('.').unwrap().1;
+        let forged = format!("{}.{}", Uuid::new_v4(), signature);
+        assert_err!(SubscriberToken::verify(
+            TokenPurpose::Unsubscribe,
+            &forged,
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn a_token_for_another_purpose_is_rejected() {
+        let token =
+            SubscriberToken::generate(TokenPurpose::ManagePreferences, Uuid::new_v4(), &secret());
+        assert_err!(SubscriberToken::verify(
+            TokenPurpose::Unsubscribe,
+            token.as_ref(),
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = SubscriberToken::generate(TokenPurpose::Unsubscribe, Uuid::new_v4(), &secret());
+        let other_secret = Secret::new("another-secret-key".into());
+        assert_err!(SubscriberToken::verify(
+            TokenPurpose::Unsubscribe,
+            token.as_ref(),
+            &other_secret
+        ));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        for token in [
+            "",
+            "not-a-token",
+            "not-a-uuid.abcd",
+            &Uuid::new_v4().to_string(),
+        ] {
+            assert_err!(SubscriberToken::verify(
+                TokenPurpose::Unsubscribe,
+                token,
+                &secret()
+            ));
+        }
+    }
+
+    #[test]
+    fn a_list_token_is_only_valid_for_its_list() {
+        let (subscriber_id, list_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let token = SubscriberToken::generate_for_list(
+            TokenPurpose::Unsubscribe,
+            subscriber_id,
+            list_id,
+            &secret(),
+        );
+        assert_ok_eq!(
+            SubscriberToken::verify_for_list(TokenPurpose::Unsubscribe, token.as_ref(), &secret()),
+            (subscriber_id, list_id)
+        );
+
+        let signature = token.as_ref().rsplit_once('.').unwrap().1;
+        let forged = format!("{}.{}.{}", subscriber_id, Uuid::new_v4(), signature);
+        assert_err!(SubscriberToken::verify_for_list(
+            TokenPurpose::Unsubscribe,
+            &forged,
+            &secret()
+        ));
+        // Nor is it valid for every list
+        assert_err!(SubscriberToken::verify(
+            TokenPurpose::Unsubscribe,
+            token.as_ref(),
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn an_expiring_token_is_valid_until_it_expires() {
+        let subscriber_id = Uuid::new_v4();
+        let token = SubscriberToken::generate_expiring(
+            TokenPurpose::ManageData,
+            subscriber_id,
+            Utc::now() + Duration::hours(1),
+            &secret(),
+        );
+        assert_ok_eq!(
+            SubscriberToken::verify_expiring(TokenPurpose::ManageData, token.as_ref(), &secret()),
+            subscriber_id
+        );
+
+        let expired = SubscriberToken::generate_expiring(
+            TokenPurpose::ManageData,
+            subscriber_id,
+            Utc::now() - Duration::seconds(1),
+            &secret(),
+        );
+        assert_err!(SubscriberToken::verify_expiring(
+            TokenPurpose::ManageData,
+            expired.as_ref(),
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn an_expiring_token_with_a_forged_expiry_is_rejected() {
+        let token = SubscriberToken::generate_expiring(
+            TokenPurpose::ManageData,
+            Uuid::new_v4(),
+            Utc::now() + Duration::hours(1),
+            &secret(),
+        );
+        let mut parts = token.as_ref().splitn(3, '.');
+        let (subscriber_id, expires_at, signature) = (
+            parts.next().unwrap(),
+            parts.next().unwrap().parse::<i64>().unwrap(),
+            parts.next().unwrap(),
+        );
+        let forged = format!("{}.{}.{}", subscriber_id, expires_at + 86400, signature);
+        assert_err!(SubscriberToken::verify_expiring(
+            TokenPurpose::ManageData,
+            &forged,
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn a_token_without_an_expiry_is_not_an_expiring_token() {
+        let token = SubscriberToken::generate(TokenPurpose::ManageData, Uuid::new_v4(), &secret());
+        assert_err!(SubscriberToken::verify_expiring(
+            TokenPurpose::ManageData,
+            token.as_ref(),
+            &secret()
+        ));
+    }
+}
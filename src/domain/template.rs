@@ -0,0 +1,489 @@
+use {htmlescape::encode_minimal, serde_json::Value};
+
+/// Content of an issue, personalised for every recipient when it is sent.
+///
+/// Values are written as `{{ name }}` or `{{ attributes.company | default: "there" }}`,
+/// and parts of the content can be left out with
+/// `{% if attributes.company %}...{% else %}...{% endif %}` (or `if not`).
+/// The fields available are `name`, `email` and the subscriber's `attributes`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Text(String),
+    Value {
+        field: Field,
+        filters: Vec<Filter>,
+    },
+    If {
+        negated: bool,
+        field: Field,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+/// A dotted path into the recipient's fields, e.g. `attributes.company`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Field(Vec<String>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Filter {
+    /// Used when the value is missing or empty
+    Default(String),
+    Upcase,
+    Downcase,
+}
+
+/// Deepest nesting of conditionals accepted, which also bounds how deep rendering recurses
+const MAX_DEPTH: usize = 16;
+
+/// A conditional whose `{% endif %}` hasn't been reached yet
+struct OpenIf {
+    negated: bool,
+    field: Field,
+    then: Vec<Node>,
+    otherwise: Option<Vec<Node>>,
+}
+
+impl OpenIf {
+    fn nodes(&mut self) -> &mut Vec<Node> {
+        match &mut self.otherwise {
+            Some(otherwise) => otherwise,
+            None => &mut self.then,
+        }
+    }
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, String> {
+        let mut nodes = Vec::new();
+        let mut open_ifs: Vec<OpenIf> = Vec::new();
+        let mut rest = source;
+        while let Some(start) = find_tag(rest) {
+            let (text, tag) = rest.split_at(start);
+            let close = if tag.starts_with("{{") { "}}" } else { "%}" };
+            let end = tag[2..]
+                .find(close)
+                .ok_or_else(|| format!("`{}` is never closed in template.", &tag[..2]))?;
+            let inner = tag[2..2 + end].trim();
+            rest = &tag[2 + end + 2..];
+
+            let current = match open_ifs.last_mut() {
+                Some(open_if) => open_if.nodes(),
+                None => &mut nodes,
+            };
+            if !text.is_empty() {
+                current.push(Node::Text(text.to_string()));
+            }
+            if close == "}}" {
+                current.push(parse_value(inner)?);
+                continue;
+            }
+            let words: Vec<_> = inner.split_whitespace().collect();
+            match words.as_slice() {
+                ["if", "not", field] => open_if(&mut open_ifs, true, field)?,
+                ["if", field] => open_if(&mut open_ifs, false, field)?,
+                ["else"] => match open_ifs.last_mut() {
+                    Some(open_if) if open_if.otherwise.is_none() => {
+                        open_if.otherwise = Some(Vec::new())
+                    }
+                    Some(_) => return Err("Unexpected second `{% else %}` in template.".into()),
+                    None => return Err("Unexpected `{% else %}` outside of `{% if %}`.".into()),
+                },
+                ["endif"] => {
+                    let open_if = open_ifs
+                        .pop()
+                        .ok_or("Unexpected `{% endif %}` without `{% if %}`.")?;
+                    let node = Node::If {
+                        negated: open_if.negated,
+                        field: open_if.field,
+                        then: open_if.then,
+                        otherwise: open_if.otherwise.unwrap_or_default(),
+                    };
+                    match open_ifs.last_mut() {
+                        Some(parent) => parent.nodes().push(node),
+                        None => nodes.push(node),
+                    }
+                }
+                _ => return Err(format!("Unknown tag `{{% {} %}}` in template.", inner)),
+            }
+        }
+        if !open_ifs.is_empty() {
+            return Err("`{% if %}` is never closed with `{% endif %}` in template.".into());
+        }
+        if !rest.is_empty() {
+            nodes.push(Node::Text(rest.to_string()));
+        }
+
+        Ok(Template { nodes })
+    }
+
+    /// A template without any tags, rendering as `text`
+    pub fn text(text: String) -> Template {
+        Template {
+            nodes: vec![Node::Text(text)],
+        }
+    }
+
+    /// Check that the template only refers to fields recipients can have:
+    /// `name`, `email`, and the attributes in `attribute_keys`
+    pub fn validate(&self, attribute_keys: &[String]) -> Result<(), String> {
+        let mut fields = Vec::new();
+        collect_fields(&self.nodes, &mut fields);
+        for Field(path) in fields {
+            let known = match path.as_slice() {
+                [field] => field == "name" || field == "email",
+                [attributes, key, ..] => attributes == "attributes" && attribute_keys.contains(key),
+                [] => false,
+            };
+            if !known {
+                return Err(format!(
+                    "The template refers to `{}`, which subscribers don't have.",
+                    path.join(".")
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fill in the template with the recipient's fields.
+    /// When `escape_html` is set, values are escaped but the template's own text isn't.
+    pub fn render(&self, fields: &Value, escape_html: bool) -> String {
+        let mut output = String::new();
+        render_nodes(&self.nodes, fields, escape_html, &mut output);
+        output
+    }
+}
+
+/// Start a conditional, nested in the ones still open
+fn open_if(open_ifs: &mut Vec<OpenIf>, negated: bool, field: &str) -> Result<(), String> {
+    if open_ifs.len() == MAX_DEPTH {
+        return Err(format!(
+            "Conditionals can't be nested more than {} deep in template.",
+            MAX_DEPTH
+        ));
+    }
+    open_ifs.push(OpenIf {
+        negated,
+        field: Field::parse(field)?,
+        then: Vec::new(),
+        otherwise: None,
+    });
+
+    Ok(())
+}
+
+/// Whether `name` can be used as a field, or as one of the keys of a subscriber's attributes
+pub(crate) fn is_identifier(name: &str) -> bool {
+    matches!(name.chars().next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Position of the next `{{` or `{%`
+fn find_tag(source: &str) -> Option<usize> {
+    match (source.find("{{"), source.find("{%")) {
+        (Some(value), Some(tag)) => Some(value.min(tag)),
+        (value, tag) => value.or(tag),
+    }
+}
+
+/// Parse the inside of `{{ ... }}`: a field followed by filters
+fn parse_value(inner: &str) -> Result<Node, String> {
+    let mut parts = split_filters(inner)?.into_iter();
+    let field = Field::parse(parts.next().unwrap_or_default().trim())?;
+    let filters = parts
+        .map(|filter| Filter::parse(filter.trim()))
+        .collect::<Result<_, _>>()?;
+
+    Ok(Node::Value { field, filters })
+}
+
+/// Split on the `|` that are outside of string literals
+fn split_filters(inner: &str) -> Result<Vec<&str>, String> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in inner.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '|' if !in_string => {
+                parts.push(&inner[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if in_string {
+        return Err(format!("Unterminated string in `{{{{ {} }}}}`.", inner));
+    }
+    parts.push(&inner[start..]);
+
+    Ok(parts)
+}
+
+impl Field {
+    fn parse(path: &str) -> Result<Field, String> {
+        if !path.split('.').all(is_identifier) {
+            return Err(format!("`{}` is not a valid field in template.", path));
+        }
+
+        Ok(Field(path.split('.').map(String::from).collect()))
+    }
+
+    /// Look up the field, treating `null` the same as a missing field
+    fn lookup<'a>(&self, fields: &'a Value) -> Option<&'a Value> {
+        self.0
+            .iter()
+            .try_fold(fields, |value, key| value.get(key))
+            .filter(|value| !value.is_null())
+    }
+}
+
+impl Filter {
+    fn parse(filter: &str) -> Result<Filter, String> {
+        let (name, argument) = match filter.split_once(':') {
+            Some((name, argument)) => (name.trim(), Some(parse_string(argument.trim())?)),
+            None => (filter, None),
+        };
+        match (name, argument) {
+            ("default", Some(argument)) => Ok(Filter::Default(argument)),
+            ("upcase", None) => Ok(Filter::Upcase),
+            ("downcase", None) => Ok(Filter::Downcase),
+            ("default", None) => Err("The `default` filter needs a value.".into()),
+            (name, _) => Err(format!("Unknown filter `{}` in template.", name)),
+        }
+    }
+
+    fn apply(&self, value: String) -> String {
+        match self {
+            Filter::Default(default) if value.is_empty() => default.clone(),
+            Filter::Default(_) => value,
+            Filter::Upcase => value.to_uppercase(),
+            Filter::Downcase => value.to_lowercase(),
+        }
+    }
+}
+
+/// Parse a double-quoted string literal, where `\"` and `\\` are escapes
+fn parse_string(literal: &str) -> Result<String, String> {
+    let unquoted = literal
+        .strip_prefix('"')
+        .and_then(|literal| literal.strip_suffix('"'))
+        .filter(|_| literal.len() >= 2)
+        .ok_or_else(|| format!("Expected a string in quotes, found `{}`.", literal))?;
+    let mut string = String::new();
+    let mut chars = unquoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c @ ('"' | '\\')) => string.push(c),
+                _ => return Err(format!("Invalid escape in `{}`.", literal)),
+            },
+            '"' => return Err(format!("Unexpected quote in `{}`.", literal)),
+            c => string.push(c),
+        }
+    }
+
+    Ok(string)
+}
+
+fn collect_fields<'a>(nodes: &'a [Node], fields: &mut Vec<&'a Field>) {
+    for node in nodes {
+        match node {
+            Node::Text(_) => {}
+            Node::Value { field, .. } => fields.push(field),
+            Node::If {
+                field,
+                then,
+                otherwise,
+                ..
+            } => {
+                fields.push(field);
+                collect_fields(then, fields);
+                collect_fields(otherwise, fields);
+            }
+        }
+    }
+}
+
+fn render_nodes(nodes: &[Node], fields: &Value, escape_html: bool, output: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Value { field, filters } => {
+                let value = field.lookup(fields).map(to_text).unwrap_or_default();
+                let value = filters
+                    .iter()
+                    .fold(value, |value, filter| filter.apply(value));
+                if escape_html {
+                    output.push_str(&encode_minimal(&value));
+                } else {
+                    output.push_str(&value);
+                }
+            }
+            Node::If {
+                negated,
+                field,
+                then,
+                otherwise,
+            } => {
+                let branch = if is_truthy(field.lookup(fields)) != *negated {
+                    then
+                } else {
+                    otherwise
+                };
+                render_nodes(branch, fields, escape_html, output);
+            }
+        }
+    }
+}
+
+fn to_text(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Missing fields, `false` and empty values are falsy
+fn is_truthy(value: Option<&Value>) -> bool {
+    match value {
+        None | Some(Value::Null) | Some(Value::Bool(false)) => false,
+        Some(Value::String(string)) => !string.is_empty(),
+        Some(Value::Array(array)) => !array.is_empty(),
+        Some(Value::Object(object)) => !object.is_empty(),
+        Some(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {crate::domain::Template, claim::assert_err, serde_json::json};
+
+    fn render(source: &str, escape_html: bool) -> String {
+        let fields = json!({
+            "name": "Ursula <Le Guin>",
+            "email": "ursula@example.com",
+            "attributes": { "company": "Acme", "plan": "", "seats": 12, "beta": true }
+        });
+        Template::parse(source)
+            .unwrap()
+            .render(&fields, escape_html)
+    }
+
+    #[test]
+    fn text_without_tags_is_left_as_is() {
+        assert_eq!(render("Hello { world }!", false), "Hello { world }!");
+    }
+
+    #[test]
+    fn values_are_filled_in() {
+        assert_eq!(
+            render(
+                "Hi {{ name }} from {{attributes.company}} ({{ attributes.seats }})",
+                false
+            ),
+            "Hi Ursula <Le Guin> from Acme (12)"
+        );
+    }
+
+    #[test]
+    fn values_are_escaped_in_html_but_not_the_template() {
+        assert_eq!(
+            render("<p>Hi {{ name }}</p>", true),
+            "<p>Hi Ursula &lt;Le Guin&gt;</p>"
+        );
+    }
+
+    #[test]
+    fn default_is_used_for_missing_or_empty_values() {
+        assert_eq!(
+            render(
+                r#"{{ attributes.title | default: "there" }} {{ attributes.plan | default: "free" }} {{ attributes.company | default: "x" }}"#,
+                false
+            ),
+            "there free Acme"
+        );
+    }
+
+    #[test]
+    fn default_values_may_contain_pipes_and_quotes() {
+        assert_eq!(
+            render(r#"{{ attributes.title | default: "a | \"b\"" }}"#, false),
+            r#"a | "b""#
+        );
+    }
+
+    #[test]
+    fn filters_are_applied_in_order() {
+        assert_eq!(
+            render(
+                r#"{{ attributes.title | default: "Friend" | upcase }}"#,
+                false
+            ),
+            "FRIEND"
+        );
+    }
+
+    #[test]
+    fn conditionals_pick_a_branch() {
+        let source = "{% if attributes.beta %}beta{% else %}stable{% endif %}\
+                      {% if attributes.plan %} paid{% endif %}\
+                      {% if not attributes.title %} untitled{% endif %}";
+        assert_eq!(render(source, false), "beta untitled");
+    }
+
+    #[test]
+    fn conditionals_can_be_nested() {
+        let source = "{% if attributes.beta %}{% if attributes.company %}{{ attributes.company }}\
+                      {% else %}-{% endif %}{% endif %}";
+        assert_eq!(render(source, false), "Acme");
+    }
+
+    #[test]
+    fn malformed_templates_are_rejected() {
+        let cases = [
+            "{{ name",
+            "{{ }}",
+            "{{ first name }}",
+            "{{ name | shout }}",
+            "{{ name | default }}",
+            r#"{{ name | default: "x }}"#,
+            "{{ name | default: x }}",
+            "{% if name %}",
+            "{% endif %}",
+            "{% else %}",
+            "{% if name %}{% else %}{% else %}{% endif %}",
+            "{% for x in y %}",
+        ];
+        for case in cases {
+            assert_err!(Template::parse(case), "{}", case);
+        }
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        let source = "{% if name %}".repeat(17) + &"{% endif %}".repeat(17);
+        assert_err!(Template::parse(&source));
+    }
+
+    #[test]
+    fn unknown_fields_fail_validation() {
+        let keys = vec!["company".to_string()];
+        let validate = |source: &str| Template::parse(source).unwrap().validate(&keys);
+        assert!(validate("{{ name }} {{ email }} {{ attributes.company }}").is_ok());
+        assert!(validate("{% if attributes.company %}{% endif %}").is_ok());
+        assert_err!(validate("{{ nmae }}"));
+        assert_err!(validate("{{ attributes.compnay }}"));
+        assert_err!(validate("{% if attributes %}{% endif %}"));
+        assert_err!(validate("{{ name.first }}"));
+    }
+}
@@ -0,0 +1,78 @@
+use {
+    super::{EmailTransport, Message, SendError},
+    std::path::PathBuf,
+    uuid::Uuid,
+};
+
+/// Writes every email to an `.eml` file in `directory` instead of sending it,
+/// which is handy in development and for inspecting what subscribers would get
+pub struct FileTransport {
+    directory: PathBuf,
+}
+
+impl FileTransport {
+    pub fn new(directory: PathBuf) -> Self {
+        Self { directory }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for FileTransport {
+    /// Write the email to a new file, returning its `Message-ID`
+    async fn send(&self, message: &Message) -> Result<Option<String>, SendError> {
+        tokio::fs::create_dir_all(&self.directory).await?;
+        let path = self.directory.join(format!("{}.eml", Uuid::new_v4()));
+        tokio::fs::write(&path, message.format()).await?;
+
+        Ok(Some(message.message_id().to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::FileTransport,
+        crate::{domain::SubscriberEmail, email_client::EmailClient},
+        uuid::Uuid,
+    };
+
+    #[tokio::test]
+    async fn emails_are_written_to_eml_files() {
+        // Arrange
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let sender = SubscriberEmail::parse("sender@example.com".into()).unwrap();
+        let recipient = SubscriberEmail::parse("recipient@example.com".into()).unwrap();
+        let email_client =
+            EmailClient::new(sender, Box::new(FileTransport::new(directory.clone())));
+
+        // Act
+        let message_id = email_client
+            .send_email(
+                &recipient,
+                "Subject",
+                "<p>Html\nContent</p>",
+                "Text\nContent",
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Assert
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+
+        let message = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(message.contains("From: sender@example.com\r\n"));
+        assert!(message.contains("To: recipient@example.com\r\n"));
+        assert!(message.contains("Subject: Subject\r\n"));
+        assert!(message.contains(&format!("Message-ID: {}\r\n", message_id)));
+        assert!(message.contains("Text\r\nContent"));
+        assert!(message.contains("<p>Html\r\nContent</p>"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
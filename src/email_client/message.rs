@@ -0,0 +1,608 @@
+use {
+    crate::domain::SubscriberEmail,
+    chrono::Utc,
+    std::fmt::{self, Display},
+    uuid::Uuid,
+};
+
+/// Lines of a message should not be longer than this, according to RFC 5322
+const MAX_LINE_LENGTH: usize = 78;
+/// Longest chunk of a header value encoded into a single RFC 2047 encoded-word.
+/// Kept well under the 75 characters allowed, so that the first word fits
+/// on the same line as the header name.
+const MAX_ENCODED_WORD_BYTES: usize = 39;
+
+/// An email, with everything needed to send it through any transport
+#[derive(Debug, Clone)]
+pub struct Message {
+    message_id: String,
+    from: Mailbox,
+    to: Mailbox,
+    reply_to: Option<Mailbox>,
+    subject: String,
+    headers: Vec<(String, String)>,
+    html_content: String,
+    text_content: String,
+    inline_images: Vec<Attachment>,
+    attachments: Vec<Attachment>,
+}
+
+impl Message {
+    pub fn new(
+        from: Mailbox,
+        to: Mailbox,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Self {
+        // A globally unique id, in the sender's domain
+        let domain = from.email.rsplit('@').next().unwrap_or("localhost");
+        let message_id = format!("<{}@{}>", Uuid::new_v4(), domain);
+
+        Self {
+            message_id,
+            from,
+            to,
+            reply_to: None,
+            subject: subject.into(),
+            headers: Vec::new(),
+            html_content: html_content.into(),
+            text_content: text_content.into(),
+            inline_images: Vec::new(),
+            attachments: Vec::new(),
+        }
+    }
+
+    pub fn with_reply_to(mut self, reply_to: Mailbox) -> Self {
+        self.reply_to = Some(reply_to);
+        self
+    }
+
+    /// Add a custom header. Its name must be printable ASCII, without a colon.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        assert!(
+            !name.is_empty() && name.bytes().all(|b| b.is_ascii_graphic() && b != b':'),
+            "{:?} is not a valid header name",
+            name
+        );
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn with_attachment(mut self, attachment: Attachment) -> Self {
+        self.attachments.push(attachment);
+        self
+    }
+
+    /// Add an image the html content shows with `<img src="cid:{content_id}">`
+    pub fn with_inline_image(mut self, content_id: &str, mut image: Attachment) -> Self {
+        image.content_id = Some(content_id.into());
+        self.inline_images.push(image);
+        self
+    }
+
+    pub fn message_id(&self) -> &str {
+        &self.message_id
+    }
+
+    pub fn from(&self) -> &Mailbox {
+        &self.from
+    }
+
+    pub fn to(&self) -> &Mailbox {
+        &self.to
+    }
+
+    pub fn reply_to(&self) -> Option<&Mailbox> {
+        self.reply_to.as_ref()
+    }
+
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    pub fn html_content(&self) -> &str {
+        &self.html_content
+    }
+
+    pub fn text_content(&self) -> &str {
+        &self.text_content
+    }
+
+    pub fn inline_images(&self) -> &[Attachment] {
+        &self.inline_images
+    }
+
+    pub fn attachments(&self) -> &[Attachment] {
+        &self.attachments
+    }
+
+    /// Format the message as RFC 5322 text, ready to be handed over to an SMTP server.
+    ///
+    /// The text and html contents are alternatives of each other, related to the
+    /// inline images if there are any, and mixed with the attachments if there are any.
+    pub fn format(&self) -> String {
+        let mut body = multipart(
+            "alternative",
+            vec![
+                text_part("text/plain", &self.text_content),
+                text_part("text/html", &self.html_content),
+            ],
+        );
+        if !self.inline_images.is_empty() {
+            let mut parts = vec![body];
+            parts.extend(self.inline_images.iter().map(|image| image.part("inline")));
+            body = multipart("related", parts);
+        }
+        if !self.attachments.is_empty() {
+            let mut parts = vec![body];
+            parts.extend(self.attachments.iter().map(|a| a.part("attachment")));
+            body = multipart("mixed", parts);
+        }
+
+        let mut message = String::new();
+        message.push_str(&header("From", self.from.tokens()));
+        message.push_str(&header("To", self.to.tokens()));
+        if let Some(reply_to) = &self.reply_to {
+            message.push_str(&header("Reply-To", reply_to.tokens()));
+        }
+        message.push_str(&header("Subject", text_tokens(&self.subject)));
+        message.push_str(&header("Date", text_tokens(&Utc::now().to_rfc2822())));
+        message.push_str(&header("Message-ID", vec![self.message_id.clone()]));
+        message.push_str(&header("MIME-Version", vec!["1.0".into()]));
+        for (name, value) in &self.headers {
+            message.push_str(&header(name, text_tokens(value)));
+        }
+        message.push_str(&body.headers);
+        message.push_str("\r\n");
+        message.push_str(&body.body);
+
+        message
+    }
+}
+
+/// A sender or a recipient, along with their name if we know it
+#[derive(Debug, Clone)]
+pub struct Mailbox {
+    name: Option<String>,
+    email: String,
+}
+
+impl Mailbox {
+    pub fn new(email: &SubscriberEmail) -> Self {
+        Self {
+            name: None,
+            email: email.as_ref().into(),
+        }
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    /// Header tokens, with the name encoded if it isn't plain ASCII
+    fn tokens(&self) -> Vec<String> {
+        let mut tokens = match &self.name {
+            Some(name) if is_plain_ascii(name) => vec![quoted(name)],
+            Some(name) => encoded_words(name),
+            None => return vec![self.email.clone()],
+        };
+        tokens.push(format!("<{}>", self.email));
+
+        tokens
+    }
+}
+
+/// `"Name" <email>`, or just the email if there is no name, without any encoding
+impl Display for Mailbox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{} <{}>", quoted(&sanitized(name)), self.email),
+            None => write!(f, "{}", self.email),
+        }
+    }
+}
+
+impl From<&SubscriberEmail> for Mailbox {
+    fn from(email: &SubscriberEmail) -> Self {
+        Self::new(email)
+    }
+}
+
+/// A file sent along with the message, either attached or shown inline
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    filename: String,
+    content_type: String,
+    content: Vec<u8>,
+    content_id: Option<String>,
+}
+
+impl Attachment {
+    pub fn new(filename: &str, content_type: &str, content: Vec<u8>) -> Self {
+        Self {
+            filename: filename.into(),
+            content_type: content_type.into(),
+            content,
+            content_id: None,
+        }
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+
+    /// Set for inline images only
+    pub fn content_id(&self) -> Option<&str> {
+        self.content_id.as_deref()
+    }
+
+    fn part(&self, disposition: &str) -> Part {
+        let filename = sanitized(&self.filename);
+        // Non-ASCII file names are percent-encoded, as per RFC 2231
+        let filename = if is_plain_ascii(&filename) {
+            format!("filename={}", quoted(&filename))
+        } else {
+            format!("filename*=utf-8''{}", urlencoding::encode(&filename))
+        };
+        let mut headers = header("Content-Type", vec![sanitized(&self.content_type)]);
+        headers.push_str(&header("Content-Transfer-Encoding", vec!["base64".into()]));
+        headers.push_str(&header(
+            "Content-Disposition",
+            vec![format!("{};", disposition), filename],
+        ));
+        if let Some(content_id) = &self.content_id {
+            headers.push_str(&header(
+                "Content-ID",
+                vec![format!("<{}>", sanitized(content_id))],
+            ));
+        }
+
+        Part {
+            headers,
+            body: wrapped_base64(&self.content),
+        }
+    }
+}
+
+/// A MIME entity: its `Content-*` headers and its body
+struct Part {
+    headers: String,
+    body: String,
+}
+
+fn text_part(content_type: &str, content: &str) -> Part {
+    let mut headers = header(
+        "Content-Type",
+        vec![format!("{};", content_type), "charset=utf-8".into()],
+    );
+    headers.push_str(&header(
+        "Content-Transfer-Encoding",
+        vec!["quoted-printable".into()],
+    ));
+
+    Part {
+        headers,
+        body: quoted_printable(content),
+    }
+}
+
+fn multipart(subtype: &str, parts: Vec<Part>) -> Part {
+    let boundary = Uuid::new_v4().to_simple().to_string();
+    let headers = header(
+        "Content-Type",
+        vec![
+            format!("multipart/{};", subtype),
+            format!("boundary=\"{}\"", boundary),
+        ],
+    );
+    let mut body = String::new();
+    for part in parts {
+        body.push_str(&format!("--{}\r\n", boundary));
+        body.push_str(&part.headers);
+        body.push_str("\r\n");
+        body.push_str(&part.body);
+        body.push_str("\r\n");
+    }
+    body.push_str(&format!("--{}--\r\n", boundary));
+
+    Part { headers, body }
+}
+
+/// Format a header, folding it between tokens so that lines stay short
+fn header(name: &str, tokens: Vec<String>) -> String {
+    let mut header = format!("{}:", name);
+    let mut line_length = header.len();
+    for (i, token) in tokens.iter().enumerate() {
+        if i > 0 && line_length + 1 + token.len() > MAX_LINE_LENGTH {
+            header.push_str("\r\n");
+            line_length = 0;
+        }
+        header.push(' ');
+        header.push_str(token);
+        line_length += 1 + token.len();
+    }
+    header.push_str("\r\n");
+
+    header
+}
+
+/// Split unstructured header text into tokens, encoding it if it isn't plain ASCII.
+/// Line breaks are replaced, so that they can't be used to inject headers.
+fn text_tokens(value: &str) -> Vec<String> {
+    let value = sanitized(value);
+    if is_plain_ascii(&value) {
+        value.split(' ').map(String::from).collect()
+    } else {
+        encoded_words(&value)
+    }
+}
+
+/// Encode text as RFC 2047 encoded-words, split on character boundaries
+fn encoded_words(value: &str) -> Vec<String> {
+    let value = sanitized(value);
+    let mut words = Vec::new();
+    let mut chunk = String::new();
+    for c in value.chars() {
+        if chunk.len() + c.len_utf8() > MAX_ENCODED_WORD_BYTES {
+            words.push(format!("=?utf-8?B?{}?=", base64::encode(&chunk)));
+            chunk.clear();
+        }
+        chunk.push(c);
+    }
+    words.push(format!("=?utf-8?B?{}?=", base64::encode(&chunk)));
+
+    words
+}
+
+fn is_plain_ascii(value: &str) -> bool {
+    value.bytes().all(|b| b == b' ' || b.is_ascii_graphic()) && !value.contains("=?")
+}
+
+fn quoted(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn sanitized(value: &str) -> String {
+    value.replace("\r\n", " ").replace(['\r', '\n'], " ")
+}
+
+/// Encode the content as quoted-printable, with CRLF line endings
+fn quoted_printable(content: &str) -> String {
+    let mut encoded = String::with_capacity(content.len());
+    for (i, line) in content.replace("\r\n", "\n").split('\n').enumerate() {
+        if i > 0 {
+            encoded.push_str("\r\n");
+        }
+        let mut line_length = 0;
+        let bytes = line.as_bytes();
+        for (j, &byte) in bytes.iter().enumerate() {
+            // Whitespace at the end of a line would be stripped on the way
+            let is_last = j + 1 == bytes.len();
+            let chunk = match byte {
+                b'!'..=b'<' | b'>'..=b'~' => (byte as char).to_string(),
+                b' ' | b'\t' if !is_last => (byte as char).to_string(),
+                _ => format!("={:02X}", byte),
+            };
+            // Leave room for the `=` of the soft line break
+            if line_length + chunk.len() > 75 {
+                encoded.push_str("=\r\n");
+                line_length = 0;
+            }
+            encoded.push_str(&chunk);
+            line_length += chunk.len();
+        }
+    }
+
+    encoded
+}
+
+fn wrapped_base64(content: &[u8]) -> String {
+    let encoded = base64::encode(content);
+    let mut wrapped = String::with_capacity(encoded.len() + encoded.len() / 38);
+    for (i, line) in encoded.as_bytes().chunks(76).enumerate() {
+        if i > 0 {
+            wrapped.push_str("\r\n");
+        }
+        // Base64 is ASCII only
+        wrapped.push_str(std::str::from_utf8(line).unwrap());
+    }
+
+    wrapped
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{Attachment, Mailbox, Message},
+        crate::domain::SubscriberEmail,
+        mail_parser::{MessageParser, MimeHeaders},
+    };
+
+    fn mailbox(email: &str) -> Mailbox {
+        Mailbox::new(&SubscriberEmail::parse(email.into()).unwrap())
+    }
+
+    fn message(subject: &str, html_content: &str, text_content: &str) -> Message {
+        Message::new(
+            mailbox("sender@example.com"),
+            mailbox("recipient@example.com"),
+            subject,
+            html_content,
+            text_content,
+        )
+    }
+
+    #[test]
+    fn a_simple_message_round_trips() {
+        // Arrange
+        let message = message("Subject", "<p>Html content</p>", "Text content");
+
+        // Act
+        let formatted = message.format();
+        let parsed = MessageParser::default()
+            .parse(formatted.as_bytes())
+            .unwrap();
+
+        // Assert
+        let from = parsed.from().unwrap().first().unwrap();
+        assert_eq!(from.address(), Some("sender@example.com"));
+        let to = parsed.to().unwrap().first().unwrap();
+        assert_eq!(to.address(), Some("recipient@example.com"));
+        assert_eq!(parsed.subject(), Some("Subject"));
+        assert_eq!(
+            parsed.message_id(),
+            Some(message.message_id().trim_matches(&['<', '>'][..]))
+        );
+        assert_eq!(parsed.body_text(0).unwrap(), "Text content");
+        assert_eq!(parsed.body_html(0).unwrap(), "<p>Html content</p>");
+        assert_eq!(parsed.attachment_count(), 0);
+    }
+
+    #[test]
+    fn non_ascii_subjects_and_names_are_encoded() {
+        // Arrange
+        let subject =
+            "Café ☕ — our longest édition spéciale, with a subject spanning several lines";
+        let message = Message::new(
+            mailbox("sender@example.com").with_name("Zoë Müller"),
+            mailbox("recipient@example.com").with_name("Jürgen \"Jay\" Ñúñez"),
+            subject,
+            "<p>Grüße</p>",
+            "Grüße",
+        );
+
+        // Act
+        let formatted = message.format();
+        let parsed = MessageParser::default()
+            .parse(formatted.as_bytes())
+            .unwrap();
+
+        // Assert
+        assert!(formatted.is_ascii());
+        assert_eq!(parsed.subject(), Some(subject));
+        let from = parsed.from().unwrap().first().unwrap();
+        assert_eq!(from.name(), Some("Zoë Müller"));
+        let to = parsed.to().unwrap().first().unwrap();
+        assert_eq!(to.name(), Some("Jürgen \"Jay\" Ñúñez"));
+        assert_eq!(parsed.body_text(0).unwrap(), "Grüße");
+        assert_eq!(parsed.body_html(0).unwrap(), "<p>Grüße</p>");
+    }
+
+    #[test]
+    fn reply_to_and_custom_headers_round_trip() {
+        // Arrange
+        let message = message("Subject", "<p>Content</p>", "Content")
+            .with_reply_to(mailbox("editor@example.com").with_name("The Editor"))
+            .with_header("X-Campaign", "issue-42");
+
+        // Act
+        let formatted = message.format();
+        let parsed = MessageParser::default()
+            .parse(formatted.as_bytes())
+            .unwrap();
+
+        // Assert
+        let reply_to = parsed.reply_to().unwrap().first().unwrap();
+        assert_eq!(reply_to.name(), Some("The Editor"));
+        assert_eq!(reply_to.address(), Some("editor@example.com"));
+        assert_eq!(
+            parsed.header_raw("X-Campaign").map(str::trim),
+            Some("issue-42")
+        );
+    }
+
+    #[test]
+    fn line_breaks_cannot_inject_headers() {
+        // Arrange
+        let message = message(
+            "Subject\r\nBcc: victim@example.com",
+            "<p>Content</p>",
+            "Content",
+        )
+        .with_header("X-Campaign", "issue-42\nBcc: victim@example.com");
+
+        // Act
+        let formatted = message.format();
+        let parsed = MessageParser::default()
+            .parse(formatted.as_bytes())
+            .unwrap();
+
+        // Assert
+        assert!(parsed.header_raw("Bcc").is_none());
+    }
+
+    #[test]
+    fn attachments_and_inline_images_round_trip() {
+        // Arrange
+        let pdf: Vec<u8> = (0..=255).cycle().take(4096).collect();
+        let logo = vec![0x89, b'P', b'N', b'G', 0, 1, 2, 3];
+        let message = message("Subject", "<p><img src=\"cid:logo\"></p>", "Content")
+            .with_inline_image(
+                "logo",
+                Attachment::new("logo.png", "image/png", logo.clone()),
+            )
+            .with_attachment(Attachment::new("issue.pdf", "application/pdf", pdf.clone()));
+
+        // Act
+        let formatted = message.format();
+        let parsed = MessageParser::default()
+            .parse(formatted.as_bytes())
+            .unwrap();
+
+        // Assert
+        assert_eq!(parsed.body_text(0).unwrap(), "Content");
+        assert_eq!(parsed.attachment_count(), 2);
+        let image = parsed.attachment(0).unwrap();
+        assert_eq!(image.content_id(), Some("logo"));
+        assert_eq!(image.content_type().unwrap().subtype(), Some("png"));
+        assert_eq!(image.contents(), &logo[..]);
+        let attachment = parsed.attachment(1).unwrap();
+        assert_eq!(attachment.attachment_name(), Some("issue.pdf"));
+        assert_eq!(attachment.content_type().unwrap().ctype(), "application");
+        assert_eq!(attachment.contents(), &pdf[..]);
+    }
+
+    #[test]
+    fn no_line_is_longer_than_78_characters() {
+        // Arrange
+        let long_line =
+            "A very long line of content, which must be wrapped. ".repeat(10) + "The end.";
+        let message = message(&"Ünïcödé ".repeat(20), &long_line, &long_line).with_attachment(
+            Attachment::new("data.bin", "application/octet-stream", vec![7; 1000]),
+        );
+
+        // Act
+        let formatted = message.format();
+        let parsed = MessageParser::default()
+            .parse(formatted.as_bytes())
+            .unwrap();
+
+        // Assert
+        for line in formatted.split("\r\n") {
+            assert!(line.len() <= 78, "{:?} is too long", line);
+        }
+        assert_eq!(parsed.body_text(0).unwrap(), long_line);
+    }
+}
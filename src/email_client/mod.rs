@@ -0,0 +1,458 @@
+mod file;
+mod message;
+mod postmark;
+mod smtp;
+
+pub use {
+    file::FileTransport,
+    message::{Attachment, Mailbox, Message},
+    postmark::{PostmarkTransport, MAX_BATCH_SIZE},
+    smtp::SmtpTransport,
+};
+
+use {
+    crate::domain::SubscriberEmail,
+    std::{
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
+};
+
+/// Sends emails on behalf of the newsletter's sender, through the configured transport.
+///
+/// Fallback transports take over, in order, when the ones before them keep failing.
+/// A transport that failed `failure_threshold` times in a row is left alone
+/// for `cooldown`, after which it gets probed again with the next email.
+pub struct EmailClient {
+    sender: SubscriberEmail,
+    providers: Vec<Provider>,
+    failure_threshold: u32,
+    cooldown: Duration,
+}
+
+impl EmailClient {
+    pub fn new(sender: SubscriberEmail, transport: Box<dyn EmailTransport>) -> Self {
+        Self {
+            sender,
+            providers: vec![Provider::new(transport)],
+            failure_threshold: 5,
+            cooldown: Duration::from_secs(30),
+        }
+    }
+
+    /// Add a transport to fall over to, after the ones already added
+    pub fn with_fallback(mut self, transport: Box<dyn EmailTransport>) -> Self {
+        self.providers.push(Provider::new(transport));
+        self
+    }
+
+    pub fn with_circuit_breaker(mut self, failure_threshold: u32, cooldown: Duration) -> Self {
+        self.failure_threshold = failure_threshold.max(1);
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// The newsletter's sender, for messages that don't pick their own
+    pub fn sender(&self) -> &SubscriberEmail {
+        &self.sender
+    }
+
+    /// Start a message from the newsletter's sender, to be sent with `send_message`
+    pub fn message(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Message {
+        Message::new(
+            Mailbox::new(&self.sender),
+            Mailbox::new(recipient),
+            subject,
+            html_content,
+            text_content,
+        )
+    }
+
+    /// Send a single email, returning the id the transport tracks it with, if any
+    pub async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<Option<String>, SendError> {
+        let message = self.message(recipient, subject, html_content, text_content);
+
+        self.send_message(&message).await
+    }
+
+    /// Send a message, returning the id the transport tracks it with, if any
+    pub async fn send_message(&self, message: &Message) -> Result<Option<String>, SendError> {
+        let mut last_error = None;
+        for provider in self.available_providers() {
+            match provider.transport.send(message).await {
+                Ok(message_id) => {
+                    provider.record_success();
+                    return Ok(message_id);
+                }
+                Err(e) => {
+                    self.record_failure(provider, &e);
+                    // The next provider would turn this email down just the same
+                    if e.is_permanent() {
+                        return Err(e);
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.expect("There is always at least one provider"))
+    }
+
+    /// Send up to `MAX_BATCH_SIZE` emails at once.
+    ///
+    /// Every email is accepted or rejected on its own, so a successful call
+    /// returns one result per email, in the same order as `messages`.
+    pub async fn send_batch(
+        &self,
+        messages: &[Message],
+    ) -> Result<Vec<Result<Option<String>, SendError>>, SendError> {
+        let mut last_error = None;
+        for provider in self.available_providers() {
+            match provider.transport.send_batch(messages).await {
+                Ok(results) => {
+                    provider.record_success();
+                    return Ok(results);
+                }
+                Err(e) => {
+                    self.record_failure(provider, &e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.expect("There is always at least one provider"))
+    }
+
+    /// Providers worth trying, in order of preference.
+    /// When every circuit is open, trying them all beats not sending at all.
+    fn available_providers(&self) -> Vec<&Provider> {
+        let now = Instant::now();
+        let available: Vec<_> = self
+            .providers
+            .iter()
+            .filter(|provider| provider.is_available(now))
+            .collect();
+
+        if available.is_empty() {
+            self.providers.iter().collect()
+        } else {
+            available
+        }
+    }
+
+    /// Count a failed send against the provider's circuit,
+    /// unless the provider is fine and only turned down what it was sent
+    fn record_failure(&self, provider: &Provider, error: &SendError) {
+        if !error.is_provider_failure() {
+            provider.record_success();
+            return;
+        }
+        let mut circuit = provider.circuit.lock().unwrap();
+        circuit.consecutive_failures += 1;
+        if circuit.consecutive_failures >= self.failure_threshold {
+            tracing::warn!(
+                error.cause_chain = ?error,
+                error.message = %error,
+                consecutive_failures = circuit.consecutive_failures,
+                "An email provider keeps failing. Falling over to the next one."
+            );
+            circuit.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
+
+/// A transport along with the health of its circuit
+struct Provider {
+    transport: Box<dyn EmailTransport>,
+    circuit: Mutex<Circuit>,
+}
+
+#[derive(Default)]
+struct Circuit {
+    consecutive_failures: u32,
+    /// Until when the provider is skipped, if it is
+    open_until: Option<Instant>,
+}
+
+impl Provider {
+    fn new(transport: Box<dyn EmailTransport>) -> Self {
+        Self {
+            transport,
+            circuit: Mutex::default(),
+        }
+    }
+
+    /// Whether the circuit is closed, or the cooldown elapsed and it can be probed again
+    fn is_available(&self, now: Instant) -> bool {
+        match self.circuit.lock().unwrap().open_until {
+            Some(open_until) => open_until <= now,
+            None => true,
+        }
+    }
+
+    fn record_success(&self) {
+        *self.circuit.lock().unwrap() = Circuit::default();
+    }
+}
+
+/// The way emails leave the application
+#[async_trait::async_trait]
+pub trait EmailTransport: Send + Sync {
+    /// Send a single message, returning the id it can be tracked with, if any
+    async fn send(&self, message: &Message) -> Result<Option<String>, SendError>;
+
+    /// Send several messages at once, returning one result per message.
+    /// Transports without a batch API send them one by one.
+    async fn send_batch(
+        &self,
+        messages: &[Message],
+    ) -> Result<Vec<Result<Option<String>, SendError>>, SendError> {
+        let mut results = Vec::with_capacity(messages.len());
+        for message in messages {
+            results.push(self.send(message).await);
+        }
+
+        Ok(results)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SendError {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error("Rejected by the email provider (error code {error_code}): {message}")]
+    Rejected { error_code: i64, message: String },
+    /// The provider turned down a request as a whole, rather than any email in it
+    #[error(
+        "The email provider refused the request ({status}, error code {error_code}): {message}"
+    )]
+    RequestRejected {
+        status: u16,
+        error_code: i64,
+        message: String,
+    },
+    #[error("The email provider sent a response that doesn't add up: {0}")]
+    UnexpectedResponse(String),
+    #[error("The SMTP server replied {code}: {message}")]
+    Smtp { code: u16, message: String },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl SendError {
+    /// Status code returned by the email provider's API, if it answered at all
+    pub fn http_status(&self) -> Option<u16> {
+        match self {
+            SendError::Http(e) => e.status().map(|status| status.as_u16()),
+            SendError::RequestRejected { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Whether sending the same email again is bound to fail too,
+    /// i.e. the request itself is invalid or the recipient is marked as inactive.
+    /// SMTP servers say so with a 5xx reply, while 4xx replies are worth retrying.
+    pub fn is_permanent(&self) -> bool {
+        matches!(
+            self,
+            SendError::Rejected {
+                error_code: 300 | 406,
+                ..
+            } | SendError::RequestRejected { .. }
+                | SendError::Smtp {
+                    code: 500..=599,
+                    ..
+                }
+        )
+    }
+
+    /// Whether the provider itself is failing, i.e. it can't be reached,
+    /// answers with a 5xx or with a response that doesn't add up
+    fn is_provider_failure(&self) -> bool {
+        match self {
+            SendError::Http(e) => match e.status() {
+                Some(status) => status.is_server_error(),
+                // The request didn't get through at all
+                None => true,
+            },
+            SendError::Rejected { .. } | SendError::RequestRejected { .. } => false,
+            SendError::UnexpectedResponse(_) | SendError::Io(_) => true,
+            // 5xx replies turn down the email rather than tell of a failing server
+            SendError::Smtp { code, .. } => !(500..=599).contains(code),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{EmailClient, PostmarkTransport, SendError},
+        crate::domain::SubscriberEmail,
+        claim::assert_ok,
+        secrecy::Secret,
+        std::time::Duration,
+        wiremock::{matchers::any, Mock, MockServer, ResponseTemplate},
+    };
+
+    fn transport(server: &MockServer) -> Box<PostmarkTransport> {
+        Box::new(PostmarkTransport::new(
+            server.uri(),
+            Secret::new("token".into()),
+            Duration::from_millis(200),
+        ))
+    }
+
+    /// Get a test instance of `EmailClient` falling over from `primary` to `secondary`
+    fn email_client(
+        primary: &MockServer,
+        secondary: &MockServer,
+        cooldown: Duration,
+    ) -> EmailClient {
+        let sender = SubscriberEmail::parse("sender@example.com".into()).unwrap();
+        EmailClient::new(sender, transport(primary))
+            .with_fallback(transport(secondary))
+            .with_circuit_breaker(2, cooldown)
+    }
+
+    async fn send(email_client: &EmailClient) -> Result<Option<String>, SendError> {
+        let recipient = SubscriberEmail::parse("recipient@example.com".into()).unwrap();
+        email_client
+            .send_email(&recipient, "Subject", "<p>Content</p>", "Content")
+            .await
+    }
+
+    #[tokio::test]
+    async fn emails_fall_over_to_the_secondary_when_the_primary_fails() {
+        // Arrange
+        let primary = MockServer::start().await;
+        let secondary = MockServer::start().await;
+        let email_client = email_client(&primary, &secondary, Duration::from_secs(60));
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&primary)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&secondary)
+            .await;
+
+        // Act
+        let outcome = send(&email_client).await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn a_failing_primary_is_skipped_once_its_circuit_opens() {
+        // Arrange
+        let primary = MockServer::start().await;
+        let secondary = MockServer::start().await;
+        let email_client = email_client(&primary, &secondary, Duration::from_secs(60));
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            // The circuit opens after two failures in a row
+            .expect(2)
+            .mount(&primary)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(5)
+            .mount(&secondary)
+            .await;
+
+        // Act
+        for _ in 0..5 {
+            assert_ok!(send(&email_client).await);
+        }
+
+        // Assert
+    }
+
+    #[tokio::test]
+    async fn rejected_emails_do_not_open_the_circuit() {
+        // Arrange
+        let primary = MockServer::start().await;
+        let secondary = MockServer::start().await;
+        let email_client = email_client(&primary, &secondary, Duration::from_secs(60));
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422).set_body_json(serde_json::json!({
+                "ErrorCode": 406,
+                "Message": "You tried to send to a recipient that has been marked as inactive."
+            })))
+            .expect(3)
+            .mount(&primary)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&secondary)
+            .await;
+
+        // Act
+        for _ in 0..3 {
+            let outcome = send(&email_client).await;
+
+            // Assert
+            assert!(matches!(
+                outcome,
+                Err(SendError::Rejected {
+                    error_code: 406,
+                    ..
+                })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn the_primary_is_probed_again_after_the_cooldown() {
+        // Arrange
+        let primary = MockServer::start().await;
+        let secondary = MockServer::start().await;
+        let email_client = email_client(&primary, &secondary, Duration::from_millis(100));
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&primary)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&primary)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(3)
+            .mount(&secondary)
+            .await;
+
+        // Act
+        for _ in 0..3 {
+            assert_ok!(send(&email_client).await);
+        }
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let outcome = send(&email_client).await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+}
@@ -0,0 +1,641 @@
+use {
+    super::{EmailTransport, Message, SendError},
+    reqwest::Client,
+    secrecy::{ExposeSecret, Secret},
+    serde::{Deserialize, Serialize},
+};
+
+/// Most messages Postmark accepts in a single call to `/email/batch`
+pub const MAX_BATCH_SIZE: usize = 500;
+
+/// Sends emails through Postmark's HTTP API
+pub struct PostmarkTransport {
+    http_client: Client,
+    base_url: String,
+    auth_token: Secret<String>,
+}
+
+impl PostmarkTransport {
+    pub fn new(base_url: String, auth_token: Secret<String>, timeout: std::time::Duration) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+
+        Self {
+            http_client,
+            base_url,
+            auth_token,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for PostmarkTransport {
+    /// Send a single email, returning the message id Postmark assigned to it, if any
+    async fn send(&self, message: &Message) -> Result<Option<String>, SendError> {
+        let url = format!("{}/email", self.base_url);
+        let request_body = SendEmailRequest::from(message);
+
+        let response = self
+            .http_client
+            .post(&url)
+            .header("X-Postmark-Server-Token", self.auth_token.expose_secret())
+            .json(&request_body)
+            .send()
+            .await?;
+        // The request only holds this email, so Postmark turning it down
+        // is about the email, e.g. because the recipient is marked as inactive
+        let response = error_for_status(response).await.map_err(|e| match e {
+            SendError::RequestRejected {
+                error_code,
+                message,
+                ..
+            } => SendError::Rejected {
+                error_code,
+                message,
+            },
+            e => e,
+        })?;
+        // The email has been accepted at this point,
+        // so a body we can't make sense of shouldn't fail the whole send
+        let message_id = response
+            .json::<SendEmailResponse>()
+            .await
+            .ok()
+            .map(|r| r.message_id);
+
+        Ok(message_id)
+    }
+
+    /// Send up to `MAX_BATCH_SIZE` emails in a single request.
+    ///
+    /// Postmark accepts or rejects every message on its own, so a successful call
+    /// returns one result per email, in the same order as `emails`.
+    async fn send_batch(
+        &self,
+        messages: &[Message],
+    ) -> Result<Vec<Result<Option<String>, SendError>>, SendError> {
+        let url = format!("{}/email/batch", self.base_url);
+        let request_body: Vec<_> = messages.iter().map(SendEmailRequest::from).collect();
+
+        let response = self
+            .http_client
+            .post(&url)
+            .header("X-Postmark-Server-Token", self.auth_token.expose_secret())
+            .json(&request_body)
+            .send()
+            .await?;
+        // A 4xx turns down the batch as a whole, e.g. because the token is invalid
+        let response = error_for_status(response).await?;
+        // Unlike in `send`, the verdict on every message is needed
+        // to tell which ones went out
+        let verdicts = response
+            .json::<Vec<BatchEmailResponse>>()
+            .await
+            .map_err(|e| SendError::UnexpectedResponse(e.to_string()))?;
+        if verdicts.len() != messages.len() {
+            return Err(SendError::UnexpectedResponse(format!(
+                "Got {} results for a batch of {} emails.",
+                verdicts.len(),
+                messages.len()
+            )));
+        }
+
+        Ok(verdicts
+            .into_iter()
+            .map(|r| match r.error_code {
+                0 => Ok(r.message_id),
+                error_code => Err(SendError::Rejected {
+                    error_code,
+                    message: r.message,
+                }),
+            })
+            .collect())
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: String,
+    to: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<String>,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<Header<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<Attachment<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Header<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Attachment<'a> {
+    name: &'a str,
+    content: String,
+    content_type: &'a str,
+    #[serde(rename = "ContentID", skip_serializing_if = "Option::is_none")]
+    content_id: Option<String>,
+}
+
+impl<'a> From<&'a Message> for SendEmailRequest<'a> {
+    fn from(message: &'a Message) -> Self {
+        // Postmark takes care of the MIME encoding, inline images included
+        let attachments = message
+            .inline_images()
+            .iter()
+            .chain(message.attachments())
+            .map(|attachment| Attachment {
+                name: attachment.filename(),
+                content: base64::encode(attachment.content()),
+                content_type: attachment.content_type(),
+                content_id: attachment
+                    .content_id()
+                    .map(|content_id| format!("cid:{}", content_id)),
+            })
+            .collect();
+
+        Self {
+            from: message.from().to_string(),
+            to: message.to().to_string(),
+            reply_to: message.reply_to().map(ToString::to_string),
+            subject: message.subject(),
+            html_body: message.html_content(),
+            text_body: message.text_content(),
+            headers: message
+                .headers()
+                .iter()
+                .map(|(name, value)| Header { name, value })
+                .collect(),
+            attachments,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SendEmailResponse {
+    #[serde(rename = "MessageID")]
+    message_id: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    #[serde(rename = "ErrorCode")]
+    error_code: i64,
+    #[serde(rename = "Message")]
+    message: String,
+}
+
+/// Fail on error statuses, with the reason Postmark gives for turning down the request
+/// on a 4xx. Other failures, 4xx without Postmark's error body included, are HTTP errors.
+async fn error_for_status(response: reqwest::Response) -> Result<reqwest::Response, SendError> {
+    let status = response.status();
+    if !status.is_client_error() {
+        return Ok(response.error_for_status()?);
+    }
+    let error = response.error_for_status_ref().unwrap_err();
+    match response.json::<ErrorResponse>().await {
+        Ok(body) => Err(SendError::RequestRejected {
+            status: status.as_u16(),
+            error_code: body.error_code,
+            message: body.message,
+        }),
+        Err(_) => Err(error.into()),
+    }
+}
+
+#[derive(Deserialize)]
+struct BatchEmailResponse {
+    #[serde(rename = "ErrorCode")]
+    error_code: i64,
+    #[serde(rename = "Message")]
+    message: String,
+    #[serde(rename = "MessageID", default)]
+    message_id: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::PostmarkTransport,
+        crate::{
+            domain::SubscriberEmail,
+            email_client::{Attachment, EmailClient, Mailbox, SendError},
+        },
+        claim::{assert_err, assert_ok},
+        fake::{
+            faker::{
+                internet::en::SafeEmail,
+                lorem::en::{Paragraph, Sentence},
+            },
+            Fake, Faker,
+        },
+        secrecy::Secret,
+        wiremock::{
+            matchers::{any, header, header_exists, method, path},
+            Mock, MockServer, Request, ResponseTemplate,
+        },
+    };
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+
+            if let Ok(body) = result {
+                dbg!(&body);
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    /// Generate a random email subject
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    /// Generate a random email content
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    /// Generate a random email content
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    /// Get a test instance of `EmailClient` sending through Postmark
+    fn email_client(base_url: String) -> EmailClient {
+        let transport = PostmarkTransport::new(
+            base_url,
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+        );
+        EmailClient::new(email(), Box::new(transport))
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1) // We expect to fire only one http request
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_server_returns_200() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_returns_the_message_id_assigned_by_the_server() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "To": "receiver@example.com",
+                "SubmittedAt": "2022-06-25T11:22:01.1233478Z",
+                "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+                "ErrorCode": 0,
+                "Message": "OK"
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_eq!(
+            outcome.unwrap().as_deref(),
+            Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817")
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_is_rejected_for_good_if_the_recipient_is_inactive() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422).set_body_json(serde_json::json!({
+                "ErrorCode": 406,
+                "Message": "You tried to send to a recipient that has been marked as inactive."
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        let error = outcome.unwrap_err();
+        assert!(matches!(
+            error,
+            SendError::Rejected {
+                error_code: 406,
+                ..
+            }
+        ));
+        assert!(error.is_permanent());
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let response = ResponseTemplate::new(500).set_delay(std::time::Duration::from_secs(180));
+
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_batch_sends_every_email_in_a_single_request() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients = vec![email(), email(), email()];
+        let (subject, content) = (subject(), content());
+        let messages: Vec<_> = recipients
+            .iter()
+            .map(|recipient| email_client.message(recipient, &subject, &content, &content))
+            .collect();
+
+        let verdict = serde_json::json!({ "ErrorCode": 0, "Message": "OK" });
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(vec![verdict; 3]))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client.send_batch(&messages).await;
+
+        // Assert
+        assert_eq!(outcome.unwrap().len(), 3);
+        let body: serde_json::Value =
+            serde_json::from_slice(&mock_server.received_requests().await.unwrap()[0].body)
+                .unwrap();
+        let messages = body.as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        for (message, recipient) in messages.iter().zip(&recipients) {
+            assert_eq!(message["To"], recipient.as_ref());
+        }
+    }
+
+    #[tokio::test]
+    async fn send_batch_returns_the_result_of_every_email() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients = [email(), email()];
+        let messages: Vec<_> = recipients
+            .iter()
+            .map(|recipient| {
+                email_client.message(recipient, "Subject", "<p>Content</p>", "Content")
+            })
+            .collect();
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {
+                    "ErrorCode": 0,
+                    "Message": "OK",
+                    "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+                    "SubmittedAt": "2022-06-27T10:31:52.1232465Z",
+                    "To": "receiver1@example.com"
+                },
+                {
+                    "ErrorCode": 406,
+                    "Message": "You tried to send to a recipient that has been marked as inactive."
+                }
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client.send_batch(&messages).await.unwrap();
+
+        // Assert
+        assert_eq!(
+            outcome[0].as_ref().unwrap().as_deref(),
+            Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817")
+        );
+        let rejection = outcome[1].as_ref().unwrap_err();
+        assert!(matches!(
+            rejection,
+            SendError::Rejected {
+                error_code: 406,
+                ..
+            }
+        ));
+        assert!(rejection.is_permanent());
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_if_the_result_of_every_email_cannot_be_read() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let messages: Vec<_> = (0..2)
+            .map(|_| email_client.message(&email(), &subject(), &content(), &content()))
+            .collect();
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                { "ErrorCode": 0, "Message": "OK" }
+            ])))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_string("Accepted"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let missing_result = email_client.send_batch(&messages).await;
+        let unreadable_body = email_client.send_batch(&messages).await;
+
+        // Assert
+        assert!(matches!(
+            missing_result,
+            Err(SendError::UnexpectedResponse(_))
+        ));
+        assert!(matches!(
+            unreadable_body,
+            Err(SendError::UnexpectedResponse(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn send_batch_is_rejected_as_a_whole_on_a_4xx() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let messages = vec![email_client.message(&email(), &subject(), &content(), &content())];
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(401).set_body_json(serde_json::json!({
+                "ErrorCode": 10,
+                "Message": "The Server Token you provided in the X-Postmark-Server-Token request header was invalid."
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client.send_batch(&messages).await;
+
+        // Assert
+        let error = outcome.unwrap_err();
+        assert!(matches!(
+            error,
+            SendError::RequestRejected {
+                status: 401,
+                error_code: 10,
+                ..
+            }
+        ));
+        assert!(error.is_permanent());
+    }
+
+    #[tokio::test]
+    async fn send_message_sends_reply_to_headers_and_attachments() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let message = email_client
+            .message(&email(), &subject(), &content(), &content())
+            .with_reply_to(Mailbox::new(&email()).with_name("The Editor"))
+            .with_header("X-Campaign", "issue-42")
+            .with_inline_image(
+                "logo",
+                Attachment::new("logo.png", "image/png", vec![1, 2, 3]),
+            )
+            .with_attachment(Attachment::new("issue.pdf", "application/pdf", vec![4, 5]));
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client.send_message(&message).await;
+
+        // Assert
+        assert_ok!(outcome);
+        let body: serde_json::Value =
+            serde_json::from_slice(&mock_server.received_requests().await.unwrap()[0].body)
+                .unwrap();
+        assert!(body["ReplyTo"]
+            .as_str()
+            .unwrap()
+            .starts_with("\"The Editor\" <"));
+        assert_eq!(body["Headers"][0]["Name"], "X-Campaign");
+        assert_eq!(body["Headers"][0]["Value"], "issue-42");
+        assert_eq!(body["Attachments"][0]["Name"], "logo.png");
+        assert_eq!(body["Attachments"][0]["Content"], base64::encode([1, 2, 3]));
+        assert_eq!(body["Attachments"][0]["ContentID"], "cid:logo");
+        assert_eq!(body["Attachments"][1]["ContentType"], "application/pdf");
+        assert!(body["Attachments"][1].get("ContentID").is_none());
+    }
+}
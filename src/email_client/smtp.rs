@@ -0,0 +1,549 @@
+use {
+    super::{EmailTransport, Message, SendError},
+    crate::configuration::SmtpSettings,
+    secrecy::ExposeSecret,
+    std::{
+        io,
+        sync::{Arc, Mutex},
+        time::Duration,
+    },
+    tokio::{
+        io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+        net::TcpStream,
+        sync::Semaphore,
+    },
+    tokio_rustls::{
+        rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName},
+        TlsConnector,
+    },
+};
+
+/// Relays emails through an SMTP server.
+///
+/// Connections are kept open between emails, up to `max_connections` of them,
+/// so that a busy delivery worker doesn't pay for a handshake on every send.
+pub struct SmtpTransport {
+    settings: SmtpSettings,
+    timeout: Duration,
+    tls_connector: TlsConnector,
+    /// Connections that are done with their last email and can take the next one
+    idle_connections: Mutex<Vec<SmtpConnection>>,
+    /// One permit per connection we are allowed to have open
+    connection_permits: Semaphore,
+}
+
+impl SmtpTransport {
+    pub fn new(settings: SmtpSettings, timeout: Duration) -> Self {
+        let mut root_store = RootCertStore::empty();
+        root_store.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject,
+                ta.spki,
+                ta.name_constraints,
+            )
+        }));
+        let tls_config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(root_store)
+            .with_no_client_auth();
+        let connection_permits = Semaphore::new(settings.max_connections.max(1));
+
+        Self {
+            settings,
+            timeout,
+            tls_connector: TlsConnector::from(Arc::new(tls_config)),
+            idle_connections: Mutex::new(Vec::new()),
+            connection_permits,
+        }
+    }
+
+    /// Get a connection ready for a new email: an idle one if it is still alive,
+    /// a new one otherwise
+    async fn checkout(&self, sender: &str) -> Result<SmtpConnection, SendError> {
+        loop {
+            let idle_connection = self.idle_connections.lock().unwrap().pop();
+            match idle_connection {
+                Some(mut connection) => {
+                    // The server may have closed it while it was sitting idle
+                    if connection.reset().await.is_ok() {
+                        return Ok(connection);
+                    }
+                }
+                None => return self.connect(sender).await,
+            }
+        }
+    }
+
+    /// Open a connection, upgraded to TLS and authenticated if configured to
+    async fn connect(&self, sender: &str) -> Result<SmtpConnection, SendError> {
+        let SmtpSettings {
+            host,
+            port,
+            username,
+            password,
+            starttls,
+            ..
+        } = &self.settings;
+        // Introduce ourselves with the sender's domain, the best name we know
+        let client_name = sender.rsplit('@').next().unwrap_or("localhost");
+
+        let stream = TcpStream::connect((host.as_str(), *port)).await?;
+        let mut connection = SmtpConnection::new(Box::new(stream));
+        connection.read_reply().await?.expect(220)?;
+        connection.ehlo(client_name).await?;
+
+        if *starttls {
+            connection.command("STARTTLS").await?.expect(220)?;
+            let server_name = ServerName::try_from(host.as_str())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let stream = self
+                .tls_connector
+                .connect(server_name, connection.into_inner())
+                .await?;
+            connection = SmtpConnection::new(Box::new(stream));
+            // Whatever the server said before the upgrade can't be trusted
+            connection.ehlo(client_name).await?;
+        }
+
+        if let (Some(username), Some(password)) = (username, password) {
+            connection
+                .authenticate(username, password.expose_secret())
+                .await?;
+        }
+
+        Ok(connection)
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for SmtpTransport {
+    /// Send a single email, returning the `Message-ID` it was sent with
+    async fn send(&self, message: &Message) -> Result<Option<String>, SendError> {
+        let sender = message.from().email();
+        let data = message.format();
+
+        let _permit = self
+            .connection_permits
+            .acquire()
+            .await
+            .expect("The connection semaphore is never closed");
+        let connection = tokio::time::timeout(self.timeout, async {
+            let mut connection = self.checkout(sender).await?;
+            connection
+                .deliver(sender, message.to().email(), &data)
+                .await?;
+            Ok::<_, SendError>(connection)
+        })
+        .await
+        .map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::TimedOut,
+                "The SMTP server took too long to answer.",
+            )
+        })??;
+        // Connections that failed are dropped along the way, since we can't tell
+        // which state they were left in. Only healthy ones go back to the pool.
+        self.idle_connections.lock().unwrap().push(connection);
+
+        Ok(Some(message.message_id().to_string()))
+    }
+
+    /// Send every email concurrently, over as many connections as the pool allows
+    async fn send_batch(
+        &self,
+        messages: &[Message],
+    ) -> Result<Vec<Result<Option<String>, SendError>>, SendError> {
+        let results =
+            futures::future::join_all(messages.iter().map(|message| self.send(message))).await;
+
+        Ok(results)
+    }
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// A reply of the SMTP server, made of one or more lines sharing the same code
+struct Reply {
+    code: u16,
+    lines: Vec<String>,
+}
+
+impl Reply {
+    fn expect(self, code: u16) -> Result<Self, SendError> {
+        if self.code == code {
+            Ok(self)
+        } else {
+            Err(SendError::Smtp {
+                code: self.code,
+                message: self.lines.join(" "),
+            })
+        }
+    }
+}
+
+struct SmtpConnection {
+    stream: BufReader<Box<dyn Stream>>,
+    /// Extensions the server advertised in its last `EHLO` reply
+    extensions: Vec<String>,
+}
+
+impl SmtpConnection {
+    fn new(stream: Box<dyn Stream>) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+            extensions: Vec::new(),
+        }
+    }
+
+    fn into_inner(self) -> Box<dyn Stream> {
+        self.stream.into_inner()
+    }
+
+    fn supports(&self, extension: &str) -> bool {
+        self.extensions
+            .iter()
+            .any(|e| e.split_whitespace().next() == Some(extension))
+    }
+
+    async fn read_reply(&mut self) -> Result<Reply, SendError> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line).await? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "The SMTP server closed the connection.",
+                )
+                .into());
+            }
+            let line = line.trim_end();
+            let code = line
+                .get(..3)
+                .and_then(|code| code.parse().ok())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Malformed reply from the SMTP server: {}", line),
+                    )
+                })?;
+            lines.push(line.get(4..).unwrap_or_default().to_string());
+            // Every line but the last one has a `-` right after the code
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok(Reply { code, lines });
+            }
+        }
+    }
+
+    async fn write_commands(&mut self, commands: &[&str]) -> Result<(), SendError> {
+        let mut buffer = String::new();
+        for command in commands {
+            buffer.push_str(command);
+            buffer.push_str("\r\n");
+        }
+        self.stream.write_all(buffer.as_bytes()).await?;
+        self.stream.flush().await?;
+
+        Ok(())
+    }
+
+    async fn command(&mut self, command: &str) -> Result<Reply, SendError> {
+        self.write_commands(&[command]).await?;
+        self.read_reply().await
+    }
+
+    /// Greet the server and remember the extensions it supports
+    async fn ehlo(&mut self, client_name: &str) -> Result<(), SendError> {
+        let reply = self
+            .command(&format!("EHLO {}", client_name))
+            .await?
+            .expect(250)?;
+        // The first line is the server's own greeting
+        self.extensions = reply.lines.into_iter().skip(1).collect();
+
+        Ok(())
+    }
+
+    /// Forget about any email the previous user of the connection left behind
+    async fn reset(&mut self) -> Result<(), SendError> {
+        self.command("RSET").await?.expect(250)?;
+
+        Ok(())
+    }
+
+    async fn authenticate(&mut self, username: &str, password: &str) -> Result<(), SendError> {
+        let supports_mechanism = |mechanism: &str| {
+            self.extensions
+                .iter()
+                .filter_map(|extension| extension.strip_prefix("AUTH "))
+                .flat_map(|mechanisms| mechanisms.split_whitespace())
+                .any(|m| m == mechanism)
+        };
+        let (plain, login) = (supports_mechanism("PLAIN"), supports_mechanism("LOGIN"));
+
+        if plain {
+            let credentials = base64::encode(format!("\0{}\0{}", username, password));
+            self.command(&format!("AUTH PLAIN {}", credentials))
+                .await?
+                .expect(235)?;
+        } else if login {
+            self.command("AUTH LOGIN").await?.expect(334)?;
+            self.command(&base64::encode(username)).await?.expect(334)?;
+            self.command(&base64::encode(password)).await?.expect(235)?;
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "The SMTP server supports neither AUTH PLAIN nor AUTH LOGIN.",
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Hand a single email over to the server.
+    /// The envelope is sent in one go when the server supports `PIPELINING`.
+    async fn deliver(&mut self, from: &str, to: &str, data: &str) -> Result<(), SendError> {
+        let mail_from = format!("MAIL FROM:<{}>", from);
+        let rcpt_to = format!("RCPT TO:<{}>", to);
+
+        if self.supports("PIPELINING") {
+            self.write_commands(&[&mail_from, &rcpt_to, "DATA"]).await?;
+            // Every reply must be read, even after a failure, to stay in sync
+            let replies = [
+                self.read_reply().await?,
+                self.read_reply().await?,
+                self.read_reply().await?,
+            ];
+            for (reply, code) in replies.into_iter().zip([250, 250, 354]) {
+                reply.expect(code)?;
+            }
+        } else {
+            self.command(&mail_from).await?.expect(250)?;
+            self.command(&rcpt_to).await?.expect(250)?;
+            self.command("DATA").await?.expect(354)?;
+        }
+        self.send_data(data).await?.expect(250)?;
+
+        Ok(())
+    }
+
+    /// Send the message after `DATA`, escaping lines that start with a dot
+    async fn send_data(&mut self, message: &str) -> Result<Reply, SendError> {
+        let mut data = String::with_capacity(message.len() + 5);
+        for line in message.split_inclusive("\r\n") {
+            if line.starts_with('.') {
+                data.push('.');
+            }
+            data.push_str(line);
+        }
+        data.push_str(".\r\n");
+
+        self.stream.write_all(data.as_bytes()).await?;
+        self.stream.flush().await?;
+        self.read_reply().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::SmtpTransport,
+        crate::{
+            configuration::SmtpSettings,
+            domain::SubscriberEmail,
+            email_client::{EmailClient, SendError},
+        },
+        std::{
+            sync::{Arc, Mutex},
+            time::Duration,
+        },
+        tokio::{
+            io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+            net::{TcpListener, TcpStream},
+        },
+    };
+
+    /// An in-process SMTP server, accepting every email
+    /// unless told to answer `RCPT TO` with something else
+    struct FakeSmtpServer {
+        port: u16,
+        /// Every line received, on any connection
+        received: Arc<Mutex<Vec<String>>>,
+        n_connections: Arc<Mutex<usize>>,
+    }
+
+    impl FakeSmtpServer {
+        async fn start(rcpt_reply: &'static str) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let server = Self {
+                port: listener.local_addr().unwrap().port(),
+                received: Arc::default(),
+                n_connections: Arc::default(),
+            };
+            let received = server.received.clone();
+            let n_connections = server.n_connections.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    *n_connections.lock().unwrap() += 1;
+                    tokio::spawn(Self::session(stream, rcpt_reply, received.clone()));
+                }
+            });
+
+            server
+        }
+
+        async fn session(
+            stream: TcpStream,
+            rcpt_reply: &'static str,
+            received: Arc<Mutex<Vec<String>>>,
+        ) {
+            let mut stream = BufReader::new(stream);
+            let mut in_data = false;
+            let mut rejected = false;
+            stream.write_all(b"220 localhost ready\r\n").await.unwrap();
+
+            loop {
+                let mut line = String::new();
+                if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+                    return;
+                }
+                let line = line.trim_end().to_string();
+                received.lock().unwrap().push(line.clone());
+
+                let reply = if in_data {
+                    if line != "." {
+                        continue;
+                    }
+                    in_data = false;
+                    "250 Queued"
+                } else if line.starts_with("EHLO") {
+                    "250-localhost\r\n250-PIPELINING\r\n250 8BITMIME"
+                } else if line.starts_with("RCPT") {
+                    rejected = !rcpt_reply.starts_with('2');
+                    rcpt_reply
+                } else if line == "DATA" && rejected {
+                    "554 No valid recipients"
+                } else if line == "DATA" {
+                    in_data = true;
+                    "354 Go ahead"
+                } else {
+                    "250 OK"
+                };
+                let reply = format!("{}\r\n", reply);
+                if stream.write_all(reply.as_bytes()).await.is_err() {
+                    return;
+                }
+            }
+        }
+
+        fn transport(&self) -> SmtpTransport {
+            let settings = SmtpSettings {
+                host: "127.0.0.1".into(),
+                port: self.port,
+                username: None,
+                password: None,
+                starttls: false,
+                max_connections: 2,
+            };
+            SmtpTransport::new(settings, Duration::from_secs(5))
+        }
+
+        fn received(&self) -> Vec<String> {
+            self.received.lock().unwrap().clone()
+        }
+
+        fn n_connections(&self) -> usize {
+            *self.n_connections.lock().unwrap()
+        }
+    }
+
+    fn email_client(transport: SmtpTransport) -> EmailClient {
+        let sender = SubscriberEmail::parse("sender@example.com".into()).unwrap();
+        EmailClient::new(sender, Box::new(transport))
+    }
+
+    fn recipient() -> SubscriberEmail {
+        SubscriberEmail::parse("recipient@example.com".into()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_runs_a_whole_smtp_session() {
+        // Arrange
+        let server = FakeSmtpServer::start("250 OK").await;
+        let email_client = email_client(server.transport());
+
+        // Act
+        let outcome = email_client
+            .send_email(&recipient(), "Subject", "<p>Content</p>", ".Content")
+            .await;
+
+        // Assert
+        assert!(outcome.unwrap().is_some());
+        let received = server.received();
+        assert_eq!(received[0], "EHLO example.com");
+        assert_eq!(received[1], "MAIL FROM:<sender@example.com>");
+        assert_eq!(received[2], "RCPT TO:<recipient@example.com>");
+        assert_eq!(received[3], "DATA");
+        assert!(received.contains(&"Subject: Subject".to_string()));
+        // Lines starting with a dot are escaped
+        assert!(received.contains(&"..Content".to_string()));
+        assert_eq!(received.last().unwrap(), ".");
+    }
+
+    #[tokio::test]
+    async fn connections_are_reused_between_emails() {
+        // Arrange
+        let server = FakeSmtpServer::start("250 OK").await;
+        let email_client = email_client(server.transport());
+
+        // Act
+        for _ in 0..3 {
+            email_client
+                .send_email(&recipient(), "Subject", "<p>Content</p>", "Content")
+                .await
+                .unwrap();
+        }
+
+        // Assert
+        assert_eq!(server.n_connections(), 1);
+        let received = server.received();
+        assert_eq!(received.iter().filter(|line| *line == "RSET").count(), 2);
+    }
+
+    #[tokio::test]
+    async fn replies_in_the_5xx_range_are_permanent_failures() {
+        // Arrange
+        let server = FakeSmtpServer::start("550 No such user here").await;
+        let email_client = email_client(server.transport());
+
+        // Act
+        let outcome = email_client
+            .send_email(&recipient(), "Subject", "<p>Content</p>", "Content")
+            .await;
+
+        // Assert
+        let error = outcome.unwrap_err();
+        assert!(matches!(error, SendError::Smtp { code: 550, .. }));
+        assert!(error.is_permanent());
+    }
+
+    #[tokio::test]
+    async fn replies_in_the_4xx_range_are_retried() {
+        // Arrange
+        let server = FakeSmtpServer::start("450 Mailbox busy").await;
+        let email_client = email_client(server.transport());
+
+        // Act
+        let outcome = email_client
+            .send_email(&recipient(), "Subject", "<p>Content</p>", "Content")
+            .await;
+
+        // Assert
+        let error = outcome.unwrap_err();
+        assert!(matches!(error, SendError::Smtp { code: 450, .. }));
+        assert!(!error.is_permanent());
+    }
+}
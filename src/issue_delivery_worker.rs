@@ -1,24 +1,34 @@
 use {
     crate::{
-        configuration::Settings, domain::SubscriberEmail, email_client::EmailClient,
+        configuration::{ApplicationSettings, Settings, WorkerSettings},
+        domain::{SubscriberEmail, SubscriberToken, Template, TokenPurpose},
+        email_client::{EmailClient, Mailbox, Message, MAX_BATCH_SIZE},
         startup::get_db_pool,
     },
-    sqlx::{PgPool, Postgres, Transaction},
-    std::time::Duration,
+    chrono::Utc,
+    rand::Rng,
+    serde_json::{json, Value},
+    sqlx::{postgres::PgListener, PgPool, Postgres, Transaction},
+    std::{
+        collections::{hash_map::Entry, HashMap},
+        sync::Mutex,
+        time::Duration,
+    },
+    tokio::{sync::watch, time::Instant},
     tracing::{field::display, Span},
     uuid::Uuid,
 };
 
+/// An issue's content, ready to be personalised for every recipient
 struct NewsletterIssue {
-    title: String,
-    text_content: String,
-    html_content: String,
+    title: Template,
+    text_content: Template,
+    html_content: Template,
 }
 
 #[tracing::instrument(skip_all)]
 async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
-    let issue = sqlx::query_as!(
-        NewsletterIssue,
+    let issue = sqlx::query!(
         r#"
         SELECT title, text_content, html_content
         FROM newsletter_issues
@@ -29,23 +39,166 @@ async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, any
     .fetch_one(pool)
     .await?;
 
-    Ok(issue)
+    Ok(NewsletterIssue {
+        title: issue_template(issue.title),
+        text_content: issue_template(issue.text_content),
+        html_content: issue_template(issue.html_content),
+    })
+}
+
+/// Templates are validated when issues are published. Content that doesn't parse
+/// (e.g. from issues published before personalisation) is sent as it is.
+fn issue_template(content: String) -> Template {
+    Template::parse(&content).unwrap_or_else(|e| {
+        tracing::warn!(error.message = %e, "Sending an issue's content without personalisation.");
+        Template::text(content)
+    })
+}
+
+/// Build the issue's email for a single subscriber, personalised with their fields
+/// and with links to manage their preferences
+/// and to unsubscribe, the latter also in the `List-Unsubscribe` headers (RFC 8058).
+/// Issues sent on behalf of a list only unsubscribe from that list.
+fn issue_message(
+    application: &ApplicationSettings,
+    issue: &NewsletterIssue,
+    recipient: &Recipient,
+) -> Message {
+    let subscriber_id = recipient.subscriber_id;
+    let secret = &application.hmac_secret;
+    let link = |path: &str, token: SubscriberToken| {
+        format!(
+            "{}{}?token={}",
+            application.base_url,
+            path,
+            urlencoding::encode(token.as_ref())
+        )
+    };
+    let unsubscribe_token = match recipient.list_id {
+        Some(list_id) => SubscriberToken::generate_for_list(
+            TokenPurpose::Unsubscribe,
+            subscriber_id,
+            list_id,
+            secret,
+        ),
+        None => SubscriberToken::generate(TokenPurpose::Unsubscribe, subscriber_id, secret),
+    };
+    let unsubscribe_link = link("/subscriptions/unsubscribe", unsubscribe_token);
+    let preferences_link = link(
+        "/preferences",
+        SubscriberToken::generate(TokenPurpose::ManagePreferences, subscriber_id, secret),
+    );
+    let html_content = format!(
+        "{}<p><a href=\"{}\">Manage your preferences</a> | <a href=\"{}\">Unsubscribe</a></p>",
+        issue.html_content.render(&recipient.fields, true),
+        preferences_link,
+        unsubscribe_link
+    );
+    let text_content = format!(
+        "{}\n\nManage your preferences: {}\nUnsubscribe: {}",
+        issue.text_content.render(&recipient.fields, false),
+        preferences_link,
+        unsubscribe_link
+    );
+
+    Message::new(
+        recipient.sender.clone(),
+        Mailbox::new(&recipient.email),
+        &issue.title.render(&recipient.fields, false),
+        &html_content,
+        &text_content,
+    )
+    .with_header("List-Unsubscribe", &format!("<{}>", unsubscribe_link))
+    .with_header("List-Unsubscribe-Post", "List-Unsubscribe=One-Click")
+}
+
+/// Who a task's email goes to, and on behalf of whom
+struct Recipient {
+    email: SubscriberEmail,
+    subscriber_id: Uuid,
+    /// The list the issue goes out on behalf of, which its unsubscribe link leaves
+    list_id: Option<Uuid>,
+    sender: Mailbox,
+    /// What the issue's templates are rendered with
+    fields: Value,
+}
+
+/// Check that the task's subscriber can still be sent to, and parse the addresses.
+/// The sender is the one of the list the task goes out on behalf of,
+/// falling back to the newsletter's sender for whatever the list doesn't set.
+fn recipient(email_client: &EmailClient, task: &DeliveryTask) -> Result<Recipient, String> {
+    let subscriber_id = task
+        .subscriber_id
+        .ok_or_else(|| "The subscriber is no longer confirmed or left the list.".to_string())?;
+    let email = SubscriberEmail::parse(task.subscriber_email.clone())?;
+    let sender = match &task.sender_email {
+        Some(sender_email) => Mailbox::new(&SubscriberEmail::parse(sender_email.clone())?),
+        None => Mailbox::new(email_client.sender()),
+    };
+    let sender = match &task.sender_name {
+        Some(sender_name) => sender.with_name(sender_name),
+        None => sender,
+    };
+
+    let fields = json!({
+        "name": task.subscriber_name,
+        "email": task.subscriber_email,
+        "attributes": task.attributes,
+    });
+
+    Ok(Recipient {
+        email,
+        subscriber_id,
+        list_id: task.list_id,
+        sender,
+        fields,
+    })
 }
 
 /// Run worker with configuration values
 pub async fn run_worker_until_stopped(app_config: Settings) -> Result<(), anyhow::Error> {
     let db_pool = get_db_pool(&app_config.database);
     let email_client = app_config.email_client.client();
+    let worker_config = app_config.worker;
+    let application = app_config.application;
+    let listener_status = spawn_listener(db_pool.clone(), worker_config.clone());
+    let rate_limiter = worker_config.max_sends_per_second.map(RateLimiter::new);
+
+    // `FOR UPDATE SKIP LOCKED` in `dequeue_tasks` makes sure that
+    // concurrent loops never pick up the same task
+    let workers = (0..worker_config.concurrency.max(1)).map(|_| {
+        worker_loop(
+            &db_pool,
+            &email_client,
+            &application,
+            &worker_config,
+            listener_status.clone(),
+            rate_limiter.as_ref(),
+        )
+    });
+    futures::future::try_join_all(workers).await?;
 
-    worker_loop(db_pool, email_client).await
+    Ok(())
 }
 
 /// Keeps pulling from queue until it fullfills tasks
-async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyhow::Error> {
+async fn worker_loop(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    application: &ApplicationSettings,
+    worker_config: &WorkerSettings,
+    mut listener_status: watch::Receiver<ListenerStatus>,
+    rate_limiter: Option<&RateLimiter>,
+) -> Result<(), anyhow::Error> {
     loop {
-        match try_execute_task(&pool, &email_client).await {
+        if let Some(rate_limiter) = rate_limiter {
+            rate_limiter
+                .until_ready(worker_config.batch_size.max(1) as u32)
+                .await;
+        }
+        match try_execute_task(pool, email_client, application, worker_config).await {
             Ok(ExecutionOutcome::EmptyQueue) => {
-                tokio::time::sleep(Duration::from_secs(10)).await;
+                wait_for_tasks(pool, worker_config, &mut listener_status).await;
             }
             Ok(ExecutionOutcome::TaskCompleted) => {}
             Err(_) => {
@@ -55,6 +208,161 @@ async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyh
     }
 }
 
+/// Spaces out sends evenly, so that all worker loops together
+/// stay under the rate allowed by the email provider
+struct RateLimiter {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(max_per_second: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / max_per_second.max(1),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait for our turn to send `n_emails` emails
+    async fn until_ready(&self, n_emails: u32) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + self.interval * n_emails;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
+
+/// Channel on which `notify_workers` signals that tasks were pushed to `issue_delivery_queue`
+const ISSUE_DELIVERY_CHANNEL: &str = "issue_delivery";
+
+/// Wake up idle workers once the current transaction commits
+#[tracing::instrument(skip_all)]
+pub async fn notify_workers(
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), sqlx::Error> {
+    // `NOTIFY` takes no bind parameters, `pg_notify` does
+    sqlx::query("SELECT pg_notify($1, '')")
+        .bind(ISSUE_DELIVERY_CHANNEL)
+        .execute(transaction)
+        .await?;
+
+    Ok(())
+}
+
+/// Whether idle workers can rely on notifications to learn about new tasks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ListenerStatus {
+    Listening,
+    Disconnected,
+}
+
+/// Listen to `ISSUE_DELIVERY_CHANNEL` in the background.
+///
+/// Every notification (and every reconnection, since notifications may have been
+/// missed in the meantime) bumps the returned watch channel, which idle workers wait on.
+fn spawn_listener(pool: PgPool, worker_config: WorkerSettings) -> watch::Receiver<ListenerStatus> {
+    let (sender, receiver) = watch::channel(ListenerStatus::Disconnected);
+    tokio::spawn(async move {
+        loop {
+            let mut listener = match connect_listener(&pool).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to listen for new delivery tasks. Falling back to polling."
+                    );
+                    tokio::time::sleep(worker_config.fallback_poll_interval()).await;
+                    continue;
+                }
+            };
+            if sender.send(ListenerStatus::Listening).is_err() {
+                return;
+            }
+            loop {
+                match listener.try_recv().await {
+                    Ok(Some(_)) => {
+                        if sender.send(ListenerStatus::Listening).is_err() {
+                            return;
+                        }
+                    }
+                    Ok(None) | Err(_) => {
+                        tracing::warn!(
+                            "Lost the connection listening for new delivery tasks. \
+                             Falling back to polling."
+                        );
+                        if sender.send(ListenerStatus::Disconnected).is_err() {
+                            return;
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    receiver
+}
+
+async fn connect_listener(pool: &PgPool) -> Result<PgListener, sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(ISSUE_DELIVERY_CHANNEL).await?;
+
+    Ok(listener)
+}
+
+/// Block until there may be a task to execute: either a notification came in,
+/// a retry is due, or - if we can't listen for notifications - the poll interval elapsed
+async fn wait_for_tasks(
+    pool: &PgPool,
+    worker_config: &WorkerSettings,
+    listener_status: &mut watch::Receiver<ListenerStatus>,
+) {
+    let status = *listener_status.borrow();
+    let timeout = match status {
+        ListenerStatus::Listening => time_until_next_retry(pool).await.unwrap_or_else(|e| {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to look up the next scheduled retry."
+            );
+            Some(worker_config.fallback_poll_interval())
+        }),
+        ListenerStatus::Disconnected => Some(worker_config.fallback_poll_interval()),
+    };
+
+    let changed = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, listener_status.changed())
+            .await
+            .unwrap_or(Ok(())),
+        None => listener_status.changed().await,
+    };
+    // The listener task is gone, don't spin on a closed channel
+    if changed.is_err() {
+        tokio::time::sleep(worker_config.fallback_poll_interval()).await;
+    }
+}
+
+/// How long until the earliest retry scheduled in the future, if there is any
+#[tracing::instrument(skip_all)]
+async fn time_until_next_retry(pool: &PgPool) -> Result<Option<Duration>, anyhow::Error> {
+    let next_retry = sqlx::query!(
+        r#"
+        SELECT MIN(execute_after) AS next_retry
+        FROM issue_delivery_queue
+        WHERE execute_after > now()
+        "#
+    )
+    .fetch_one(pool)
+    .await?
+    .next_retry;
+
+    Ok(next_retry.map(|next_retry| (next_retry - Utc::now()).to_std().unwrap_or(Duration::ZERO)))
+}
+
 /// Completeness of tasks in queue
 pub enum ExecutionOutcome {
     TaskCompleted,
@@ -62,6 +370,7 @@ pub enum ExecutionOutcome {
 }
 
 /// Takes single task item from queue and execute(send email).
+/// With `batch_size` above 1, takes as many tasks and sends them all at once.
 #[tracing::instrument(
     skip_all,
     fields(
@@ -73,85 +382,377 @@ pub enum ExecutionOutcome {
 pub async fn try_execute_task(
     pool: &PgPool,
     email_client: &EmailClient,
+    application: &ApplicationSettings,
+    worker_config: &WorkerSettings,
 ) -> Result<ExecutionOutcome, anyhow::Error> {
-    let task = dequeue_task(pool).await?;
-    // if the queue is empty, return
-    if task.is_none() {
-        return Ok(ExecutionOutcome::EmptyQueue);
+    let batch_size = worker_config.batch_size.clamp(1, MAX_BATCH_SIZE);
+    if batch_size > 1 {
+        return try_execute_batch(pool, email_client, application, worker_config, batch_size).await;
     }
-    let (transaction, issue_id, email) = task.unwrap();
+
+    let (mut transaction, mut tasks) = dequeue_tasks(pool, 1).await?;
+    // if the queue is empty, return
+    let task = match tasks.pop() {
+        Some(task) => task,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
     // `Span::current` will send records to fields only when this block is being executed
     Span::current()
-        .record("newsletter_issue_id", &display(issue_id))
-        .record("subscriber_email", &display(&email));
-
-    match SubscriberEmail::parse(email.clone()) {
-        Ok(email) => {
-            let issue = get_issue(pool, issue_id).await?;
-            if let Err(e) = email_client
-                .send_email(
-                    &email,
-                    &issue.title,
-                    &issue.html_content,
-                    &issue.text_content,
+        .record("newsletter_issue_id", &display(task.newsletter_issue_id))
+        .record("subscriber_email", &display(&task.subscriber_email));
+
+    let outcome = if task.suppressed {
+        DeliveryOutcome::Suppressed
+    } else {
+        match recipient(email_client, &task) {
+            Ok(recipient) => {
+                let issue = get_issue(pool, task.newsletter_issue_id).await?;
+                let message = issue_message(application, &issue, &recipient);
+                match email_client.send_message(&message).await {
+                    Ok(message_id) => DeliveryOutcome::Sent { message_id },
+                    Err(e) => DeliveryOutcome::Failed {
+                        http_status: e.http_status().map(|status| status as i16),
+                        permanent: e.is_permanent(),
+                        error: e.into(),
+                    },
+                }
+            }
+            Err(error) => DeliveryOutcome::Invalid { error },
+        }
+    };
+    complete_task(&mut transaction, worker_config, &task, outcome).await?;
+    transaction.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Takes up to `batch_size` tasks from queue and sends them in a single request.
+/// Every email the provider rejects is retried or given up on by itself.
+#[tracing::instrument(skip(pool, email_client, application, worker_config), err)]
+async fn try_execute_batch(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    application: &ApplicationSettings,
+    worker_config: &WorkerSettings,
+    batch_size: usize,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let (mut transaction, tasks) = dequeue_tasks(pool, batch_size as i64).await?;
+    if tasks.is_empty() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+
+    let mut issues = HashMap::new();
+    let mut recipients = Vec::new();
+    for task in &tasks {
+        if let Entry::Vacant(entry) = issues.entry(task.newsletter_issue_id) {
+            entry.insert(get_issue(pool, task.newsletter_issue_id).await?);
+        }
+        if task.suppressed {
+            complete_task(
+                &mut transaction,
+                worker_config,
+                task,
+                DeliveryOutcome::Suppressed,
+            )
+            .await?;
+            continue;
+        }
+        match recipient(email_client, task) {
+            Ok(recipient) => recipients.push((task, recipient)),
+            Err(error) => {
+                complete_task(
+                    &mut transaction,
+                    worker_config,
+                    task,
+                    DeliveryOutcome::Invalid { error },
                 )
-                .await
-            {
-                tracing::error!(
-                   error.cause_chain = ?e,
-                   error.message = %e,
-                   "Failed to deliver issue to confirmed subscriber. \
-                    Skipping."
+                .await?
+            }
+        }
+    }
+    // Nothing left to send, the provider would reject an empty batch anyway
+    if recipients.is_empty() {
+        transaction.commit().await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+
+    let messages: Vec<_> = recipients
+        .iter()
+        .map(|(task, recipient)| {
+            let issue = &issues[&task.newsletter_issue_id];
+            issue_message(application, issue, recipient)
+        })
+        .collect();
+    let outcomes: Vec<_> = match email_client.send_batch(&messages).await {
+        Ok(results) => results
+            .into_iter()
+            .map(|result| match result {
+                Ok(message_id) => DeliveryOutcome::Sent { message_id },
+                Err(e) => DeliveryOutcome::Failed {
+                    http_status: e.http_status().map(|status| status as i16),
+                    permanent: e.is_permanent(),
+                    error: e.into(),
+                },
+            })
+            .collect(),
+        // The whole batch failed, every email in it counts as a failed attempt,
+        // given up on at once if the provider turned down the request itself
+        Err(e) => recipients
+            .iter()
+            .map(|_| DeliveryOutcome::Failed {
+                http_status: e.http_status().map(|status| status as i16),
+                permanent: e.is_permanent(),
+                error: anyhow::anyhow!(e.to_string()),
+            })
+            .collect(),
+    };
+    for ((task, _), outcome) in recipients.iter().zip(outcomes) {
+        complete_task(&mut transaction, worker_config, task, outcome).await?;
+    }
+    transaction.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// What came out of attempting a delivery
+enum DeliveryOutcome {
+    /// Accepted by the email provider
+    Sent { message_id: Option<String> },
+    /// Not accepted by the email provider. Permanent failures are not retried.
+    Failed {
+        error: anyhow::Error,
+        http_status: Option<i16>,
+        permanent: bool,
+    },
+    /// Never attempted, because a stored address is invalid
+    /// or the subscriber is no longer confirmed or left the list
+    Invalid { error: String },
+    /// Never attempted, because the address was suppressed after the task was queued
+    Suppressed,
+}
+
+/// Update the queue and the delivery ledger according to the outcome of a task.
+/// Changes are only committed along with `transaction`.
+#[tracing::instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id=%task.newsletter_issue_id,
+        subscriber_email=%task.subscriber_email
+    )
+)]
+async fn complete_task(
+    transaction: &mut PgTransaction,
+    worker_config: &WorkerSettings,
+    task: &DeliveryTask,
+    outcome: DeliveryOutcome,
+) -> Result<(), anyhow::Error> {
+    let issue_id = task.newsletter_issue_id;
+    let email = task.subscriber_email.as_str();
+    match outcome {
+        DeliveryOutcome::Sent { message_id } => {
+            record_delivery(
+                transaction,
+                issue_id,
+                email,
+                DeliveryStatus::Sent,
+                message_id.as_deref(),
+            )
+            .await?;
+            delete_task(transaction, issue_id, email).await?;
+        }
+        DeliveryOutcome::Failed {
+            error,
+            http_status,
+            permanent,
+        } => {
+            let n_attempts = task.n_attempts + 1;
+            if !permanent && n_attempts < worker_config.max_attempts {
+                tracing::warn!(
+                    error.cause_chain = ?error,
+                    error.message = %error,
+                    n_attempts,
+                    "Failed to deliver issue to confirmed subscriber. \
+                     Retrying later."
                 );
+                let delay = backoff_delay(worker_config, n_attempts);
+                retry_task(transaction, issue_id, email, n_attempts, delay).await?;
+                return Ok(());
             }
+            tracing::error!(
+               error.cause_chain = ?error,
+               error.message = %error,
+               n_attempts,
+               "Failed to deliver issue to confirmed subscriber. \
+                Moving it to the dead-letter table."
+            );
+            let failure = DeliveryFailure {
+                n_attempts,
+                last_error: error.to_string(),
+                http_status,
+            };
+            record_delivery(transaction, issue_id, email, DeliveryStatus::Failed, None).await?;
+            dead_letter_task(transaction, task, &failure).await?;
         }
-        Err(e) => {
+        DeliveryOutcome::Invalid { error } => {
             tracing::error!(
-                error.cause_chain = ?e,
-                error.message = %e,
-                "Skipping a confirmed subscriber. \
-                 Their stored contact details are invalid."
+                error.cause_chain = ?error,
+                error.message = %error,
+                "Skipping a subscriber. \
+                 They are no longer confirmed, left the list \
+                 or their stored contact details are invalid."
             );
+            let failure = DeliveryFailure {
+                n_attempts: task.n_attempts,
+                last_error: error,
+                http_status: None,
+            };
+            record_delivery(transaction, issue_id, email, DeliveryStatus::Skipped, None).await?;
+            dead_letter_task(transaction, task, &failure).await?;
+        }
+        DeliveryOutcome::Suppressed => {
+            tracing::warn!("Skipping a suppressed address.");
+            record_delivery(
+                transaction,
+                issue_id,
+                email,
+                DeliveryStatus::Suppressed,
+                None,
+            )
+            .await?;
+            delete_task(transaction, issue_id, email).await?;
         }
     }
-    delete_task(transaction, issue_id, &email).await?;
 
-    Ok(ExecutionOutcome::TaskCompleted)
+    Ok(())
+}
+
+/// Compute how long a failed delivery should wait before its next attempt.
+///
+/// The delay doubles with every attempt up to `backoff_max_milliseconds`,
+/// and half of it is randomised so that retries of a failed batch don't hit
+/// the email provider all at once.
+fn backoff_delay(worker_config: &WorkerSettings, n_attempts: i32) -> Duration {
+    let exponent = n_attempts.saturating_sub(1).clamp(0, 32) as u32;
+    let delay = worker_config
+        .backoff_base_milliseconds
+        .saturating_mul(2u64.saturating_pow(exponent))
+        .min(worker_config.backoff_max_milliseconds);
+    let jitter = rand::thread_rng().gen_range(0..=delay / 2);
+
+    Duration::from_millis(delay - delay / 2 + jitter)
 }
 
 type PgTransaction = Transaction<'static, Postgres>;
 
-#[tracing::instrument(skip_all)]
-async fn dequeue_task(
+/// A row of `issue_delivery_queue` that is ready to be executed
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_attempts: i32,
+    /// `None` when the subscriber is no longer confirmed, e.g. they unsubscribed,
+    /// or left the list the issue goes out on behalf of
+    subscriber_id: Option<Uuid>,
+    subscriber_name: Option<String>,
+    attributes: Option<Value>,
+    /// The list the issue goes out on behalf of, `None` for the newsletter's sender
+    list_id: Option<Uuid>,
+    sender_name: Option<String>,
+    sender_email: Option<String>,
+    /// Whether the address was suppressed after the task was queued
+    suppressed: bool,
+}
+
+/// Lock up to `limit` tasks that are ready to be executed.
+/// They stay locked until the returned transaction ends.
+#[tracing::instrument(skip(pool))]
+async fn dequeue_tasks(
     pool: &PgPool,
-) -> Result<Option<(PgTransaction, Uuid, String)>, anyhow::Error> {
+    limit: i64,
+) -> Result<(PgTransaction, Vec<DeliveryTask>), anyhow::Error> {
     let mut transaction = pool.begin().await?;
-    let r = sqlx::query!(
+    let tasks = sqlx::query_as!(
+        DeliveryTask,
         r#"
-        SELECT newsletter_issue_id, subscriber_email
+        SELECT
+            newsletter_issue_id,
+            subscriber_email,
+            n_attempts,
+            subscriptions.id AS "subscriber_id?",
+            subscriptions.name AS "subscriber_name?",
+            subscriptions.attributes AS "attributes?",
+            issue_delivery_queue.list_id,
+            lists.sender_name AS "sender_name?",
+            lists.sender_email AS "sender_email?",
+            EXISTS (
+                SELECT 1
+                FROM suppressions
+                WHERE suppressions.email = COALESCE(
+                    subscriptions.email_normalised,
+                    lower(issue_delivery_queue.subscriber_email)
+                )
+            ) AS "suppressed!"
         FROM issue_delivery_queue
-        FOR UPDATE
+        LEFT JOIN subscriptions ON
+            subscriptions.email = issue_delivery_queue.subscriber_email AND
+            subscriptions.status = 'confirmed' AND (
+                issue_delivery_queue.list_id IS NULL OR
+                EXISTS (
+                    SELECT 1
+                    FROM list_subscriptions
+                    WHERE
+                        list_subscriptions.subscriber_id = subscriptions.id AND
+                        list_subscriptions.list_id = issue_delivery_queue.list_id AND
+                        list_subscriptions.status = 'confirmed'
+                )
+            )
+        LEFT JOIN lists ON lists.list_id = issue_delivery_queue.list_id
+        WHERE execute_after <= now()
+        FOR UPDATE OF issue_delivery_queue
         SKIP LOCKED
-        LIMIT 1
-        "#
+        LIMIT $1
+        "#,
+        limit
     )
-    .fetch_optional(&mut transaction)
+    .fetch_all(&mut transaction)
     .await?;
 
-    if let Some(r) = r {
-        Ok(Some((
-            transaction,
-            r.newsletter_issue_id,
-            r.subscriber_email,
-        )))
-    } else {
-        Ok(None)
-    }
+    Ok((transaction, tasks))
+}
+
+/// Put the task back in the queue, to be picked up again once `delay` has passed
+#[tracing::instrument(skip_all)]
+async fn retry_task(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+    email: &str,
+    n_attempts: i32,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    let execute_after = Utc::now() + chrono::Duration::from_std(delay)?;
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_attempts = $3,
+            execute_after = $4
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        issue_id,
+        email,
+        n_attempts,
+        execute_after
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(())
 }
 
 #[tracing::instrument(skip_all)]
 async fn delete_task(
-    mut transaction: PgTransaction,
+    transaction: &mut PgTransaction,
     issue_id: Uuid,
     email: &str,
 ) -> Result<(), anyhow::Error> {
@@ -165,9 +766,183 @@ async fn delete_task(
         issue_id,
         email
     )
-    .execute(&mut transaction)
+    .execute(transaction)
     .await?;
-    transaction.commit().await?;
 
     Ok(())
 }
+
+/// State of a single delivery, as tracked in `newsletter_issue_deliveries`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    /// Waiting in `issue_delivery_queue`, possibly for a retry
+    Queued,
+    /// Accepted by the email provider
+    Sent,
+    /// Given up on after running out of attempts
+    Failed,
+    /// Never attempted, because the stored address is invalid
+    Skipped,
+    /// Never attempted, because the address is suppressed
+    Suppressed,
+    /// Sent, but the email provider reported a permanent bounce
+    Bounced,
+    /// Sent, but the email provider reported a temporary bounce
+    SoftBounced,
+    /// Sent, and the recipient marked it as spam
+    Complained,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Queued => "queued",
+            DeliveryStatus::Sent => "sent",
+            DeliveryStatus::Failed => "failed",
+            DeliveryStatus::Skipped => "skipped",
+            DeliveryStatus::Suppressed => "suppressed",
+            DeliveryStatus::Bounced => "bounced",
+            DeliveryStatus::SoftBounced => "soft_bounced",
+            DeliveryStatus::Complained => "complained",
+        }
+    }
+}
+
+/// Update the delivery ledger with the outcome of a task
+#[tracing::instrument(skip(transaction, issue_id, email))]
+async fn record_delivery(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+    email: &str,
+    status: DeliveryStatus,
+    provider_message_id: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issue_deliveries
+        SET
+            status = $3,
+            provider_message_id = $4,
+            updated_at = now()
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        issue_id,
+        email,
+        status.as_str(),
+        provider_message_id
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Why a delivery was given up on
+struct DeliveryFailure {
+    n_attempts: i32,
+    last_error: String,
+    /// Status code returned by the email provider, if it answered at all
+    http_status: Option<i16>,
+}
+
+/// Move the task from the queue to `issue_delivery_failures`,
+/// where it waits to be inspected and requeued by an admin
+#[tracing::instrument(skip_all)]
+async fn dead_letter_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    failure: &DeliveryFailure,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_failures (
+            newsletter_issue_id,
+            subscriber_email,
+            list_id,
+            n_attempts,
+            last_error,
+            http_status,
+            failed_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET
+            list_id = EXCLUDED.list_id,
+            n_attempts = EXCLUDED.n_attempts,
+            last_error = EXCLUDED.last_error,
+            http_status = EXCLUDED.http_status,
+            failed_at = EXCLUDED.failed_at
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        task.list_id,
+        failure.n_attempts,
+        failure.last_error,
+        failure.http_status
+    )
+    .execute(&mut *transaction)
+    .await?;
+    delete_task(
+        transaction,
+        task.newsletter_issue_id,
+        &task.subscriber_email,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{backoff_delay, RateLimiter},
+        crate::configuration::WorkerSettings,
+        std::time::Duration,
+        tokio::time::Instant,
+    };
+
+    fn worker_config() -> WorkerSettings {
+        WorkerSettings {
+            max_attempts: 10,
+            backoff_base_milliseconds: 1000,
+            backoff_max_milliseconds: 10000,
+            fallback_poll_interval_milliseconds: 10000,
+            concurrency: 1,
+            max_sends_per_second: None,
+            batch_size: 1,
+        }
+    }
+
+    #[test]
+    fn backoff_delay_doubles_with_every_attempt() {
+        let worker_config = worker_config();
+        for (n_attempts, max_delay) in [(1, 1000), (2, 2000), (3, 4000), (4, 8000)] {
+            let delay = backoff_delay(&worker_config, n_attempts);
+            assert!(delay >= Duration::from_millis(max_delay / 2));
+            assert!(delay <= Duration::from_millis(max_delay));
+        }
+    }
+
+    #[test]
+    fn backoff_delay_is_capped() {
+        let worker_config = worker_config();
+        for n_attempts in [5, 10, 100, i32::MAX] {
+            let delay = backoff_delay(&worker_config, n_attempts);
+            assert!(delay >= Duration::from_millis(5000));
+            assert!(delay <= Duration::from_millis(10000));
+        }
+    }
+
+    #[tokio::test]
+    async fn rate_limiter_spaces_out_sends() {
+        let rate_limiter = RateLimiter::new(10);
+        let started_at = Instant::now();
+
+        for _ in 0..6 {
+            rate_limiter.until_ready(1).await;
+        }
+
+        // The first send goes out right away, the five others wait 100ms each
+        assert!(started_at.elapsed() >= Duration::from_millis(500));
+    }
+}
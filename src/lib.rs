@@ -1,4 +1,5 @@
 pub mod authentication;
+pub mod bounces;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
@@ -7,5 +8,10 @@ pub mod issue_delivery_worker;
 pub mod routes;
 pub mod session_state;
 pub mod startup;
+pub mod subscriber_data;
+pub mod subscriber_export;
+pub mod subscriber_import;
+pub mod subscription_sweeper;
+pub mod suppressions;
 pub mod telemetry;
 pub mod utils;
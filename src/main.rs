@@ -3,6 +3,7 @@ use {
         configuration::get_config,
         issue_delivery_worker::run_worker_until_stopped,
         startup::Application,
+        subscription_sweeper::run_sweeper_until_stopped,
         telemetry::{get_subscriber, init_subscriber},
     },
     std::fmt::{Debug, Display},
@@ -19,13 +20,15 @@ async fn main() -> anyhow::Result<()> {
 
     // Create tasks to be in separate threads
     let app_task = tokio::spawn(main_app.run_until_stopped());
-    let worker_task = tokio::spawn(run_worker_until_stopped(app_config));
+    let worker_task = tokio::spawn(run_worker_until_stopped(app_config.clone()));
+    let sweeper_task = tokio::spawn(run_sweeper_until_stopped(app_config));
 
-    // Run both tasks concurrently / in parallel
-    // this will run until one of the two tasks completes or errors out
+    // Run all tasks concurrently / in parallel
+    // this will run until one of the tasks completes or errors out
     tokio::select! {
         api = app_task => report_exit("API", api),
         background = worker_task => report_exit("Background worker", background),
+        sweeper = sweeper_task => report_exit("Subscription sweeper", sweeper),
     };
 
     Ok(())
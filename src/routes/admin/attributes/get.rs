@@ -0,0 +1,102 @@
+use {
+    crate::utils::e500,
+    actix_web::{http::header::ContentType, web, HttpResponse},
+    actix_web_flash_messages::IncomingFlashMessages,
+    anyhow::Context,
+    htmlescape::encode_minimal,
+    sqlx::PgPool,
+    std::fmt::Write,
+};
+
+struct AttributeCount {
+    key: String,
+    n_subscribers: i64,
+}
+
+/// List the attributes in use, along with a form to set one on a subscriber
+pub async fn attributes_form(
+    flash_message: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_message.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let mut attributes_html = String::new();
+    for attribute in get_attribute_counts(&pool).await.map_err(e500)? {
+        writeln!(
+            attributes_html,
+            "<li><b>{}</b>: {} subscribers</li>",
+            encode_minimal(&attribute.key),
+            attribute.n_subscribers
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Attributes</title>
+    </head>
+    <body>
+        {msg_html}
+        <ul>
+            {attributes_html}
+        </ul>
+        <p>Issues can use attributes as <code>{{{{ attributes.company }}}}</code>.</p>
+        <form action="/admin/attributes" method="post">
+            <label>Subscriber email
+                <input
+                    type="email"
+                    placeholder="Enter the subscriber's email address"
+                    name="email"
+                >
+            </label>
+            <br>
+            <label>Attribute
+                <input
+                    type="text"
+                    placeholder="e.g. company"
+                    name="key"
+                >
+            </label>
+            <br>
+            <label>Value
+                <input
+                    type="text"
+                    placeholder="Leave empty to remove the attribute"
+                    name="value"
+                >
+            </label>
+            <br>
+            <button type="submit">Set attribute</button>
+        </form>
+        <p><a href="/admin/dashboard">&lt;- Back</a></p>
+    </body>
+</html>
+    "#
+        )))
+}
+
+#[tracing::instrument(name = "Get attribute counts", skip(pool))]
+async fn get_attribute_counts(pool: &PgPool) -> Result<Vec<AttributeCount>, anyhow::Error> {
+    let attributes = sqlx::query_as!(
+        AttributeCount,
+        r#"
+        SELECT key AS "key!", COUNT(*) AS "n_subscribers!"
+        FROM subscriptions, jsonb_object_keys(attributes) AS key
+        GROUP BY key
+        ORDER BY key
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the attributes.")?;
+
+    Ok(attributes)
+}
@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::attributes_form;
+pub use post::set_attribute;
@@ -0,0 +1,111 @@
+use {
+    crate::{
+        domain::{is_identifier, SubscriberEmail},
+        utils::{e500, see_other},
+    },
+    actix_web::{web, HttpResponse},
+    actix_web_flash_messages::FlashMessage,
+    anyhow::Context,
+    htmlescape::encode_minimal,
+    sqlx::PgPool,
+};
+
+/// Longest attribute key accepted
+const MAX_KEY_LENGTH: usize = 64;
+/// Longest attribute value accepted
+const MAX_VALUE_LENGTH: usize = 1024;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    email: String,
+    key: String,
+    /// An empty value removes the attribute
+    value: String,
+}
+
+/// Set an attribute of a subscriber, or remove it
+#[tracing::instrument(
+    name = "Set a subscriber attribute",
+    skip(form, pool),
+    fields(email = %form.email, key = %form.key)
+)]
+pub async fn set_attribute(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let FormData { email, key, value } = form.into_inner();
+    let email = match SubscriberEmail::parse(email) {
+        Ok(email) => email,
+        Err(e) => {
+            FlashMessage::error(encode_minimal(&e)).send();
+            return Ok(see_other("/admin/attributes"));
+        }
+    };
+    let key = key.trim();
+    if key.len() > MAX_KEY_LENGTH || !is_identifier(key) {
+        FlashMessage::error(format!(
+            "Attributes are named with up to {} letters, digits and underscores, \
+             not starting with a digit.",
+            MAX_KEY_LENGTH
+        ))
+        .send();
+        return Ok(see_other("/admin/attributes"));
+    }
+    if value.chars().count() > MAX_VALUE_LENGTH {
+        FlashMessage::error(format!(
+            "Attribute values can't be longer than {} chars.",
+            MAX_VALUE_LENGTH
+        ))
+        .send();
+        return Ok(see_other("/admin/attributes"));
+    }
+
+    let value = Some(value.trim()).filter(|value| !value.is_empty());
+    if !update_attribute(&pool, &email, key, value)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error(format!(
+            "There is no subscriber {}.",
+            encode_minimal(email.as_ref())
+        ))
+        .send();
+        return Ok(see_other("/admin/attributes"));
+    }
+    FlashMessage::info(format!(
+        "The attributes of {} have been updated.",
+        encode_minimal(email.as_ref())
+    ))
+    .send();
+
+    Ok(see_other("/admin/attributes"))
+}
+
+/// Set the attribute to `value`, or remove it if there is none.
+/// Returns `false` if there is no such subscriber.
+#[tracing::instrument(skip(pool))]
+async fn update_attribute(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+    key: &str,
+    value: Option<&str>,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET attributes = CASE
+            WHEN $3::text IS NULL THEN attributes - $2
+            ELSE attributes || jsonb_build_object($2::text, $3::text)
+        END
+        WHERE email_normalised = $1
+        "#,
+        email.normalised(),
+        key,
+        value
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update the subscriber's attributes.")?;
+
+    Ok(result.rows_affected() == 1)
+}
@@ -28,6 +28,13 @@ pub async fn admin_dashboard(
             <p>Available actions:</p>
             <ol>
                 <li><a href="/admin/newsletter">Send a newsletter issue</a></li>
+                <li><a href="/admin/lists">Manage mailing lists</a></li>
+                <li><a href="/admin/topics">Manage topics</a></li>
+                <li><a href="/admin/import">Import subscribers</a></li>
+                <li><a href="/admin/subscribers">Export subscribers</a></li>
+                <li><a href="/admin/tags">Tag subscribers</a></li>
+                <li><a href="/admin/attributes">Set subscriber attributes</a></li>
+                <li><a href="/admin/suppressions">Manage suppressed addresses</a></li>
                 <li><a href="/admin/password">Change password</a></li>
                 <li>
                     <form name="logoutForm" action="/admin/logout" method="post">
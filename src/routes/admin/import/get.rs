@@ -0,0 +1,95 @@
+use {
+    crate::{routes::get_lists, utils::e500},
+    actix_web::{http::header::ContentType, web, HttpResponse},
+    actix_web_flash_messages::IncomingFlashMessages,
+    htmlescape::encode_minimal,
+    sqlx::PgPool,
+    std::fmt::Write,
+};
+
+/// Form to import subscribers from a CSV export, e.g. from another newsletter platform
+pub async fn import_form(
+    flash_message: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_message.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let mut lists_html = String::new();
+    for list in get_lists(&pool).await.map_err(e500)? {
+        writeln!(
+            lists_html,
+            r#"<option value="{}">{}</option>"#,
+            encode_minimal(&list.slug),
+            encode_minimal(&list.name)
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Import subscribers</title>
+    </head>
+    <body>
+        {msg_html}
+        <form action="/admin/import" method="post">
+            <label>CSV
+                <textarea
+                    placeholder="Paste the CSV export, starting with its header row"
+                    name="csv"
+                    rows="20"
+                    cols="80"
+                ></textarea>
+            </label>
+            <br>
+            <label>Email column
+                <input type="text" name="email_column" value="Email Address">
+            </label>
+            <br>
+            <label>Name columns
+                <input
+                    type="text"
+                    placeholder="Joined with spaces"
+                    name="name_columns"
+                    value="First Name, Last Name"
+                >
+            </label>
+            <br>
+            <label>Attribute columns
+                <input
+                    type="text"
+                    placeholder="e.g. company=Company, plan=Plan"
+                    name="attribute_columns"
+                >
+            </label>
+            <br>
+            <label>List
+                <select name="list">
+                    {lists_html}
+                </select>
+            </label>
+            <br>
+            <label>
+                <input type="radio" name="status" value="pending_confirmation" checked>
+                Send them a confirmation email
+            </label>
+            <label>
+                <input type="radio" name="status" value="confirmed">
+                They already confirmed their subscription
+            </label>
+            <br>
+            <button type="submit">Import</button>
+        </form>
+        <p><a href="/admin/dashboard">&lt;- Back</a></p>
+    </body>
+</html>
+    "#
+        )))
+}
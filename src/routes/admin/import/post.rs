@@ -0,0 +1,144 @@
+use {
+    crate::{
+        configuration::SubscriptionSettings,
+        email_client::EmailClient,
+        routes::get_list_by_slug,
+        startup::{ApplicationBaseUrl, HmacSecret},
+        subscriber_import::{
+            ColumnMapping, ImportReport, ImportStatus, RowError, SubscriberImport,
+        },
+        utils::{e500, see_other},
+    },
+    actix_web::{http::header::ContentType, web, HttpResponse},
+    actix_web_flash_messages::FlashMessage,
+    anyhow::Context,
+    htmlescape::encode_minimal,
+    sqlx::PgPool,
+    std::fmt::Write,
+};
+
+/// Largest import form accepted, in bytes. The CSV is sent url-encoded,
+/// which can take up to three times its own size.
+pub const IMPORT_FORM_LIMIT: usize = 32 * 1024 * 1024;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    csv: String,
+    email_column: String,
+    /// Separated by commas
+    name_columns: String,
+    /// `key=Header` pairs, separated by commas
+    attribute_columns: String,
+    list: String,
+    status: ImportStatus,
+}
+
+/// Import the subscribers of a CSV export and report what happened to every row
+#[tracing::instrument(
+    name = "Import subscribers from CSV",
+    skip_all,
+    fields(list = %form.list, status = form.status.as_str())
+)]
+pub async fn import_subscribers(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    subscription_config: web::Data<SubscriptionSettings>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form = form.into_inner();
+    let import = ColumnMapping::parse(
+        &form.email_column,
+        &form.name_columns,
+        &form.attribute_columns,
+    )
+    .and_then(|mapping| SubscriberImport::parse(&form.csv, &mapping));
+    let import = match import {
+        Ok(import) => import,
+        Err(e) => {
+            FlashMessage::error(encode_minimal(&e)).send();
+            return Ok(see_other("/admin/import"));
+        }
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let list = match get_list_by_slug(&mut transaction, &form.list)
+        .await
+        .map_err(e500)?
+    {
+        Some(list) => list,
+        None => {
+            FlashMessage::error(format!("There is no list {}.", encode_minimal(&form.list))).send();
+            return Ok(see_other("/admin/import"));
+        }
+    };
+    transaction.rollback().await.map_err(e500)?;
+
+    let report = import
+        .run(
+            &pool,
+            &email_client,
+            &base_url.0,
+            subscription_config.confirmation_token_expires_at(),
+            &list,
+            form.status,
+            &secret.0,
+        )
+        .await
+        .map_err(e500)?;
+
+    Ok(report_page(&report))
+}
+
+fn report_page(report: &ImportReport) -> HttpResponse {
+    let rows_html = |rows: &[RowError]| {
+        let mut html = String::new();
+        for row in rows {
+            writeln!(
+                html,
+                "<li>Line {}: {}</li>",
+                row.line,
+                encode_minimal(&row.message)
+            )
+            .unwrap();
+        }
+        html
+    };
+    let n_imported = report.n_imported;
+    let n_skipped = report.skipped.len();
+    let n_errors = report.errors.len();
+    let skipped_html = rows_html(&report.skipped);
+    let errors_html = rows_html(&report.errors);
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Import report</title>
+    </head>
+    <body>
+        <p>Imported {n_imported} subscribers.</p>
+        <p>Skipped {n_skipped} rows:</p>
+        <ul>
+            {skipped_html}
+        </ul>
+        <p>{n_errors} rows have errors:</p>
+        <ul>
+            {errors_html}
+        </ul>
+        <p><a href="/admin/import">Import more subscribers</a></p>
+        <p><a href="/admin/dashboard">&lt;- Back</a></p>
+    </body>
+</html>
+    "#
+        ))
+}
@@ -0,0 +1,198 @@
+use {
+    crate::utils::e500,
+    actix_web::{http::header::ContentType, web, HttpResponse},
+    anyhow::Context,
+    chrono::{DateTime, Utc},
+    htmlescape::encode_minimal,
+    sqlx::PgPool,
+    uuid::Uuid,
+};
+
+#[derive(serde::Deserialize)]
+pub struct QueryParams {
+    subscriber_email: Option<String>,
+}
+
+struct DeliveryTotals {
+    total: i64,
+    queued: i64,
+    sent: i64,
+    failed: i64,
+    skipped: i64,
+    suppressed: i64,
+    bounced: i64,
+    soft_bounced: i64,
+    complained: i64,
+}
+
+struct Delivery {
+    status: String,
+    provider_message_id: Option<String>,
+    queued_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+/// Show how far the delivery of an issue has gotten,
+/// optionally looking up what happened to a single subscriber
+pub async fn issue_progress(
+    issue_id: web::Path<Uuid>,
+    query: web::Query<QueryParams>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let title = match get_issue_title(&pool, issue_id).await.map_err(e500)? {
+        Some(title) => title,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let DeliveryTotals {
+        total,
+        queued,
+        sent,
+        failed,
+        skipped,
+        suppressed,
+        bounced,
+        soft_bounced,
+        complained,
+    } = get_delivery_totals(&pool, issue_id).await.map_err(e500)?;
+    let processed = sent + failed + skipped + suppressed + bounced + soft_bounced + complained;
+
+    let lookup_html = match &query.subscriber_email {
+        None => String::new(),
+        Some(subscriber_email) => {
+            let delivery = get_delivery(&pool, issue_id, subscriber_email)
+                .await
+                .map_err(e500)?;
+            let subscriber_email = encode_minimal(subscriber_email);
+            match delivery {
+                None => format!("<p>No delivery of this issue to {subscriber_email}.</p>"),
+                Some(delivery) => format!(
+                    "<p>Delivery to {subscriber_email}: {} \
+                     (provider message id: {}, queued at {}, last updated at {})</p>",
+                    delivery.status,
+                    encode_minimal(delivery.provider_message_id.as_deref().unwrap_or("-")),
+                    delivery.queued_at.to_rfc3339(),
+                    delivery.updated_at.to_rfc3339(),
+                ),
+            }
+        }
+    };
+    let title = encode_minimal(&title);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Issue delivery progress</title>
+    </head>
+    <body>
+        <h1>Delivery of "{title}"</h1>
+        <progress value="{processed}" max="{total}"></progress>
+        <p>{processed} of {total} deliveries processed.</p>
+        <ul>
+            <li>Queued: {queued}</li>
+            <li>Sent: {sent}</li>
+            <li>Failed: {failed}</li>
+            <li>Skipped: {skipped}</li>
+            <li>Suppressed: {suppressed}</li>
+            <li>Bounced: {bounced}</li>
+            <li>Soft bounced: {soft_bounced}</li>
+            <li>Marked as spam: {complained}</li>
+        </ul>
+        <p><a href="/admin/newsletter/{issue_id}/failures">See failed deliveries</a></p>
+        <form action="/admin/issues/{issue_id}" method="get">
+            <label>Look up a subscriber
+                <input
+                    type="text"
+                    placeholder="Enter subscriber email"
+                    name="subscriber_email"
+                >
+            </label>
+            <button type="submit">Look up</button>
+        </form>
+        {lookup_html}
+        <p><a href="/admin/dashboard">&lt;- Back</a></p>
+    </body>
+</html>
+    "#
+        )))
+}
+
+#[tracing::instrument(name = "Get issue title", skip(pool))]
+pub async fn get_issue_title(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Option<String>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT title
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the newsletter issue.")?;
+
+    Ok(row.map(|r| r.title))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_delivery_totals(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<DeliveryTotals, anyhow::Error> {
+    let totals = sqlx::query_as!(
+        DeliveryTotals,
+        r#"
+        SELECT
+            COUNT(*) AS "total!",
+            COUNT(*) FILTER (WHERE status = 'queued') AS "queued!",
+            COUNT(*) FILTER (WHERE status = 'sent') AS "sent!",
+            COUNT(*) FILTER (WHERE status = 'failed') AS "failed!",
+            COUNT(*) FILTER (WHERE status = 'skipped') AS "skipped!",
+            COUNT(*) FILTER (WHERE status = 'suppressed') AS "suppressed!",
+            COUNT(*) FILTER (WHERE status = 'bounced') AS "bounced!",
+            COUNT(*) FILTER (WHERE status = 'soft_bounced') AS "soft_bounced!",
+            COUNT(*) FILTER (WHERE status = 'complained') AS "complained!"
+        FROM newsletter_issue_deliveries
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count the deliveries of the newsletter issue.")?;
+
+    Ok(totals)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_delivery(
+    pool: &PgPool,
+    issue_id: Uuid,
+    subscriber_email: &str,
+) -> Result<Option<Delivery>, anyhow::Error> {
+    let delivery = sqlx::query_as!(
+        Delivery,
+        r#"
+        SELECT status, provider_message_id, queued_at, updated_at
+        FROM newsletter_issue_deliveries
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        issue_id,
+        subscriber_email
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the delivery.")?;
+
+    Ok(delivery)
+}
@@ -0,0 +1,147 @@
+use {
+    crate::utils::e500,
+    actix_web::{http::header::ContentType, web, HttpResponse},
+    actix_web_flash_messages::IncomingFlashMessages,
+    anyhow::Context,
+    htmlescape::encode_minimal,
+    sqlx::{PgPool, Postgres, Transaction},
+    std::fmt::Write,
+    uuid::Uuid,
+};
+
+/// Slug of the list everyone subscribed to before lists existed,
+/// used whenever no list is given
+pub const DEFAULT_LIST: &str = "default";
+
+/// A newsletter of its own, with its own subscribers and sender
+pub struct List {
+    pub list_id: Uuid,
+    pub slug: String,
+    pub name: String,
+    pub sender_name: Option<String>,
+    pub sender_email: Option<String>,
+}
+
+/// List the existing mailing lists, along with a form to add a new one
+pub async fn lists_form(
+    flash_message: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_message.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let mut lists_html = String::new();
+    for list in get_lists(&pool).await.map_err(e500)? {
+        let sender = match (&list.sender_name, &list.sender_email) {
+            (None, None) => "default sender".to_string(),
+            (name, email) => format!(
+                "{} &lt;{}&gt;",
+                encode_minimal(name.as_deref().unwrap_or_default()),
+                encode_minimal(email.as_deref().unwrap_or("default address"))
+            ),
+        };
+        writeln!(
+            lists_html,
+            "<li><b>{}</b> ({}), sent by {}</li>",
+            encode_minimal(&list.name),
+            encode_minimal(&list.slug),
+            sender
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Mailing lists</title>
+    </head>
+    <body>
+        {msg_html}
+        <ul>
+            {lists_html}
+        </ul>
+        <form action="/admin/lists" method="post">
+            <label>Slug
+                <input
+                    type="text"
+                    placeholder="Used in subscription forms"
+                    name="slug"
+                >
+            </label>
+            <br>
+            <label>Name
+                <input
+                    type="text"
+                    placeholder="Enter the list's name"
+                    name="name"
+                >
+            </label>
+            <br>
+            <label>Sender name
+                <input
+                    type="text"
+                    placeholder="Leave empty for the default"
+                    name="sender_name"
+                >
+            </label>
+            <br>
+            <label>Sender email
+                <input
+                    type="email"
+                    placeholder="Leave empty for the default"
+                    name="sender_email"
+                >
+            </label>
+            <br>
+            <button type="submit">Add list</button>
+        </form>
+        <p><a href="/admin/dashboard">&lt;- Back</a></p>
+    </body>
+</html>
+    "#
+        )))
+}
+
+#[tracing::instrument(name = "Get lists", skip(pool))]
+pub async fn get_lists(pool: &PgPool) -> Result<Vec<List>, anyhow::Error> {
+    let lists = sqlx::query_as!(
+        List,
+        r#"
+        SELECT list_id, slug, name, sender_name, sender_email
+        FROM lists
+        ORDER BY created_at, slug
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the lists.")?;
+
+    Ok(lists)
+}
+
+#[tracing::instrument(name = "Get list by slug", skip(transaction))]
+pub async fn get_list_by_slug(
+    transaction: &mut Transaction<'_, Postgres>,
+    slug: &str,
+) -> Result<Option<List>, anyhow::Error> {
+    let list = sqlx::query_as!(
+        List,
+        r#"
+        SELECT list_id, slug, name, sender_name, sender_email
+        FROM lists
+        WHERE slug = $1
+        "#,
+        slug
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to retrieve the list.")?;
+
+    Ok(list)
+}
@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::{get_list_by_slug, get_lists, lists_form, List, DEFAULT_LIST};
+pub use post::add_list;
@@ -0,0 +1,101 @@
+use {
+    crate::{
+        domain::SubscriberEmail,
+        utils::{e500, see_other},
+    },
+    actix_web::{web, HttpResponse},
+    actix_web_flash_messages::FlashMessage,
+    anyhow::Context,
+    sqlx::PgPool,
+    uuid::Uuid,
+};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    slug: String,
+    name: String,
+    sender_name: String,
+    sender_email: String,
+}
+
+/// Add a mailing list subscribers can join and issues can be published to
+#[tracing::instrument(name = "Add a list", skip(form, pool), fields(slug = %form.slug))]
+pub async fn add_list(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let slug = form.slug.trim();
+    let is_valid_slug = !slug.is_empty()
+        && slug.len() <= 64
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !is_valid_slug {
+        FlashMessage::error(
+            "List slugs should be between 1 and 64 lowercase letters, digits or dashes.",
+        )
+        .send();
+        return Ok(see_other("/admin/lists"));
+    }
+    let name = form.name.trim();
+    if name.is_empty() || name.chars().count() > 256 {
+        FlashMessage::error("List names should be between 1 and 256 chars long.").send();
+        return Ok(see_other("/admin/lists"));
+    }
+    let sender_name = Some(form.sender_name.trim()).filter(|name| !name.is_empty());
+    let sender_email = match form.sender_email.trim() {
+        "" => None,
+        sender_email => match SubscriberEmail::parse(sender_email.to_string()) {
+            Ok(sender_email) => Some(sender_email),
+            Err(e) => {
+                FlashMessage::error(e).send();
+                return Ok(see_other("/admin/lists"));
+            }
+        },
+    };
+
+    let inserted = insert_list(
+        &pool,
+        slug,
+        name,
+        sender_name,
+        sender_email.as_ref().map(|e| e.as_ref()),
+    )
+    .await
+    .map_err(e500)?;
+    if inserted {
+        FlashMessage::info(format!("The list {} has been added.", slug)).send();
+    } else {
+        FlashMessage::error(format!("The list {} already exists.", slug)).send();
+    }
+
+    Ok(see_other("/admin/lists"))
+}
+
+/// Insert the list, returning whether its slug wasn't taken yet
+#[tracing::instrument(skip(pool))]
+async fn insert_list(
+    pool: &PgPool,
+    slug: &str,
+    name: &str,
+    sender_name: Option<&str>,
+    sender_email: Option<&str>,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO lists (list_id, slug, name, sender_name, sender_email, created_at)
+        VALUES ($1, $2, $3, $4, $5, now())
+        ON CONFLICT DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        slug,
+        name,
+        sender_name,
+        sender_email
+    )
+    .execute(pool)
+    .await
+    .context("Failed to insert the list.")?;
+
+    Ok(result.rows_affected() > 0)
+}
@@ -1,9 +1,25 @@
+mod attributes;
 mod dashboard;
+mod import;
+mod issues;
+mod lists;
 mod logout;
 mod newsletter;
 mod password;
+mod subscribers;
+mod suppressions;
+mod tags;
+mod topics;
 
+pub use attributes::*;
 pub use dashboard::admin_dashboard;
+pub use import::*;
+pub use issues::*;
+pub use lists::*;
 pub use logout::*;
 pub use newsletter::*;
 pub use password::*;
+pub use subscribers::*;
+pub use suppressions::*;
+pub use tags::*;
+pub use topics::*;
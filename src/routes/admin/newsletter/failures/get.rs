@@ -0,0 +1,111 @@
+use {
+    crate::{routes::admin::issues::get_issue_title, utils::e500},
+    actix_web::{http::header::ContentType, web, HttpResponse},
+    actix_web_flash_messages::IncomingFlashMessages,
+    anyhow::Context,
+    chrono::{DateTime, Utc},
+    htmlescape::encode_minimal,
+    sqlx::PgPool,
+    std::fmt::Write,
+    uuid::Uuid,
+};
+
+struct DeliveryFailure {
+    subscriber_email: String,
+    n_attempts: i32,
+    last_error: String,
+    http_status: Option<i16>,
+    failed_at: DateTime<Utc>,
+}
+
+/// List the deliveries of an issue that have been given up on
+pub async fn delivery_failures(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    flash_message: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let title = match get_issue_title(&pool, issue_id).await.map_err(e500)? {
+        Some(title) => title,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let failures = get_delivery_failures(&pool, issue_id).await.map_err(e500)?;
+
+    let mut msg_html = String::new();
+    for m in flash_message.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let mut rows_html = String::new();
+    for failure in &failures {
+        writeln!(
+            rows_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            encode_minimal(&failure.subscriber_email),
+            failure.n_attempts,
+            failure
+                .http_status
+                .map(|status| status.to_string())
+                .unwrap_or_else(|| "-".into()),
+            encode_minimal(&failure.last_error),
+            failure.failed_at.to_rfc3339(),
+        )
+        .unwrap();
+    }
+    let title = encode_minimal(&title);
+    let n_failures = failures.len();
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Failed deliveries</title>
+    </head>
+    <body>
+        {msg_html}
+        <h1>Failed deliveries of "{title}"</h1>
+        <p>{n_failures} deliveries have been given up on.</p>
+        <table>
+            <tr>
+                <th>Subscriber</th>
+                <th>Attempts</th>
+                <th>HTTP status</th>
+                <th>Last error</th>
+                <th>Failed at</th>
+            </tr>
+            {rows_html}
+        </table>
+        <form action="/admin/newsletter/{issue_id}/failures/requeue" method="post">
+            <button type="submit">Requeue all</button>
+        </form>
+        <p><a href="/admin/dashboard">&lt;- Back</a></p>
+    </body>
+</html>
+    "#
+        )))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_delivery_failures(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Vec<DeliveryFailure>, anyhow::Error> {
+    let failures = sqlx::query_as!(
+        DeliveryFailure,
+        r#"
+        SELECT subscriber_email, n_attempts, last_error, http_status, failed_at
+        FROM issue_delivery_failures
+        WHERE newsletter_issue_id = $1
+        ORDER BY failed_at
+        "#,
+        issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve failed deliveries.")?;
+
+    Ok(failures)
+}
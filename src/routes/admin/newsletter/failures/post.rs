@@ -0,0 +1,95 @@
+use {
+    crate::{
+        issue_delivery_worker::notify_workers,
+        utils::{e500, see_other},
+    },
+    actix_web::{web, HttpResponse},
+    actix_web_flash_messages::FlashMessage,
+    anyhow::Context,
+    sqlx::{PgPool, Postgres, Transaction},
+    uuid::Uuid,
+};
+
+/// Push every failed delivery of an issue back into `issue_delivery_queue`
+#[tracing::instrument(name = "Requeue failed deliveries", skip(pool))]
+pub async fn requeue_delivery_failures(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let n_requeued = requeue_failed_tasks(&mut transaction, issue_id)
+        .await
+        .context("Failed to requeue failed deliveries")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to requeue failed deliveries.")
+        .map_err(e500)?;
+
+    FlashMessage::info(format!(
+        "{n_requeued} failed deliveries have been requeued."
+    ))
+    .send();
+    Ok(see_other(&format!("/admin/newsletter/{issue_id}/failures")))
+}
+
+/// Move rows from `issue_delivery_failures` back to `issue_delivery_queue`,
+/// with a fresh retry budget, and mark them as `queued` again in the delivery ledger
+#[tracing::instrument(skip(transaction))]
+async fn requeue_failed_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<u64, sqlx::Error> {
+    let n_requeued = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id, subscriber_email, list_id
+        )
+        SELECT newsletter_issue_id, subscriber_email, list_id
+        FROM issue_delivery_failures
+        WHERE newsletter_issue_id = $1
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issue_deliveries
+        SET
+            status = 'queued',
+            provider_message_id = NULL,
+            updated_at = now()
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email IN (
+                SELECT subscriber_email
+                FROM issue_delivery_failures
+                WHERE newsletter_issue_id = $1
+            )
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_failures
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    notify_workers(transaction).await?;
+
+    Ok(n_requeued)
+}
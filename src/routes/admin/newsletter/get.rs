@@ -1,17 +1,49 @@
 use {
-    actix_web::{http::header::ContentType, HttpResponse},
+    crate::{
+        routes::{get_lists, get_topics, DEFAULT_LIST},
+        utils::e500,
+    },
+    actix_web::{http::header::ContentType, web, HttpResponse},
     actix_web_flash_messages::IncomingFlashMessages,
+    htmlescape::encode_minimal,
+    sqlx::PgPool,
     std::fmt::Write,
 };
 
 pub async fn publish_issue_form(
     flash_message: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let mut msg_html = String::new();
     let idempotency_key = uuid::Uuid::new_v4();
     for m in flash_message.iter() {
         writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
     }
+    let mut lists_html = String::new();
+    for list in get_lists(&pool).await.map_err(e500)? {
+        let checked = if list.slug == DEFAULT_LIST {
+            "checked"
+        } else {
+            ""
+        };
+        writeln!(
+            lists_html,
+            r#"<label><input type="checkbox" name="list" value="{}" {}>{}</label><br>"#,
+            encode_minimal(&list.slug),
+            checked,
+            encode_minimal(&list.name)
+        )
+        .unwrap();
+    }
+    let mut topics_html = String::new();
+    for topic in get_topics(&pool).await.map_err(e500)? {
+        writeln!(
+            topics_html,
+            r#"<option value="{0}">{0}</option>"#,
+            encode_minimal(&topic.name)
+        )
+        .unwrap();
+    }
 
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
@@ -52,6 +84,25 @@ pub async fn publish_issue_form(
                 />
             </label>
             <br>
+            <fieldset>
+                <legend>Send to</legend>
+                {lists_html}
+            </fieldset>
+            <label>Segment:<br>
+                <input
+                    type="text"
+                    placeholder="e.g. beta and not region:eu, empty for everyone"
+                    name="segment"
+                />
+            </label>
+            <br>
+            <label>Topic:<br>
+                <select name="topic">
+                    <option value="">None, for every subscriber</option>
+                    {topics_html}
+                </select>
+            </label>
+            <br>
             <input hidden type="text" name="idempotency_key" value="{idempotency_key}" />
             <button type="submit">Submit</button>
         </form>
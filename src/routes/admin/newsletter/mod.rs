@@ -1,5 +1,7 @@
+mod failures;
 mod get;
 mod post;
 
+pub use failures::*;
 pub use get::publish_issue_form;
 pub use post::publish_issue;
@@ -1,24 +1,62 @@
 use {
     crate::{
         authentication::UserId,
+        domain::{Segment, Template},
         idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+        issue_delivery_worker::notify_workers,
+        routes::DEFAULT_LIST,
         utils::{e400, e500, see_other},
     },
     actix_web::{web, HttpResponse},
     actix_web_flash_messages::FlashMessage,
     anyhow::Context,
+    htmlescape::encode_minimal,
     sqlx::{PgPool, Postgres, Transaction},
     uuid::Uuid,
 };
 
-#[derive(serde::Deserialize)]
+/// Fields of the publish form.
+/// Every checked list comes as its own `list` field, so the form is read as a list of pairs.
 pub struct FormData {
     title: String,
     text_content: String,
     html_content: String,
+    /// Only subscribers who didn't opt out of the topic receive the issue
+    topic: Option<String>,
+    /// Slugs of the lists to send the issue to, in order of preference
+    lists: Vec<String>,
+    /// Only subscribers whose tags match the segment receive the issue
+    segment: Option<String>,
     idempotency_key: String,
 }
 
+impl From<Vec<(String, String)>> for FormData {
+    fn from(pairs: Vec<(String, String)>) -> Self {
+        let mut form = FormData {
+            title: String::new(),
+            text_content: String::new(),
+            html_content: String::new(),
+            topic: None,
+            lists: Vec::new(),
+            segment: None,
+            idempotency_key: String::new(),
+        };
+        for (key, value) in pairs {
+            match key.as_str() {
+                "title" => form.title = value,
+                "text_content" => form.text_content = value,
+                "html_content" => form.html_content = value,
+                "topic" => form.topic = Some(value),
+                "list" if !form.lists.contains(&value) => form.lists.push(value),
+                "segment" => form.segment = Some(value),
+                "idempotency_key" => form.idempotency_key = value,
+                _ => {}
+            }
+        }
+        form
+    }
+}
+
 fn success_message() -> FlashMessage {
     FlashMessage::info(
         "The newsletter issue has been accepted - \
@@ -26,14 +64,14 @@ fn success_message() -> FlashMessage {
     )
 }
 
-/// Publish a newsletter issue to subscribed users
+/// Publish a newsletter issue to the subscribers of the chosen lists
 #[tracing::instrument(
     name = "Publish a newsletter issue",
     skip_all,
     fields(user_id=%&*user_id)
 )]
 pub async fn publish_issue(
-    form: web::Form<FormData>,
+    form: web::Form<Vec<(String, String)>>,
     user_id: web::ReqData<UserId>,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, actix_web::Error> {
@@ -42,8 +80,41 @@ pub async fn publish_issue(
         title,
         text_content,
         html_content,
+        topic,
+        mut lists,
+        segment,
         idempotency_key,
-    } = form.0;
+    } = FormData::from(form.into_inner());
+    // The form sends an empty topic when the issue is for every subscriber
+    let topic = topic.filter(|topic| !topic.is_empty());
+    if lists.is_empty() {
+        lists.push(DEFAULT_LIST.into());
+    }
+    // Likewise, an empty segment means every subscriber of the lists
+    let segment = match segment.filter(|segment| !segment.trim().is_empty()) {
+        Some(segment) => match Segment::parse(&segment) {
+            Ok(parsed) => Some((segment, parsed)),
+            Err(e) => {
+                FlashMessage::error(encode_minimal(&e)).send();
+                return Ok(see_other("/admin/newsletter"));
+            }
+        },
+        None => None,
+    };
+    let attribute_keys = get_attribute_keys(&pool)
+        .await
+        .context("Failed to retrieve the subscribers' attributes")
+        .map_err(e500)?;
+    for (part, content) in [
+        ("title", &title),
+        ("text content", &text_content),
+        ("HTML content", &html_content),
+    ] {
+        if let Err(e) = Template::parse(content).and_then(|t| t.validate(&attribute_keys)) {
+            FlashMessage::error(encode_minimal(&format!("Invalid {}: {}", part, e))).send();
+            return Ok(see_other("/admin/newsletter"));
+        }
+    }
     // Return early if we have a saved response in the database, since it's already been sent
     let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
     let mut transaction = match try_processing(&pool, &idempotency_key, *user_id)
@@ -57,16 +128,38 @@ pub async fn publish_issue(
         }
     };
 
-    // Save issue in db
-    let issue_id = insert_newsletter_issue(&mut transaction, &title, &text_content, &html_content)
+    let list_ids = get_list_ids(&mut transaction, &lists)
         .await
-        .context("Failed to store newsletter issue details")
+        .context("Failed to retrieve the target lists")
         .map_err(e500)?;
+    if list_ids.len() != lists.len() {
+        return Err(e400("The issue targets lists that don't exist."));
+    }
+
+    // Save issue in db
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &title,
+        &text_content,
+        &html_content,
+        topic.as_deref(),
+        segment.as_ref().map(|(segment, _)| segment.as_str()),
+        &list_ids,
+    )
+    .await
+    .context("Failed to store newsletter issue details")
+    .map_err(e500)?;
     // Push to working queue
-    enqueue_delivery_tasks(&mut transaction, issue_id)
-        .await
-        .context("Failed to enqueue delivery tasks")
-        .map_err(e500)?;
+    enqueue_delivery_tasks(
+        &mut transaction,
+        issue_id,
+        topic.as_deref(),
+        &list_ids,
+        segment.as_ref().map(|(_, segment)| segment),
+    )
+    .await
+    .context("Failed to enqueue delivery tasks")
+    .map_err(e500)?;
     let response = see_other("/admin/newsletter");
     let response = save_response(&idempotency_key, *user_id, response, *transaction)
         .await
@@ -76,13 +169,60 @@ pub async fn publish_issue(
     Ok(response)
 }
 
-/// Insert a new row in `newsletter_issues` table
+/// Keys of the attributes set on at least one subscriber,
+/// the only ones templates may refer to
+#[tracing::instrument(skip_all)]
+async fn get_attribute_keys(pool: &PgPool) -> Result<Vec<String>, sqlx::Error> {
+    let keys = sqlx::query!(
+        r#"
+        SELECT DISTINCT jsonb_object_keys(attributes) AS "key!"
+        FROM subscriptions
+        "#
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| r.key)
+    .collect();
+
+    Ok(keys)
+}
+
+/// Look up the ids of the lists with the given slugs, in the same order.
+/// Unknown slugs are left out.
+#[tracing::instrument(skip(transaction))]
+async fn get_list_ids(
+    transaction: &mut Transaction<'_, Postgres>,
+    slugs: &[String],
+) -> Result<Vec<Uuid>, sqlx::Error> {
+    let list_ids = sqlx::query!(
+        r#"
+        SELECT list_id
+        FROM lists
+        WHERE slug = ANY($1)
+        ORDER BY array_position($1, slug)
+        "#,
+        slugs
+    )
+    .fetch_all(transaction)
+    .await?
+    .into_iter()
+    .map(|r| r.list_id)
+    .collect();
+
+    Ok(list_ids)
+}
+
+/// Insert a new row in `newsletter_issues` table, along with the lists it targets
 #[tracing::instrument(skip_all)]
 async fn insert_newsletter_issue(
     transaction: &mut Transaction<'_, Postgres>,
     title: &str,
     text_content: &str,
     html_content: &str,
+    topic: Option<&str>,
+    segment: Option<&str>,
+    list_ids: &[Uuid],
 ) -> Result<Uuid, sqlx::Error> {
     let newsletter_issue_id = Uuid::new_v4();
     sqlx::query!(
@@ -92,14 +232,29 @@ async fn insert_newsletter_issue(
           title,
           text_content,
           html_content,
+          topic,
+          segment,
           published_at
         )
-        VALUES ($1, $2, $3, $4, now())
+        VALUES ($1, $2, $3, $4, $5, $6, now())
         "#,
         newsletter_issue_id,
         title,
         text_content,
-        html_content
+        html_content,
+        topic,
+        segment
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+        SELECT $1, list_id
+        FROM UNNEST($2::uuid[]) AS list_id
+        "#,
+        newsletter_issue_id,
+        list_ids
     )
     .execute(transaction)
     .await?;
@@ -107,25 +262,115 @@ async fn insert_newsletter_issue(
     Ok(newsletter_issue_id)
 }
 
-/// Enqueue the task in `issue_delivery_queue` table
+/// Enqueue the task in `issue_delivery_queue` table for every confirmed subscriber
+/// of the target lists who didn't opt out of the issue's topic and isn't suppressed,
+/// record each of them as `queued` in the delivery ledger and wake up the workers.
+///
+/// Subscribers of several target lists get a single email, sent on behalf of
+/// the first of their lists in `list_ids`.
+///
+/// The segment is compiled into the statement's `WHERE` clause, which is why
+/// the statement is assembled at runtime. Tags are bound as parameters.
 #[tracing::instrument(skip_all)]
 async fn enqueue_delivery_tasks(
     transaction: &mut Transaction<'_, Postgres>,
     newsletter_issue_id: Uuid,
+    topic: Option<&str>,
+    list_ids: &[Uuid],
+    segment: Option<&Segment>,
 ) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+    // $1 to $3 are taken by the fixed parameters
+    let (segment_condition, tags) = match segment {
+        Some(segment) => {
+            let tags: Vec<_> = segment.tags().into_iter().map(AsRef::as_ref).collect();
+            (segment_condition(segment, &tags, 4), tags)
+        }
+        None => ("TRUE".to_string(), Vec::new()),
+    };
+    let sql = format!(
         r#"
-        INSERT INTO issue_delivery_queue (
-            newsletter_issue_id, subscriber_email
+        WITH queued AS (
+            INSERT INTO issue_delivery_queue (
+                newsletter_issue_id, subscriber_email, list_id
+            )
+            SELECT DISTINCT ON (email) $1, email, list_subscriptions.list_id
+            FROM subscriptions
+            JOIN list_subscriptions ON list_subscriptions.subscriber_id = subscriptions.id
+            WHERE
+                subscriptions.status = 'confirmed' AND
+                list_subscriptions.status = 'confirmed' AND
+                list_subscriptions.list_id = ANY($3) AND
+                NOT EXISTS (
+                    SELECT 1
+                    FROM topic_opt_outs
+                    WHERE
+                        topic_opt_outs.subscriber_id = subscriptions.id AND
+                        topic_opt_outs.topic = $2
+                ) AND
+                NOT EXISTS (
+                    SELECT 1
+                    FROM suppressions
+                    WHERE suppressions.email = subscriptions.email_normalised
+                ) AND
+                ({segment_condition})
+            ORDER BY email, array_position($3, list_subscriptions.list_id)
+            RETURNING newsletter_issue_id, subscriber_email
         )
-        SELECT $1, email
-        FROM subscriptions
-        WHERE status = 'confirmed'
-        "#,
-        newsletter_issue_id
-    )
-    .execute(transaction)
-    .await?;
+        INSERT INTO newsletter_issue_deliveries (
+            newsletter_issue_id,
+            subscriber_email,
+            status,
+            queued_at,
+            updated_at
+        )
+        SELECT newsletter_issue_id, subscriber_email, 'queued', now(), now()
+        FROM queued
+        "#
+    );
+    let mut query = sqlx::query(&sql)
+        .bind(newsletter_issue_id)
+        .bind(topic)
+        .bind(list_ids);
+    for tag in tags {
+        query = query.bind(tag);
+    }
+    query.execute(&mut *transaction).await?;
+    notify_workers(transaction).await?;
 
     Ok(())
 }
+
+/// Compile the segment into an SQL condition on `subscriptions`.
+/// Each tag is referred to as the parameter numbered `first_parameter`
+/// plus its position in `tags`.
+fn segment_condition(segment: &Segment, tags: &[&str], first_parameter: usize) -> String {
+    match segment {
+        Segment::Tag(tag) => {
+            let position = tags
+                .iter()
+                .position(|t| *t == tag.as_ref())
+                .expect("Every tag of the segment is in `tags`");
+            format!(
+                "EXISTS (\
+                    SELECT 1 FROM subscriber_tags \
+                    WHERE subscriber_tags.subscriber_id = subscriptions.id AND \
+                    subscriber_tags.tag = ${}\
+                )",
+                first_parameter + position
+            )
+        }
+        Segment::Not(segment) => {
+            format!("NOT {}", segment_condition(segment, tags, first_parameter))
+        }
+        Segment::And(left, right) => format!(
+            "({} AND {})",
+            segment_condition(left, tags, first_parameter),
+            segment_condition(right, tags, first_parameter)
+        ),
+        Segment::Or(left, right) => format!(
+            "({} OR {})",
+            segment_condition(left, tags, first_parameter),
+            segment_condition(right, tags, first_parameter)
+        ),
+    }
+}
@@ -0,0 +1,76 @@
+use {
+    crate::{
+        routes::get_list_by_slug,
+        subscriber_export::{self, ExportFilter, ExportFormat},
+        utils::{e400, e500},
+    },
+    actix_web::{
+        http::header::{ContentDisposition, DispositionParam, DispositionType},
+        web, HttpResponse,
+    },
+    anyhow::Context,
+    futures::StreamExt,
+    sqlx::PgPool,
+};
+
+/// Every parameter is optional, and empty ones are ignored
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    /// `csv` or `ndjson`, CSV by default
+    format: Option<String>,
+    status: Option<String>,
+    /// First day of the subscription date range, as `YYYY-MM-DD`
+    from: Option<String>,
+    /// Last day of the subscription date range, as `YYYY-MM-DD`
+    until: Option<String>,
+    /// Slug of the list subscribers are members of
+    list: Option<String>,
+}
+
+/// Stream the subscribers matching the filters as a file to download
+#[tracing::instrument(name = "Export subscribers", skip(parameters, pool))]
+pub async fn export_subscribers(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let non_empty = |parameter: &Option<String>| parameter.clone().filter(|p| !p.is_empty());
+    let format = match non_empty(&parameters.format) {
+        Some(format) => ExportFormat::parse(&format).map_err(e400)?,
+        None => ExportFormat::Csv,
+    };
+    let list_id = match non_empty(&parameters.list) {
+        Some(slug) => {
+            let mut transaction = pool
+                .begin()
+                .await
+                .context("Failed to acquire a Postgres connection from the pool")
+                .map_err(e500)?;
+            let list = get_list_by_slug(&mut transaction, &slug)
+                .await
+                .map_err(e500)?
+                .ok_or_else(|| e400(format!("There is no list {}.", slug)))?;
+            transaction.rollback().await.map_err(e500)?;
+            Some(list.list_id)
+        }
+        None => None,
+    };
+    let filter = ExportFilter::parse(
+        non_empty(&parameters.status).as_deref(),
+        non_empty(&parameters.from).as_deref(),
+        non_empty(&parameters.until).as_deref(),
+        list_id,
+    )
+    .map_err(e400)?;
+
+    let rows = subscriber_export::export_subscribers(pool.get_ref().clone(), filter, format);
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "subscribers.{}",
+                format.extension()
+            ))],
+        })
+        .streaming(rows.map(|row| row.map(web::Bytes::from))))
+}
@@ -0,0 +1,71 @@
+use {
+    crate::{routes::get_lists, utils::e500},
+    actix_web::{http::header::ContentType, web, HttpResponse},
+    htmlescape::encode_minimal,
+    sqlx::PgPool,
+    std::fmt::Write,
+};
+
+/// Form to export subscribers, filtered by status, subscription date and list
+pub async fn subscribers_form(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let mut lists_html = String::new();
+    for list in get_lists(&pool).await.map_err(e500)? {
+        writeln!(
+            lists_html,
+            r#"<option value="{}">{}</option>"#,
+            encode_minimal(&list.slug),
+            encode_minimal(&list.name)
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Export subscribers</title>
+    </head>
+    <body>
+        <form action="/admin/subscribers/export" method="get">
+            <label>Status
+                <select name="status">
+                    <option value="">Any</option>
+                    <option value="confirmed">Confirmed</option>
+                    <option value="pending_confirmation">Pending confirmation</option>
+                    <option value="unsubscribed">Unsubscribed</option>
+                </select>
+            </label>
+            <br>
+            <label>Subscribed from
+                <input type="date" name="from">
+            </label>
+            <label>until
+                <input type="date" name="until">
+            </label>
+            <br>
+            <label>List
+                <select name="list">
+                    <option value="">Any</option>
+                    {lists_html}
+                </select>
+            </label>
+            <br>
+            <label>
+                <input type="radio" name="format" value="csv" checked>CSV
+            </label>
+            <label>
+                <input type="radio" name="format" value="ndjson">JSON lines
+            </label>
+            <br>
+            <button type="submit">Export</button>
+        </form>
+        <p><a href="/admin/dashboard">&lt;- Back</a></p>
+    </body>
+</html>
+    "#
+        )))
+}
@@ -0,0 +1,5 @@
+mod export;
+mod get;
+
+pub use export::export_subscribers;
+pub use get::subscribers_form;
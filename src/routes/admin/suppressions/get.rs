@@ -0,0 +1,123 @@
+use {
+    crate::utils::e500,
+    actix_web::{http::header::ContentType, web, HttpResponse},
+    actix_web_flash_messages::IncomingFlashMessages,
+    anyhow::Context,
+    chrono::{DateTime, Utc},
+    htmlescape::encode_minimal,
+    sqlx::PgPool,
+    std::fmt::Write,
+};
+
+struct Suppression {
+    email: String,
+    reason: String,
+    suppressed_at: DateTime<Utc>,
+}
+
+/// List the suppressed addresses, along with a form to add or remove one
+pub async fn suppressions_form(
+    flash_message: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_message.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let mut suppressions_html = String::new();
+    for suppression in get_suppressions(&pool).await.map_err(e500)? {
+        writeln!(
+            suppressions_html,
+            "<li><b>{}</b>: {}, since {}</li>",
+            encode_minimal(&suppression.email),
+            encode_minimal(&suppression.reason),
+            suppression.suppressed_at.format("%Y-%m-%d %H:%M")
+        )
+        .unwrap();
+    }
+    let n_erased = count_erased_subscribers(&pool).await.map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Suppressions</title>
+    </head>
+    <body>
+        {msg_html}
+        <p>Suppressed addresses are never emailed, whoever subscribes or imports them.</p>
+        <ul>
+            {suppressions_html}
+        </ul>
+        <p>
+            The addresses of {n_erased} erased subscribers are suppressed too.
+            Only a hash of them is kept, remove one by entering it below.
+        </p>
+        <form action="/admin/suppressions" method="post">
+            <label>Email
+                <input
+                    type="email"
+                    placeholder="Enter the address"
+                    name="email"
+                >
+            </label>
+            <br>
+            <label>Reason
+                <select name="reason">
+                    <option value="manual" selected>Manual</option>
+                    <option value="hard_bounce">Hard bounce</option>
+                    <option value="spam_complaint">Spam complaint</option>
+                </select>
+            </label>
+            <br>
+            <label>
+                <input type="radio" name="action" value="add" checked>Suppress
+            </label>
+            <label>
+                <input type="radio" name="action" value="remove">Remove
+            </label>
+            <br>
+            <button type="submit">Update suppressions</button>
+        </form>
+        <p><a href="/admin/dashboard">&lt;- Back</a></p>
+    </body>
+</html>
+    "#
+        )))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_suppressions(pool: &PgPool) -> Result<Vec<Suppression>, anyhow::Error> {
+    let suppressions = sqlx::query_as!(
+        Suppression,
+        r#"
+        SELECT email, reason, suppressed_at
+        FROM suppressions
+        ORDER BY suppressed_at DESC, email
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the suppressed addresses.")?;
+
+    Ok(suppressions)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn count_erased_subscribers(pool: &PgPool) -> Result<i64, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM erased_subscribers
+        "#
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count the erased subscribers.")?;
+
+    Ok(row.count)
+}
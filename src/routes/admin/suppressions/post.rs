@@ -0,0 +1,73 @@
+use {
+    crate::{
+        domain::SubscriberEmail,
+        startup::HmacSecret,
+        suppressions::{suppress, unsuppress, SuppressionReason},
+        utils::{e500, see_other},
+    },
+    actix_web::{web, HttpResponse},
+    actix_web_flash_messages::FlashMessage,
+    htmlescape::encode_minimal,
+    sqlx::PgPool,
+};
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SuppressionAction {
+    Add,
+    Remove,
+}
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    email: String,
+    /// Only used when adding
+    reason: SuppressionReason,
+    action: SuppressionAction,
+}
+
+/// Suppress an address, or let it be emailed again
+#[tracing::instrument(
+    name = "Update suppressions",
+    skip(form, pool, secret),
+    fields(email = %form.email)
+)]
+pub async fn update_suppressions(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form = form.into_inner();
+    let email = match SubscriberEmail::parse(form.email) {
+        Ok(email) => email,
+        Err(e) => {
+            FlashMessage::error(encode_minimal(&e)).send();
+            return Ok(see_other("/admin/suppressions"));
+        }
+    };
+    let message = match form.action {
+        SuppressionAction::Add => {
+            if suppress(pool.get_ref(), email.as_ref(), form.reason)
+                .await
+                .map_err(e500)?
+            {
+                format!("{} has been suppressed.", email)
+            } else {
+                format!("{} was already suppressed.", email)
+            }
+        }
+        SuppressionAction::Remove => {
+            if unsuppress(pool.get_ref(), email.as_ref(), &secret.0)
+                .await
+                .map_err(e500)?
+            {
+                format!("{} can be emailed again.", email)
+            } else {
+                format!("{} wasn't suppressed.", email)
+            }
+        }
+    };
+    FlashMessage::info(encode_minimal(&message)).send();
+
+    Ok(see_other("/admin/suppressions"))
+}
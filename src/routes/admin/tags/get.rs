@@ -0,0 +1,100 @@
+use {
+    crate::utils::e500,
+    actix_web::{http::header::ContentType, web, HttpResponse},
+    actix_web_flash_messages::IncomingFlashMessages,
+    anyhow::Context,
+    htmlescape::encode_minimal,
+    sqlx::PgPool,
+    std::fmt::Write,
+};
+
+struct TagCount {
+    tag: String,
+    n_subscribers: i64,
+}
+
+/// List the tags in use, along with a form to tag or untag a subscriber
+pub async fn tags_form(
+    flash_message: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_message.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let mut tags_html = String::new();
+    for tag in get_tag_counts(&pool).await.map_err(e500)? {
+        writeln!(
+            tags_html,
+            "<li><b>{}</b>: {} subscribers</li>",
+            encode_minimal(&tag.tag),
+            tag.n_subscribers
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Tags</title>
+    </head>
+    <body>
+        {msg_html}
+        <ul>
+            {tags_html}
+        </ul>
+        <form action="/admin/tags" method="post">
+            <label>Subscriber email
+                <input
+                    type="email"
+                    placeholder="Enter the subscriber's email address"
+                    name="email"
+                >
+            </label>
+            <br>
+            <label>Tags
+                <input
+                    type="text"
+                    placeholder="e.g. beta, region:eu"
+                    name="tags"
+                >
+            </label>
+            <br>
+            <label>
+                <input type="radio" name="action" value="add" checked>Add
+            </label>
+            <label>
+                <input type="radio" name="action" value="remove">Remove
+            </label>
+            <br>
+            <button type="submit">Update tags</button>
+        </form>
+        <p><a href="/admin/dashboard">&lt;- Back</a></p>
+    </body>
+</html>
+    "#
+        )))
+}
+
+#[tracing::instrument(name = "Get tag counts", skip(pool))]
+async fn get_tag_counts(pool: &PgPool) -> Result<Vec<TagCount>, anyhow::Error> {
+    let tags = sqlx::query_as!(
+        TagCount,
+        r#"
+        SELECT tag, COUNT(*) AS "n_subscribers!"
+        FROM subscriber_tags
+        GROUP BY tag
+        ORDER BY tag
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the tags.")?;
+
+    Ok(tags)
+}
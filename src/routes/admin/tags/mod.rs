@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::tags_form;
+pub use post::tag_subscriber;
@@ -0,0 +1,146 @@
+use {
+    crate::{
+        domain::{SubscriberEmail, SubscriberTag},
+        utils::{e500, see_other},
+    },
+    actix_web::{web, HttpResponse},
+    actix_web_flash_messages::FlashMessage,
+    anyhow::Context,
+    htmlescape::encode_minimal,
+    sqlx::PgPool,
+    uuid::Uuid,
+};
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagAction {
+    Add,
+    Remove,
+}
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    email: String,
+    /// Separated by commas or whitespace
+    tags: String,
+    action: TagAction,
+}
+
+/// Add tags to a subscriber, or remove tags from them
+#[tracing::instrument(name = "Tag a subscriber", skip(form, pool), fields(email = %form.email))]
+pub async fn tag_subscriber(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form = form.into_inner();
+    let email = match SubscriberEmail::parse(form.email) {
+        Ok(email) => email,
+        Err(e) => {
+            FlashMessage::error(encode_minimal(&e)).send();
+            return Ok(see_other("/admin/tags"));
+        }
+    };
+    let tags: Result<Vec<_>, _> = form
+        .tags
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|tag| !tag.is_empty())
+        .map(SubscriberTag::parse)
+        .collect();
+    let tags: Vec<String> = match tags {
+        Ok(tags) if !tags.is_empty() => tags.iter().map(|t| t.as_ref().to_owned()).collect(),
+        Ok(_) => {
+            FlashMessage::error("Enter at least one tag.").send();
+            return Ok(see_other("/admin/tags"));
+        }
+        Err(e) => {
+            FlashMessage::error(encode_minimal(&e)).send();
+            return Ok(see_other("/admin/tags"));
+        }
+    };
+
+    let subscriber_id = match get_subscriber_id(&pool, &email).await.map_err(e500)? {
+        Some(subscriber_id) => subscriber_id,
+        None => {
+            FlashMessage::error(format!(
+                "There is no subscriber {}.",
+                encode_minimal(email.as_ref())
+            ))
+            .send();
+            return Ok(see_other("/admin/tags"));
+        }
+    };
+    match form.action {
+        TagAction::Add => add_tags(&pool, subscriber_id, &tags).await,
+        TagAction::Remove => remove_tags(&pool, subscriber_id, &tags).await,
+    }
+    .map_err(e500)?;
+    FlashMessage::info(format!(
+        "The tags of {} have been updated.",
+        encode_minimal(email.as_ref())
+    ))
+    .send();
+
+    Ok(see_other("/admin/tags"))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_subscriber_id(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT id FROM subscriptions WHERE email_normalised = $1
+        "#,
+        email.normalised()
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the subscriber.")?;
+
+    Ok(subscriber.map(|s| s.id))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn add_tags(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    tags: &[String],
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_tags (subscriber_id, tag, tagged_at)
+        SELECT $1, tag, now()
+        FROM UNNEST($2::text[]) AS tag
+        ON CONFLICT DO NOTHING
+        "#,
+        subscriber_id,
+        tags
+    )
+    .execute(pool)
+    .await
+    .context("Failed to add tags to the subscriber.")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip(pool))]
+async fn remove_tags(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    tags: &[String],
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM subscriber_tags
+        WHERE subscriber_id = $1 AND tag = ANY($2)
+        "#,
+        subscriber_id,
+        tags
+    )
+    .execute(pool)
+    .await
+    .context("Failed to remove tags from the subscriber.")?;
+
+    Ok(())
+}
@@ -0,0 +1,93 @@
+use {
+    crate::utils::e500,
+    actix_web::{http::header::ContentType, web, HttpResponse},
+    actix_web_flash_messages::IncomingFlashMessages,
+    anyhow::Context,
+    htmlescape::encode_minimal,
+    sqlx::PgPool,
+    std::fmt::Write,
+};
+
+/// A topic issues can be published under, which subscribers can opt out of
+pub struct Topic {
+    pub name: String,
+    pub description: String,
+}
+
+/// List the existing topics, along with a form to add a new one
+pub async fn topics_form(
+    flash_message: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_message.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let mut topics_html = String::new();
+    for topic in get_topics(&pool).await.map_err(e500)? {
+        writeln!(
+            topics_html,
+            "<li><b>{}</b>: {}</li>",
+            encode_minimal(&topic.name),
+            encode_minimal(&topic.description)
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Topics</title>
+    </head>
+    <body>
+        {msg_html}
+        <ul>
+            {topics_html}
+        </ul>
+        <form action="/admin/topics" method="post">
+            <label>Name
+                <input
+                    type="text"
+                    placeholder="Enter the topic's name"
+                    name="name"
+                >
+            </label>
+            <br>
+            <label>Description
+                <input
+                    type="text"
+                    placeholder="Describe the topic to subscribers"
+                    name="description"
+                >
+            </label>
+            <br>
+            <button type="submit">Add topic</button>
+        </form>
+        <p><a href="/admin/dashboard">&lt;- Back</a></p>
+    </body>
+</html>
+    "#
+        )))
+}
+
+#[tracing::instrument(name = "Get topics", skip(pool))]
+pub async fn get_topics(pool: &PgPool) -> Result<Vec<Topic>, anyhow::Error> {
+    let topics = sqlx::query_as!(
+        Topic,
+        r#"
+        SELECT name, description
+        FROM topics
+        ORDER BY name
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the topics.")?;
+
+    Ok(topics)
+}
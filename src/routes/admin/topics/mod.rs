@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::{get_topics, topics_form, Topic};
+pub use post::add_topic;
@@ -0,0 +1,56 @@
+use {
+    crate::utils::{e500, see_other},
+    actix_web::{web, HttpResponse},
+    actix_web_flash_messages::FlashMessage,
+    anyhow::Context,
+    sqlx::PgPool,
+};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    name: String,
+    description: String,
+}
+
+/// Add a topic issues can be published under
+#[tracing::instrument(name = "Add a topic", skip(form, pool), fields(name = %form.name))]
+pub async fn add_topic(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = form.name.trim();
+    if name.is_empty() || name.chars().count() > 64 {
+        FlashMessage::error("Topic names should be between 1 and 64 chars long.").send();
+        return Ok(see_other("/admin/topics"));
+    }
+
+    let inserted = insert_topic(&pool, name, form.description.trim())
+        .await
+        .map_err(e500)?;
+    if inserted {
+        FlashMessage::info(format!("The topic {} has been added.", name)).send();
+    } else {
+        FlashMessage::error(format!("The topic {} already exists.", name)).send();
+    }
+
+    Ok(see_other("/admin/topics"))
+}
+
+/// Insert the topic, returning whether it didn't exist yet
+#[tracing::instrument(skip(pool))]
+async fn insert_topic(pool: &PgPool, name: &str, description: &str) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO topics (name, description, created_at)
+        VALUES ($1, $2, now())
+        ON CONFLICT DO NOTHING
+        "#,
+        name,
+        description
+    )
+    .execute(pool)
+    .await
+    .context("Failed to insert the topic.")?;
+
+    Ok(result.rows_affected() > 0)
+}
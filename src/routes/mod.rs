@@ -3,13 +3,25 @@ mod health_check;
 mod home;
 mod login;
 mod newsletters;
+mod preferences;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_data;
+mod subscriptions_resend;
+mod subscriptions_unsubscribe;
+mod webhooks;
 
 pub use admin::*;
 pub use health_check::*;
 pub use home::*;
 pub use login::*;
 pub use newsletters::*;
+pub use preferences::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_data::{data_page, download_data, erase_data, request_data_link};
+pub use subscriptions_resend::{record_resend_attempt, resend_confirmation};
+pub use subscriptions_unsubscribe::{
+    unsubscribe, unsubscribe_form, unsubscribe_from_everything, unsubscribe_subscriber,
+};
+pub use webhooks::{postmark_bounce, postmark_complaint};
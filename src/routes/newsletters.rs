@@ -1,17 +1,16 @@
 use {
     crate::{
-        authentication::{validate_credentials, AuthError, Credentials},
+        authentication::{basic_authentication, validate_credentials, AuthError},
         domain::SubscriberEmail,
         email_client::EmailClient,
-        routes::error_chain_fmt,
+        routes::{error_chain_fmt, DEFAULT_LIST},
     },
     actix_web::{
-        http::header::{self, HeaderMap, HeaderValue},
+        http::header::{self, HeaderValue},
         web, HttpRequest, HttpResponse, ResponseError,
     },
     anyhow::Context,
     reqwest::StatusCode,
-    secrecy::Secret,
     sqlx::PgPool,
 };
 
@@ -111,42 +110,12 @@ pub async fn publish_newsletter(
     Ok(HttpResponse::Ok().finish())
 }
 
-fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
-    let header_value = headers
-        .get("Authorization")
-        .context("The 'Authorization' header is missing")?
-        .to_str()
-        .context("The 'Authorization' header was not valid UTF8 string.")?;
-
-    let base64encoded_segment = header_value
-        .strip_prefix("Basic ")
-        .context("The authorization scheme was not 'Basic'.")?;
-    let decoded_bytes = base64::decode_config(base64encoded_segment, base64::STANDARD)
-        .context("Failed to base64-decode 'Basic' credentials.")?;
-    let decoded_credentials = String::from_utf8(decoded_bytes)
-        .context("The decoded credential string is not valid UTF8.")?;
-
-    // Split to two segments, using ":" as delimiter
-    let mut credentials = decoded_credentials.splitn(2, ':');
-    let username = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("An username must be provided in 'Basic' auth."))?
-        .to_string();
-    let password = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth."))?
-        .to_string();
-
-    Ok(Credentials {
-        username,
-        password: Secret::new(password),
-    })
-}
-
 struct ConfirmedSubscriber {
     email: SubscriberEmail,
 }
 
+/// Confirmed subscribers of the default list, the only one this endpoint publishes to,
+/// whose address isn't suppressed
 #[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
 async fn get_confirmed_subscribers(
     pool: &PgPool,
@@ -155,8 +124,19 @@ async fn get_confirmed_subscribers(
         r#"
             SELECT email
             FROM subscriptions
-            WHERE status = 'confirmed'
+            JOIN list_subscriptions ON list_subscriptions.subscriber_id = subscriptions.id
+            JOIN lists ON lists.list_id = list_subscriptions.list_id
+            WHERE
+                subscriptions.status = 'confirmed' AND
+                list_subscriptions.status = 'confirmed' AND
+                lists.slug = $1 AND
+                NOT EXISTS (
+                    SELECT 1
+                    FROM suppressions
+                    WHERE suppressions.email = subscriptions.email_normalised
+                )
         "#,
+        DEFAULT_LIST
     )
     .fetch_all(pool)
     .await?
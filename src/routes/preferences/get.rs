@@ -0,0 +1,148 @@
+use {
+    crate::{
+        domain::{SubscriberToken, TokenPurpose},
+        routes::get_topics,
+        startup::HmacSecret,
+        utils::e500,
+    },
+    actix_web::{http::header::ContentType, web, HttpResponse},
+    actix_web_flash_messages::IncomingFlashMessages,
+    anyhow::Context,
+    htmlescape::encode_minimal,
+    sqlx::PgPool,
+    std::fmt::Write,
+    uuid::Uuid,
+};
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    token: String,
+}
+
+struct Subscriber {
+    name: String,
+    email: String,
+}
+
+/// Let a subscriber change their name, email address and topics,
+/// authenticated by the token of the link sent along with every issue
+pub async fn preferences_form(
+    parameters: web::Query<Parameters>,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = match SubscriberToken::verify(
+        TokenPurpose::ManagePreferences,
+        &parameters.token,
+        &secret.0,
+    ) {
+        Ok(subscriber_id) => subscriber_id,
+        Err(_) => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+    let subscriber = match get_subscriber(&pool, subscriber_id).await.map_err(e500)? {
+        Some(subscriber) => subscriber,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+    let opted_out = get_opted_out_topics(&pool, subscriber_id)
+        .await
+        .map_err(e500)?;
+    let mut topics_html = String::new();
+    for topic in get_topics(&pool).await.map_err(e500)? {
+        let checked = if opted_out.contains(&topic.name) {
+            ""
+        } else {
+            "checked"
+        };
+        writeln!(
+            topics_html,
+            r#"<label><input type="checkbox" name="topic" value="{}" {checked}> {}</label><br>"#,
+            encode_minimal(&topic.name),
+            encode_minimal(&topic.description),
+        )
+        .unwrap();
+    }
+    let token = encode_minimal(&parameters.token);
+    let name = encode_minimal(&subscriber.name);
+    let email = encode_minimal(&subscriber.email);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Your preferences</title>
+    </head>
+    <body>
+        {msg_html}
+        <form action="/preferences" method="post">
+            <input hidden type="text" name="token" value="{token}">
+            <label>Name
+                <input type="text" name="name" value="{name}">
+            </label>
+            <br>
+            <label>Email
+                <input type="email" name="email" value="{email}">
+            </label>
+            <p>Changing your email address takes effect once you confirm the new one.</p>
+            <p>Topics you receive:</p>
+            {topics_html}
+            <button type="submit">Save preferences</button>
+        </form>
+    </body>
+</html>
+    "#
+        )))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT name, email
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the subscriber.")?;
+
+    Ok(subscriber)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_opted_out_topics(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<String>, anyhow::Error> {
+    let topics = sqlx::query!(
+        r#"
+        SELECT topic
+        FROM topic_opt_outs
+        WHERE subscriber_id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the topics the subscriber opted out of.")?
+    .into_iter()
+    .map(|r| r.topic)
+    .collect();
+
+    Ok(topics)
+}
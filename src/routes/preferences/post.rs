@@ -0,0 +1,484 @@
+use {
+    crate::{
+        configuration::SubscriptionSettings,
+        domain::{SubscriberEmail, SubscriberName, SubscriberToken, TokenPurpose},
+        email_client::EmailClient,
+        routes::generate_subscription_token,
+        startup::{ApplicationBaseUrl, HmacSecret},
+        suppressions::is_suppressed,
+        utils::{e500, see_other},
+    },
+    actix_web::{web, HttpResponse},
+    actix_web_flash_messages::FlashMessage,
+    anyhow::Context,
+    chrono::{DateTime, Utc},
+    secrecy::Secret,
+    sqlx::{PgPool, Postgres, Transaction},
+    std::collections::BTreeSet,
+    tracing::Instrument,
+    uuid::Uuid,
+};
+
+/// Fields of the preferences form.
+/// Every checked topic comes as its own `topic` field, which `web::Form` can't collect
+/// into a struct, so the form is read as a list of pairs instead.
+struct FormData {
+    token: String,
+    name: String,
+    email: String,
+    topics: BTreeSet<String>,
+}
+
+impl From<Vec<(String, String)>> for FormData {
+    fn from(pairs: Vec<(String, String)>) -> Self {
+        let mut form = FormData {
+            token: String::new(),
+            name: String::new(),
+            email: String::new(),
+            topics: BTreeSet::new(),
+        };
+        for (key, value) in pairs {
+            match key.as_str() {
+                "token" => form.token = value,
+                "name" => form.name = value,
+                "email" => form.email = value,
+                "topic" => {
+                    form.topics.insert(value);
+                }
+                _ => {}
+            }
+        }
+        form
+    }
+}
+
+struct Subscriber {
+    name: String,
+    email: String,
+}
+
+/// Apply the changes a subscriber made on their preferences page.
+/// A new email address is only used once the subscriber confirms it.
+///
+/// The page reads the same whether or not the new address belongs to another subscriber,
+/// so that preferences links can't be used to find out who is subscribed:
+/// the owner of the address is notified instead.
+#[tracing::instrument(
+    name = "Update subscriber preferences",
+    skip_all,
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn update_preferences(
+    form: web::Form<Vec<(String, String)>>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    secret: web::Data<HmacSecret>,
+    subscription_config: web::Data<SubscriptionSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form = FormData::from(form.into_inner());
+    let subscriber_id =
+        match SubscriberToken::verify(TokenPurpose::ManagePreferences, &form.token, &secret.0) {
+            Ok(subscriber_id) => subscriber_id,
+            Err(_) => return Ok(HttpResponse::Unauthorized().finish()),
+        };
+    tracing::Span::current().record("subscriber_id", &tracing::field::display(subscriber_id));
+    let preferences_page = format!("/preferences?token={}", urlencoding::encode(&form.token));
+
+    let name = match SubscriberName::parse(form.name) {
+        Ok(name) => name,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&preferences_page));
+        }
+    };
+    let email = match SubscriberEmail::parse(form.email) {
+        Ok(email) => email,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&preferences_page));
+        }
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let subscriber = match get_subscriber_for_update(&mut transaction, subscriber_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(subscriber) => subscriber,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    let email_changed = email.as_ref() != subscriber.email;
+    let email_taken = email_changed
+        && is_email_taken(&mut transaction, subscriber_id, &email)
+            .await
+            .map_err(e500)?;
+
+    if name.as_ref() != subscriber.name {
+        update_name(&mut transaction, subscriber_id, name.as_ref())
+            .await
+            .map_err(e500)?;
+        record_preference_change(
+            &mut transaction,
+            subscriber_id,
+            "name",
+            &subscriber.name,
+            name.as_ref(),
+        )
+        .await
+        .map_err(e500)?;
+    }
+    update_topics(&mut transaction, subscriber_id, &form.topics)
+        .await
+        .map_err(e500)?;
+
+    let subscription_token = if email_changed && !email_taken {
+        let subscription_token = generate_subscription_token();
+        store_email_change_token(
+            &mut transaction,
+            subscriber_id,
+            &subscription_token,
+            email.as_ref(),
+            subscription_config.confirmation_token_expires_at(),
+        )
+        .await
+        .map_err(e500)?;
+        Some(subscription_token)
+    } else {
+        None
+    };
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update subscriber preferences.")
+        .map_err(e500)?;
+
+    if email_changed {
+        FlashMessage::info(format!(
+            "We sent a confirmation link to {}. \
+             Your email address changes once you click it.",
+            email
+        ))
+        .send();
+        // Sent in the background, so that neither a failing email provider
+        // nor how long it takes gives away whether the address is taken
+        tokio::spawn(
+            async move {
+                let result = match subscription_token {
+                    Some(subscription_token) => send_email_change_confirmation(
+                        &email_client,
+                        &pool,
+                        &secret.0,
+                        &email,
+                        &base_url.0,
+                        &subscription_token,
+                    )
+                    .await
+                    .context("Failed to send the email confirming a new email address."),
+                    None => send_email_taken_notice(&email_client, &pool, &secret.0, &email)
+                        .await
+                        .context("Failed to notify the owner of an email address."),
+                };
+                if let Err(e) = result {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to send an email about a change of address."
+                    );
+                }
+            }
+            .instrument(tracing::Span::current()),
+        );
+    }
+    FlashMessage::info("Your preferences have been updated.").send();
+
+    Ok(see_other(&preferences_page))
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn get_subscriber_for_update(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT name, email
+        FROM subscriptions
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to retrieve the subscriber.")?;
+
+    Ok(subscriber)
+}
+
+/// Whether another subscriber already uses the address
+#[tracing::instrument(skip(transaction))]
+async fn is_email_taken(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    email: &SubscriberEmail,
+) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id
+        FROM subscriptions
+        WHERE email_normalised = $1 AND id <> $2
+        "#,
+        email.normalised(),
+        subscriber_id
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to look up the email address.")?;
+
+    Ok(row.is_some())
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn update_name(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    name: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET name = $2
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        name
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to update the subscriber's name.")?;
+
+    Ok(())
+}
+
+/// Opt the subscriber out of every topic they didn't check, and back in the ones they did
+#[tracing::instrument(skip(transaction))]
+async fn update_topics(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    checked_topics: &BTreeSet<String>,
+) -> Result<(), anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            topics.name,
+            topic_opt_outs.subscriber_id IS NOT NULL AS "opted_out!"
+        FROM topics
+        LEFT JOIN topic_opt_outs ON
+            topic_opt_outs.topic = topics.name AND
+            topic_opt_outs.subscriber_id = $1
+        ORDER BY topics.name
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to retrieve the subscriber's topics.")?;
+
+    let received_before: Vec<_> = rows
+        .iter()
+        .filter(|r| !r.opted_out)
+        .map(|r| r.name.as_str())
+        .collect();
+    let received_after: Vec<_> = rows
+        .iter()
+        .filter(|r| checked_topics.contains(&r.name))
+        .map(|r| r.name.as_str())
+        .collect();
+    if received_before == received_after {
+        return Ok(());
+    }
+
+    let opted_out: Vec<_> = rows
+        .iter()
+        .filter(|r| !checked_topics.contains(&r.name))
+        .map(|r| r.name.clone())
+        .collect();
+    sqlx::query!(
+        r#"
+        DELETE FROM topic_opt_outs
+        WHERE subscriber_id = $1
+        "#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to clear the topics the subscriber opted out of.")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO topic_opt_outs (subscriber_id, topic)
+        SELECT $1, topic
+        FROM UNNEST($2::TEXT[]) AS topic
+        "#,
+        subscriber_id,
+        &opted_out
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the topics the subscriber opted out of.")?;
+
+    record_preference_change(
+        transaction,
+        subscriber_id,
+        "topics",
+        &received_before.join(","),
+        &received_after.join(","),
+    )
+    .await
+}
+
+/// Store a token that switches the subscriber to `new_email` once confirmed
+#[tracing::instrument(skip(transaction, subscription_token))]
+async fn store_email_change_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    subscription_token: &str,
+    new_email: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens (
+            subscription_token,
+            subscriber_id,
+            new_email,
+            created_at,
+            expires_at
+        )
+        VALUES ($1, $2, $3, now(), $4)
+        "#,
+        subscription_token,
+        subscriber_id,
+        new_email,
+        expires_at
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to store the token confirming a new email address.")?;
+
+    Ok(())
+}
+
+/// Keep track of every change made to a subscriber's preferences
+#[tracing::instrument(skip(transaction))]
+pub async fn record_preference_change(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    field: &str,
+    old_value: &str,
+    new_value: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_preference_changes (
+            change_id,
+            subscriber_id,
+            field,
+            old_value,
+            new_value,
+            changed_at
+        )
+        VALUES ($1, $2, $3, $4, $5, now())
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        field,
+        old_value,
+        new_value
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to record the change of preferences.")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip(email_client, pool, secret, base_url, subscription_token))]
+async fn send_email_change_confirmation(
+    email_client: &EmailClient,
+    pool: &PgPool,
+    secret: &Secret<String>,
+    new_email: &SubscriberEmail,
+    base_url: &str,
+    subscription_token: &str,
+) -> Result<(), anyhow::Error> {
+    if is_suppressed(pool, new_email.as_ref(), secret)
+        .await
+        .context("Failed to check whether the address is suppressed.")?
+    {
+        tracing::warn!("Not sending an email change confirmation to a suppressed address.");
+        return Ok(());
+    }
+    let confirmation_link = format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url, subscription_token
+    );
+    let plain_body = format!(
+        "Visit {} to confirm your new email address.",
+        confirmation_link
+    );
+    let html_body = format!(
+        "Click <a href=\"{}\">here</a> to confirm your new email address.",
+        confirmation_link
+    );
+    email_client
+        .send_email(
+            new_email,
+            "Confirm your new email address",
+            &html_body,
+            &plain_body,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Let the owner of an address know that someone tried to switch to it,
+/// rather than telling that someone the address is subscribed
+#[tracing::instrument(skip(email_client, pool, secret))]
+async fn send_email_taken_notice(
+    email_client: &EmailClient,
+    pool: &PgPool,
+    secret: &Secret<String>,
+    email: &SubscriberEmail,
+) -> Result<(), anyhow::Error> {
+    if is_suppressed(pool, email.as_ref(), secret)
+        .await
+        .context("Failed to check whether the address is suppressed.")?
+    {
+        tracing::warn!("Not sending an email taken notice to a suppressed address.");
+        return Ok(());
+    }
+    let plain_body = "Someone asked to switch their subscription to this email address, \
+        which is already subscribed.\n\
+        Nothing changed, you can ignore this email if it wasn't you.";
+    let html_body = "Someone asked to switch their subscription to this email address, \
+        which is already subscribed.<br />\
+        Nothing changed, you can ignore this email if it wasn't you.";
+    email_client
+        .send_email(
+            email,
+            "Your email address is already subscribed",
+            html_body,
+            plain_body,
+        )
+        .await?;
+
+    Ok(())
+}
@@ -1,19 +1,26 @@
 use {
     crate::{
+        configuration::SubscriptionSettings,
         domain::{NewSubscriber, SubscriberEmail, SubscriberName},
         email_client::EmailClient,
-        startup::ApplicationBaseUrl,
+        routes::{get_list_by_slug, DEFAULT_LIST},
+        startup::{ApplicationBaseUrl, ConfirmationRedirectUrl, HmacSecret},
+        suppressions::is_suppressed,
     },
     actix_web::{
+        http::header::ContentType,
         web::{Data, Form},
         HttpResponse, ResponseError,
     },
     anyhow::Context,
-    chrono::Utc,
+    chrono::{DateTime, Utc},
+    htmlescape::encode_minimal,
     rand::{distributions::Alphanumeric, thread_rng, Rng},
     reqwest::StatusCode,
+    secrecy::Secret,
     serde::Deserialize,
     sqlx::{PgPool, Postgres, Transaction},
+    tracing::Instrument,
     uuid::Uuid,
 };
 
@@ -21,6 +28,8 @@ use {
 pub struct FormData {
     pub email: String,
     pub name: String,
+    /// Slug of the list to subscribe to, the default list if missing
+    pub list: Option<String>,
 }
 
 pub fn parse_subscriber(form: FormData) -> Result<NewSubscriber, String> {
@@ -29,7 +38,7 @@ pub fn parse_subscriber(form: FormData) -> Result<NewSubscriber, String> {
     Ok(NewSubscriber { email, name })
 }
 
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -39,10 +48,11 @@ fn generate_subscription_token() -> String {
 
 #[tracing::instrument(
     name = "Adding a new subscriber", 
-    skip(form, pool, email_client, base_url),
+    skip(form, pool, email_client, base_url, subscription_config, redirect_url, secret),
     fields(
         subscriber_email = %form.email,
-        subscriber_name = %form.name
+        subscriber_name = %form.name,
+        list = tracing::field::Empty
     )
 )]
 pub async fn subscribe(
@@ -50,38 +60,161 @@ pub async fn subscribe(
     pool: Data<PgPool>,
     email_client: Data<EmailClient>,
     base_url: Data<ApplicationBaseUrl>,
+    subscription_config: Data<SubscriptionSettings>,
+    redirect_url: Data<ConfirmationRedirectUrl>,
+    secret: Data<HmacSecret>,
 ) -> Result<HttpResponse, SubscribeError> {
+    let slug = form
+        .list
+        .clone()
+        .filter(|slug| !slug.is_empty())
+        .unwrap_or_else(|| DEFAULT_LIST.into());
+    tracing::Span::current().record("list", &tracing::field::display(&slug));
     let new_subscriber = parse_subscriber(form.0).map_err(SubscribeError::ValidationError)?;
+    if is_suppressed(pool.get_ref(), new_subscriber.email.as_ref(), &secret.0)
+        .await
+        .context("Failed to check whether the address is suppressed.")?
+    {
+        // Answer just like for any other address, the suppression list is nobody's business
+        tracing::warn!("Not subscribing a suppressed address.");
+        return Ok(check_your_inbox(&redirect_url));
+    }
 
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
+    let list = get_list_by_slug(&mut transaction, &slug)
+        .await?
+        .ok_or_else(|| SubscribeError::ValidationError(format!("There is no list {}.", slug)))?;
 
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    let subscriber_id = match insert_subscriber(&mut transaction, &new_subscriber)
+        .await
+        .context("Failed to insert new subscriber in the database.")?
+    {
+        Some(subscriber_id) => subscriber_id,
+        None => {
+            let (subscriber_id, status) =
+                get_existing_subscriber(&mut transaction, &new_subscriber.email)
+                    .await
+                    .context("Failed to retrieve an existing subscriber.")?;
+            if status == "confirmed" {
+                // Answer just like for a new address, so that the form can't be used
+                // to find out who is subscribed
+                if is_confirmed_member(&mut transaction, list.list_id, subscriber_id)
+                    .await
+                    .context("Failed to retrieve the subscriber's lists.")?
+                {
+                    return Ok(check_your_inbox(&redirect_url));
+                }
+                // Joining another list goes through double opt-in too
+                revoke_confirmation_tokens(&mut transaction, subscriber_id)
+                    .await
+                    .context("Failed to revoke previous confirmation tokens.")?;
+            } else {
+                restart_double_opt_in(&mut transaction, subscriber_id, &new_subscriber)
+                    .await
+                    .context("Failed to reset an existing subscriber to pending confirmation.")?;
+            }
+            subscriber_id
+        }
+    };
+    add_to_list(&mut transaction, list.list_id, subscriber_id)
         .await
-        .context("Failed to insert new subscriber in the database.")?;
+        .context("Failed to add the subscriber to the list.")?;
 
     let subscription_token = generate_subscription_token();
-    store_token(&mut transaction, subscriber_id, &subscription_token)
-        .await
-        .context("Failed to store the confirmation token for a new subscriber.")?;
+    store_token(
+        &mut transaction,
+        subscriber_id,
+        &subscription_token,
+        subscription_config.confirmation_token_expires_at(),
+    )
+    .await
+    .context("Failed to store the confirmation token for a new subscriber.")?;
 
     transaction
         .commit()
         .await
         .context("Failed to commit SQL transaction to store a new subscriber.")?;
 
-    send_confirmation_email(
-        &email_client,
+    spawn_confirmation_email(
+        email_client,
+        pool,
+        secret,
+        base_url,
         new_subscriber,
-        &base_url.0,
-        &subscription_token,
-    )
-    .await
-    .context("Failed to send a confirmation email.")?;
+        subscription_token,
+    );
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(check_your_inbox(&redirect_url))
+}
+
+/// Tell the visitor a confirmation email is on its way
+pub fn check_your_inbox(redirect_url: &ConfirmationRedirectUrl) -> HttpResponse {
+    redirect_url
+        .redirect("pending_confirmation")
+        .unwrap_or_else(|| {
+            subscription_page(
+                StatusCode::OK,
+                "Check your inbox",
+                "<p>Check your inbox, we sent you a link to confirm your subscription.</p>",
+            )
+        })
+}
+
+/// Render a page of the public subscription flows
+pub fn subscription_page(status: StatusCode, title: &str, content: &str) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    {content}
+    <p><a href="/">Back to the newsletter</a></p>
+</body>
+</html>"#
+        ))
+}
+
+/// Send a confirmation email in the background.
+///
+/// Waiting for the email provider would make the addresses that get an email
+/// answer slower than those that don't, giving away who is subscribed.
+pub fn spawn_confirmation_email(
+    email_client: Data<EmailClient>,
+    pool: Data<PgPool>,
+    secret: Data<HmacSecret>,
+    base_url: Data<ApplicationBaseUrl>,
+    new_subscriber: NewSubscriber,
+    subscription_token: String,
+) {
+    tokio::spawn(
+        async move {
+            if let Err(e) = send_confirmation_email(
+                &email_client,
+                &pool,
+                &secret.0,
+                new_subscriber,
+                &base_url.0,
+                &subscription_token,
+            )
+            .await
+            {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to send a confirmation email."
+                );
+            }
+        }
+        .instrument(tracing::Span::current()),
+    );
 }
 
 #[derive(thiserror::Error)]
@@ -99,6 +232,20 @@ impl ResponseError for SubscribeError {
             Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        let message = match self {
+            Self::ValidationError(e) => encode_minimal(e),
+            Self::UnexpectedError(_) => {
+                "Something went wrong on our side, please try again later.".to_string()
+            }
+        };
+        subscription_page(
+            self.status_code(),
+            "Subscription failed",
+            &format!("<p>{}</p>", message),
+        )
+    }
 }
 
 impl std::fmt::Debug for SubscribeError {
@@ -107,6 +254,8 @@ impl std::fmt::Debug for SubscribeError {
     }
 }
 
+/// Insert the subscriber, returning their id,
+/// or `None` if someone already subscribed with the same email address
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(new_subscriber, transaction)
@@ -114,18 +263,157 @@ impl std::fmt::Debug for SubscribeError {
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
+) -> Result<Option<Uuid>, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
-    sqlx::query!(
+    let inserted = sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        INSERT INTO subscriptions (id, email, email_normalised, name, subscribed_at, status)
+        VALUES ($1, $2, $3, $4, $5, 'pending_confirmation')
+        ON CONFLICT (email_normalised) DO NOTHING
+        RETURNING id
         "#,
         subscriber_id,
         new_subscriber.email.as_ref(),
+        new_subscriber.email.normalised(),
         new_subscriber.name.as_ref(),
         Utc::now()
     )
+    .fetch_optional(transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok(inserted.map(|r| r.id))
+}
+
+/// Add the subscriber to the list, pending their confirmation.
+/// Confirmed memberships are left alone.
+#[tracing::instrument(name = "Add subscriber to list", skip(transaction))]
+async fn add_to_list(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+        VALUES ($1, $2, 'pending_confirmation', now())
+        ON CONFLICT (list_id, subscriber_id) DO NOTHING
+        "#,
+        list_id,
+        subscriber_id
+    )
+    .execute(transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Check list membership", skip(transaction))]
+async fn is_confirmed_member(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let membership = sqlx::query!(
+        r#"
+        SELECT status
+        FROM list_subscriptions
+        WHERE list_id = $1 AND subscriber_id = $2
+        "#,
+        list_id,
+        subscriber_id
+    )
+    .fetch_optional(transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok(matches!(membership, Some(m) if m.status == "confirmed"))
+}
+
+/// Lock the subscriber with the given email address, returning their id and status
+#[tracing::instrument(name = "Get existing subscriber", skip(transaction))]
+async fn get_existing_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<(Uuid, String), sqlx::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT id, status
+        FROM subscriptions
+        WHERE email_normalised = $1
+        FOR UPDATE
+        "#,
+        email.normalised()
+    )
+    .fetch_one(transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok((subscriber.id, subscriber.status))
+}
+
+/// Put a pending or unsubscribed subscriber through double opt-in again,
+/// keeping the address as they just spelled it.
+/// Their previous confirmation tokens are revoked in favour of the one about to be sent.
+#[tracing::instrument(
+    name = "Restart double opt-in for an existing subscriber",
+    skip(transaction, new_subscriber)
+)]
+async fn restart_double_opt_in(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    new_subscriber: &NewSubscriber,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET
+            name = $2,
+            email = $3,
+            status = 'pending_confirmation'
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        new_subscriber.name.as_ref(),
+        new_subscriber.email.as_ref()
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    revoke_confirmation_tokens(transaction, subscriber_id).await
+}
+
+/// Revoke the subscriber's outstanding confirmation links,
+/// leaving alone those confirming a change of email address
+#[tracing::instrument(name = "Revoke confirmation tokens", skip(transaction))]
+pub async fn revoke_confirmation_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM subscription_tokens
+        WHERE subscriber_id = $1 AND new_email IS NULL
+        "#,
+        subscriber_id
+    )
     .execute(transaction)
     .await
     .map_err(|e| {
@@ -133,7 +421,7 @@ pub async fn insert_subscriber(
         e
     })?;
 
-    Ok(subscriber_id)
+    Ok(())
 }
 
 #[tracing::instrument(
@@ -144,14 +432,21 @@ pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
     subscription_token: &str,
+    expires_at: DateTime<Utc>,
 ) -> Result<(), StoreTokenError> {
     sqlx::query!(
         r#"
-        INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-        VALUES ($1, $2)
+        INSERT INTO subscription_tokens (
+            subscription_token,
+            subscriber_id,
+            created_at,
+            expires_at
+        )
+        VALUES ($1, $2, now(), $3)
         "#,
         subscription_token,
-        subscriber_id
+        subscriber_id,
+        expires_at
     )
     .execute(transaction)
     .await
@@ -163,16 +458,26 @@ pub async fn store_token(
     Ok(())
 }
 
+/// Nothing is sent to suppressed addresses
 #[tracing::instrument(
     name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber, base_url)
+    skip(email_client, pool, secret, new_subscriber, base_url)
 )]
 pub async fn send_confirmation_email(
     email_client: &EmailClient,
+    pool: &PgPool,
+    secret: &Secret<String>,
     new_subscriber: NewSubscriber,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
+    if is_suppressed(pool, new_subscriber.email.as_ref(), secret)
+        .await
+        .context("Failed to check whether the address is suppressed.")?
+    {
+        tracing::warn!("Not sending a confirmation email to a suppressed address.");
+        return Ok(());
+    }
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
@@ -189,7 +494,9 @@ pub async fn send_confirmation_email(
     );
     email_client
         .send_email(&new_subscriber.email, "Welcome", &html_body, &plain_body)
-        .await
+        .await?;
+
+    Ok(())
 }
 
 pub struct StoreTokenError(sqlx::Error);
@@ -1,7 +1,16 @@
 use {
-    actix_web::{web, HttpResponse},
+    crate::{
+        domain::normalise_email,
+        routes::{record_preference_change, subscription_page},
+        startup::ConfirmationRedirectUrl,
+        utils::is_unique_violation,
+    },
+    actix_web::{http::StatusCode, web, HttpResponse},
+    anyhow::Context,
+    chrono::{DateTime, Utc},
+    htmlescape::encode_minimal,
     serde::Deserialize,
-    sqlx::PgPool,
+    sqlx::{PgPool, Postgres, Transaction},
     uuid::Uuid,
 };
 
@@ -10,33 +19,237 @@ pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, pool))]
-pub async fn confirm(parameters: web::Query<Parameters>, pool: web::Data<PgPool>) -> HttpResponse {
-    let id = match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
-        Ok(id) => id,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
+/// What a subscription token confirms
+pub struct ConfirmationToken {
+    pub subscriber_id: Uuid,
+    /// Set when the token confirms a change of email address rather than a new subscriber
+    pub new_email: Option<String>,
+    pub expires_at: DateTime<Utc>,
+    /// Tokens can only be used once
+    pub consumed_at: Option<DateTime<Utc>>,
+    pub name: String,
+    pub email: String,
+    /// Slug of a list the subscriber is waiting to join, if any
+    pub list: Option<String>,
+}
+
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(parameters, pool, redirect_url)
+)]
+pub async fn confirm(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    redirect_url: web::Data<ConfirmationRedirectUrl>,
+) -> HttpResponse {
+    let token = match get_confirmation_token(&pool, &parameters.subscription_token).await {
+        Ok(Some(token)) => token,
+        Ok(None) => return invalid_link(&redirect_url),
+        Err(_) => return internal_error_page(),
     };
+    if token.consumed_at.is_some() {
+        return invalid_link(&redirect_url);
+    }
+    if token.expires_at <= Utc::now() {
+        return redirect_url
+            .redirect("expired")
+            .unwrap_or_else(|| expired_link_page(&token));
+    }
+
+    match confirm_with_token(&pool, &parameters.subscription_token, &token).await {
+        Ok(Confirmation::Confirmed) => confirmed(&redirect_url, &token),
+        // Somebody used the token in the meantime
+        Ok(Confirmation::AlreadyUsed) => invalid_link(&redirect_url),
+        Ok(Confirmation::EmailTaken) => email_taken(&redirect_url),
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to confirm");
+            internal_error_page()
+        }
+    }
+}
 
-    match id {
-        None => HttpResponse::Unauthorized().finish(),
-        Some(subscriber_id) => {
-            if confirm_subscriber(&pool, subscriber_id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
+/// Tell the subscriber what their link just confirmed
+fn confirmed(redirect_url: &ConfirmationRedirectUrl, token: &ConfirmationToken) -> HttpResponse {
+    match &token.new_email {
+        None => redirect_url.redirect("confirmed").unwrap_or_else(|| {
+            subscription_page(
+                StatusCode::OK,
+                "Subscription confirmed",
+                "<p>Thanks for confirming your subscription, welcome aboard!</p>",
+            )
+        }),
+        Some(new_email) => redirect_url.redirect("email_changed").unwrap_or_else(|| {
+            subscription_page(
+                StatusCode::OK,
+                "Email address changed",
+                &format!(
+                    "<p>From now on, issues will be sent to {}.</p>",
+                    encode_minimal(new_email)
+                ),
+            )
+        }),
+    }
+}
+
+/// Unknown tokens and tokens used already are treated the same,
+/// offering to send a new link
+fn invalid_link(redirect_url: &ConfirmationRedirectUrl) -> HttpResponse {
+    redirect_url.redirect("invalid").unwrap_or_else(|| {
+        subscription_page(
+            StatusCode::UNAUTHORIZED,
+            "Invalid link",
+            r#"<p>This confirmation link is invalid or has already been used.</p>
+    <form action="/subscriptions/resend" method="post">
+        <label>Email
+            <input type="email" placeholder="Enter your email address" name="email">
+        </label>
+        <button type="submit">Send me a new link</button>
+    </form>"#,
+        )
+    })
+}
+
+/// Another subscriber took the new address after the link was sent
+fn email_taken(redirect_url: &ConfirmationRedirectUrl) -> HttpResponse {
+    redirect_url.redirect("email_taken").unwrap_or_else(|| {
+        subscription_page(
+            StatusCode::CONFLICT,
+            "Address already in use",
+            "<p>That address is already in use by another subscription, \
+             your email address hasn't changed.</p>",
+        )
+    })
+}
+
+fn internal_error_page() -> HttpResponse {
+    subscription_page(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Confirmation failed",
+        "<p>Something went wrong on our side, please try again later.</p>",
+    )
+}
+
+/// Tell the subscriber their link expired, offering to send a new one
+fn expired_link_page(token: &ConfirmationToken) -> HttpResponse {
+    let resend_html = match &token.new_email {
+        // Changes of email address are asked for again from the preferences page
+        Some(_) => "<p>Change your email address again from your preferences \
+                    to get a new link.</p>"
+            .to_string(),
+        // Subscribing to a list again sends a link confirming every list
+        // the subscriber is waiting to join
+        None => format!(
+            r#"<form action="/subscriptions" method="post">
+            <input hidden type="text" name="name" value="{}">
+            <input hidden type="text" name="email" value="{}">
+            <input hidden type="text" name="list" value="{}">
+            <button type="submit">Send me a new link</button>
+        </form>"#,
+            encode_minimal(&token.name),
+            encode_minimal(&token.email),
+            encode_minimal(token.list.as_deref().unwrap_or_default())
+        ),
+    };
+
+    subscription_page(
+        StatusCode::GONE,
+        "Link expired",
+        &format!("<p>This confirmation link has expired.</p>\n    {resend_html}"),
+    )
+}
+
+/// What using a token led to
+enum Confirmation {
+    Confirmed,
+    AlreadyUsed,
+    /// The new email address belongs to another subscriber by now.
+    /// Nothing changed, the token can be used again if the address is freed.
+    EmailTaken,
+}
+
+/// Use up the token and apply what it confirms
+#[tracing::instrument(name = "Confirm with token", skip(pool, subscription_token, token))]
+async fn confirm_with_token(
+    pool: &PgPool,
+    subscription_token: &str,
+    token: &ConfirmationToken,
+) -> Result<Confirmation, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    if !consume_token(&mut transaction, subscription_token).await? {
+        return Ok(Confirmation::AlreadyUsed);
+    }
+    match &token.new_email {
+        None => confirm_subscriber(&mut transaction, token.subscriber_id).await?,
+        Some(new_email) => {
+            if !change_subscriber_email(&mut transaction, token.subscriber_id, new_email).await? {
+                // Dropping the transaction rolls it back
+                return Ok(Confirmation::EmailTaken);
             }
-            HttpResponse::Ok().finish()
         }
     }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscription token.")?;
+
+    Ok(Confirmation::Confirmed)
+}
+
+/// Mark the token as used, unless it already is or has expired
+#[tracing::instrument(skip_all)]
+async fn consume_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE subscription_tokens
+        SET consumed_at = now()
+        WHERE
+            subscription_token = $1 AND
+            consumed_at IS NULL AND
+            expires_at > now()
+        "#,
+        subscription_token
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to consume the subscription token.")?;
+
+    Ok(result.rows_affected() == 1)
 }
 
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+/// Mark the subscriber as confirmed, along with the lists they are waiting to join
+#[tracing::instrument(
+    name = "Mark subscriber as confirmed",
+    skip(subscriber_id, transaction)
+)]
+pub async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
     sqlx::query!(
         r#"
         UPDATE subscriptions SET status = 'confirmed' WHERE id = $1
         "#,
         subscriber_id
     )
-    .execute(pool)
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    sqlx::query!(
+        r#"
+        UPDATE list_subscriptions SET status = 'confirmed' WHERE subscriber_id = $1
+        "#,
+        subscriber_id
+    )
+    .execute(transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
@@ -45,17 +258,73 @@ pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<()
     Ok(())
 }
 
-#[tracing::instrument(
-    name = "Get subscriber's id from token",
-    skip(subscription_token, pool)
-)]
-pub async fn get_subscriber_id_from_token(
+/// Switch the subscriber to the email address they just confirmed,
+/// returning `false` if another subscriber uses the address
+#[tracing::instrument(name = "Change subscriber's email", skip(transaction))]
+pub async fn change_subscriber_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    new_email: &str,
+) -> Result<bool, anyhow::Error> {
+    let old_email = sqlx::query!(
+        r#"
+        SELECT email FROM subscriptions WHERE id = $1 FOR UPDATE
+        "#,
+        subscriber_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to retrieve the subscriber's email.")?
+    .email;
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET email = $2, email_normalised = $3 WHERE id = $1
+        "#,
+        subscriber_id,
+        new_email,
+        normalise_email(new_email)
+    )
+    .execute(&mut *transaction)
+    .await;
+    match result {
+        Ok(_) => {}
+        // Someone subscribed with the address after the link was sent
+        Err(e) if is_unique_violation(&e) => return Ok(false),
+        Err(e) => return Err(e).context("Failed to update the subscriber's email."),
+    }
+    record_preference_change(transaction, subscriber_id, "email", &old_email, new_email).await?;
+
+    Ok(true)
+}
+
+#[tracing::instrument(name = "Get confirmation token", skip(subscription_token, pool))]
+pub async fn get_confirmation_token(
     pool: &PgPool,
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
+) -> Result<Option<ConfirmationToken>, sqlx::Error> {
+    let result = sqlx::query_as!(
+        ConfirmationToken,
         r#"
-        SELECT subscriber_id FROM subscription_tokens
+        SELECT
+            subscription_tokens.subscriber_id,
+            subscription_tokens.new_email,
+            subscription_tokens.expires_at,
+            subscription_tokens.consumed_at,
+            subscriptions.name,
+            subscriptions.email,
+            (
+                SELECT lists.slug
+                FROM list_subscriptions
+                JOIN lists ON lists.list_id = list_subscriptions.list_id
+                WHERE
+                    list_subscriptions.subscriber_id = subscription_tokens.subscriber_id AND
+                    list_subscriptions.status = 'pending_confirmation'
+                ORDER BY list_subscriptions.subscribed_at DESC
+                LIMIT 1
+            ) AS "list?"
+        FROM subscription_tokens
+        JOIN subscriptions ON subscriptions.id = subscription_tokens.subscriber_id
         WHERE subscription_token = $1
         "#,
         subscription_token
@@ -67,5 +336,5 @@ pub async fn get_subscriber_id_from_token(
         e
     })?;
 
-    Ok(result.map(|r| r.subscriber_id))
+    Ok(result)
 }
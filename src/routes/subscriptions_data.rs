@@ -0,0 +1,298 @@
+use {
+    crate::{
+        configuration::SubscriptionSettings,
+        domain::{SubscriberEmail, SubscriberToken, TokenPurpose},
+        email_client::EmailClient,
+        routes::{record_resend_attempt, subscription_page, SubscribeError},
+        startup::{ApplicationBaseUrl, HmacSecret},
+        subscriber_data::{collect_subscriber_data, erase_subscriber},
+        suppressions::is_suppressed,
+        utils::e500,
+    },
+    actix_web::{
+        http::{
+            header::{ContentDisposition, ContentType, DispositionParam, DispositionType},
+            StatusCode,
+        },
+        web, HttpRequest, HttpResponse,
+    },
+    anyhow::Context,
+    secrecy::Secret,
+    serde::Deserialize,
+    sqlx::{PgPool, Postgres, Transaction},
+    tracing::Instrument,
+    uuid::Uuid,
+};
+
+const REQUEST_FORM: &str = r#"<form action="/subscriptions/data" method="post">
+        <label>Email
+            <input type="email" name="email" placeholder="Enter the address you subscribed with">
+        </label>
+        <button type="submit">Email me a link</button>
+    </form>"#;
+
+#[derive(Deserialize)]
+pub struct DataParameters {
+    token: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DataTokenParameters {
+    token: String,
+}
+
+#[derive(Deserialize)]
+pub struct DataRequestFormData {
+    email: String,
+}
+
+/// Without a token, ask for the address to send a link to.
+/// With the token of that link, offer to download or erase the subscriber's data.
+#[tracing::instrument(name = "Show the subscriber data page", skip(parameters, secret))]
+pub async fn data_page(
+    parameters: web::Query<DataParameters>,
+    secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    let token = match &parameters.token {
+        Some(token) => token,
+        None => {
+            return subscription_page(
+                StatusCode::OK,
+                "Your data",
+                &format!(
+                    "<p>We can send you a link to download everything we store about you, \
+                     or to have it erased.</p>\n    {}",
+                    REQUEST_FORM
+                ),
+            )
+        }
+    };
+    if SubscriberToken::verify_expiring(TokenPurpose::ManageData, token, &secret.0).is_err() {
+        return invalid_link();
+    }
+    let token = urlencoding::encode(token);
+
+    subscription_page(
+        StatusCode::OK,
+        "Your data",
+        &format!(
+            r#"<p><a href="/subscriptions/data/download?token={token}">Download your data</a></p>
+    <p>Erasing your data unsubscribes you from every list, and can't be undone.</p>
+    <form action="/subscriptions/data/erase?token={token}" method="post">
+        <button type="submit">Erase my data</button>
+    </form>"#
+        ),
+    )
+}
+
+/// Email a link to the subscriber's data.
+///
+/// The response doesn't tell whether the address is subscribed at all,
+/// neither by its content nor by how long it takes,
+/// and requests are limited like confirmation emails.
+#[tracing::instrument(
+    name = "Send a link to a subscriber's data",
+    skip(form, request, pool, email_client, base_url, subscription_config, secret),
+    fields(subscriber_email = %form.email)
+)]
+pub async fn request_data_link(
+    form: web::Form<DataRequestFormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    subscription_config: web::Data<SubscriptionSettings>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, SubscribeError> {
+    let email = SubscriberEmail::parse(form.0.email).map_err(SubscribeError::ValidationError)?;
+    let ip = request
+        .connection_info()
+        .realip_remote_addr()
+        .unwrap_or("unknown")
+        .to_owned();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    if !record_resend_attempt(&mut transaction, &email, &ip, &subscription_config)
+        .await
+        .context("Failed to record a request for a subscriber's data.")?
+    {
+        return Ok(subscription_page(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many attempts",
+            "<p>We already sent you several links, please check your inbox or try again later.</p>",
+        ));
+    }
+    let subscriber_id = get_subscriber_id(&mut transaction, &email)
+        .await
+        .context("Failed to look up the subscriber.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to request a subscriber's data.")?;
+
+    if let Some(subscriber_id) = subscriber_id {
+        let token = SubscriberToken::generate_expiring(
+            TokenPurpose::ManageData,
+            subscriber_id,
+            subscription_config.data_request_expires_at(),
+            &secret.0,
+        );
+        // Sent in the background, like confirmation emails,
+        // so that known addresses don't take longer to answer
+        tokio::spawn(
+            async move {
+                if let Err(e) =
+                    send_data_link(&email_client, &pool, &secret.0, &email, &base_url.0, &token)
+                        .await
+                {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to send a link to a subscriber's data."
+                    );
+                }
+            }
+            .instrument(tracing::Span::current()),
+        );
+    }
+
+    Ok(subscription_page(
+        StatusCode::OK,
+        "Check your inbox",
+        "<p>If this address is subscribed, we sent it a link to your data.</p>",
+    ))
+}
+
+/// Download everything stored about the subscriber, as JSON
+#[tracing::instrument(name = "Download a subscriber's data", skip(parameters, pool, secret))]
+pub async fn download_data(
+    parameters: web::Query<DataTokenParameters>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = match SubscriberToken::verify_expiring(
+        TokenPurpose::ManageData,
+        &parameters.token,
+        &secret.0,
+    ) {
+        Ok(subscriber_id) => subscriber_id,
+        Err(_) => return Ok(invalid_link()),
+    };
+    let data = match collect_subscriber_data(&pool, subscriber_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(data) => data,
+        None => return Ok(invalid_link()),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("subscriber-data.json".into())],
+        })
+        .body(data.to_string()))
+}
+
+/// Erase everything stored about the subscriber, but a hash of their address
+/// that keeps them from being imported again
+#[tracing::instrument(name = "Erase a subscriber's data", skip(parameters, pool, secret))]
+pub async fn erase_data(
+    parameters: web::Query<DataTokenParameters>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = match SubscriberToken::verify_expiring(
+        TokenPurpose::ManageData,
+        &parameters.token,
+        &secret.0,
+    ) {
+        Ok(subscriber_id) => subscriber_id,
+        Err(_) => return Ok(invalid_link()),
+    };
+    if !erase_subscriber(&pool, subscriber_id, &secret.0)
+        .await
+        .map_err(e500)?
+    {
+        return Ok(invalid_link());
+    }
+
+    Ok(subscription_page(
+        StatusCode::OK,
+        "Data erased",
+        "<p>Your data has been erased, you won't hear from us again.</p>",
+    ))
+}
+
+/// The link expired, was tampered with, or its subscriber was erased already
+fn invalid_link() -> HttpResponse {
+    subscription_page(
+        StatusCode::UNAUTHORIZED,
+        "Invalid link",
+        &format!(
+            "<p>This link is invalid or has expired, you can ask for a new one.</p>\n    {}",
+            REQUEST_FORM
+        ),
+    )
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn get_subscriber_id(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT id
+        FROM subscriptions
+        WHERE email_normalised = $1
+        "#,
+        email.normalised()
+    )
+    .fetch_optional(transaction)
+    .await?;
+
+    Ok(subscriber.map(|s| s.id))
+}
+
+#[tracing::instrument(skip(email_client, pool, secret, base_url, token))]
+async fn send_data_link(
+    email_client: &EmailClient,
+    pool: &PgPool,
+    secret: &Secret<String>,
+    email: &SubscriberEmail,
+    base_url: &str,
+    token: &SubscriberToken,
+) -> Result<(), anyhow::Error> {
+    if is_suppressed(pool, email.as_ref(), secret)
+        .await
+        .context("Failed to check whether the address is suppressed.")?
+    {
+        tracing::warn!("Not sending a data link to a suppressed address.");
+        return Ok(());
+    }
+    let link = format!(
+        "{}/subscriptions/data?token={}",
+        base_url,
+        urlencoding::encode(token.as_ref())
+    );
+    let plain_body = format!(
+        "Visit {} to download or erase the data we store about you.\n\
+         The link only works for a while, you can ask for a new one on the same page.",
+        link
+    );
+    let html_body = format!(
+        "Click <a href=\"{}\">here</a> to download or erase the data we store about you.<br />\
+         The link only works for a while, you can ask for a new one on the same page.",
+        link
+    );
+    email_client
+        .send_email(email, "Your data", &html_body, &plain_body)
+        .await?;
+
+    Ok(())
+}
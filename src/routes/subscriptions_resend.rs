@@ -0,0 +1,203 @@
+use {
+    crate::{
+        configuration::SubscriptionSettings,
+        domain::{NewSubscriber, SubscriberEmail, SubscriberName},
+        email_client::EmailClient,
+        routes::{
+            check_your_inbox, generate_subscription_token, revoke_confirmation_tokens,
+            spawn_confirmation_email, store_token, subscription_page, SubscribeError,
+        },
+        startup::{ApplicationBaseUrl, ConfirmationRedirectUrl, HmacSecret},
+    },
+    actix_web::{
+        http::StatusCode,
+        web::{Data, Form},
+        HttpRequest, HttpResponse,
+    },
+    anyhow::Context,
+    chrono::Utc,
+    serde::Deserialize,
+    sqlx::{PgPool, Postgres, Transaction},
+    uuid::Uuid,
+};
+
+#[derive(Deserialize)]
+pub struct ResendFormData {
+    email: String,
+}
+
+/// Send a new confirmation email to a pending subscriber.
+///
+/// The response doesn't tell whether the address is subscribed at all,
+/// neither by its content nor by how long it takes.
+/// Attempts are limited per address and per IP, whether or not the address exists,
+/// so that the endpoint can't be used to flood someone's inbox.
+#[tracing::instrument(
+    name = "Resend a confirmation email",
+    skip(
+        form,
+        request,
+        pool,
+        email_client,
+        base_url,
+        subscription_config,
+        redirect_url,
+        secret
+    ),
+    fields(subscriber_email = %form.email)
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn resend_confirmation(
+    form: Form<ResendFormData>,
+    request: HttpRequest,
+    pool: Data<PgPool>,
+    email_client: Data<EmailClient>,
+    base_url: Data<ApplicationBaseUrl>,
+    subscription_config: Data<SubscriptionSettings>,
+    redirect_url: Data<ConfirmationRedirectUrl>,
+    secret: Data<HmacSecret>,
+) -> Result<HttpResponse, SubscribeError> {
+    let email = SubscriberEmail::parse(form.0.email).map_err(SubscribeError::ValidationError)?;
+    // Forwarding headers can be spoofed to dodge the limit per IP,
+    // the limit per address still protects inboxes
+    let ip = request
+        .connection_info()
+        .realip_remote_addr()
+        .unwrap_or("unknown")
+        .to_owned();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    if !record_resend_attempt(&mut transaction, &email, &ip, &subscription_config)
+        .await
+        .context("Failed to record an attempt to resend a confirmation email.")?
+    {
+        return Ok(subscription_page(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many attempts",
+            "<p>We already sent you several links, please check your inbox or try again later.</p>",
+        ));
+    }
+
+    let pending_subscriber = get_pending_subscriber(&mut transaction, &email)
+        .await
+        .context("Failed to retrieve a pending subscriber.")?;
+    let subscription_token = match &pending_subscriber {
+        Some((subscriber_id, _)) => {
+            revoke_confirmation_tokens(&mut transaction, *subscriber_id)
+                .await
+                .context("Failed to revoke previous confirmation tokens.")?;
+            let subscription_token = generate_subscription_token();
+            store_token(
+                &mut transaction,
+                *subscriber_id,
+                &subscription_token,
+                subscription_config.confirmation_token_expires_at(),
+            )
+            .await
+            .context("Failed to store a new confirmation token.")?;
+            Some(subscription_token)
+        }
+        None => None,
+    };
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to resend a confirmation email.")?;
+
+    if let (Some((_, name)), Some(subscription_token)) = (pending_subscriber, subscription_token) {
+        let name = SubscriberName::parse(name).map_err(|e| anyhow::anyhow!(e))?;
+        spawn_confirmation_email(
+            email_client,
+            pool,
+            secret,
+            base_url,
+            NewSubscriber { email, name },
+            subscription_token,
+        );
+    }
+
+    Ok(check_your_inbox(&redirect_url))
+}
+
+/// Record the attempt, unless the address or the IP already used up their attempts.
+/// Returns whether the attempt is allowed.
+///
+/// Links to a subscriber's data count towards the same limit,
+/// as both send an email to whatever address is entered.
+#[tracing::instrument(skip(transaction, subscription_config))]
+pub async fn record_resend_attempt(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+    ip: &str,
+    subscription_config: &SubscriptionSettings,
+) -> Result<bool, anyhow::Error> {
+    let window_start =
+        Utc::now() - chrono::Duration::from_std(subscription_config.resend_window())?;
+    let attempts = sqlx::query!(
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE email = $1) AS "by_email!",
+            COUNT(*) FILTER (WHERE ip = $2) AS "by_ip!"
+        FROM subscription_resend_attempts
+        WHERE requested_at > $3
+        "#,
+        email.normalised(),
+        ip,
+        window_start
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+    let max_attempts = i64::from(subscription_config.resend_max_attempts);
+    if attempts.by_email >= max_attempts || attempts.by_ip >= max_attempts {
+        tracing::warn!("Too many attempts to resend a confirmation email.");
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_resend_attempts (attempt_id, email, ip, requested_at)
+        VALUES ($1, $2, $3, now())
+        "#,
+        Uuid::new_v4(),
+        email.normalised(),
+        ip
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(true)
+}
+
+/// Lock the subscriber with the given address if they are waiting to join a list,
+/// returning their id and name.
+/// Confirmed subscribers can be waiting to join another list.
+#[tracing::instrument(skip(transaction))]
+async fn get_pending_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Option<(Uuid, String)>, sqlx::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT id, name
+        FROM subscriptions
+        WHERE
+            email_normalised = $1 AND
+            EXISTS (
+                SELECT 1
+                FROM list_subscriptions
+                WHERE
+                    list_subscriptions.subscriber_id = subscriptions.id AND
+                    list_subscriptions.status = 'pending_confirmation'
+            )
+        FOR UPDATE
+        "#,
+        email.normalised()
+    )
+    .fetch_optional(transaction)
+    .await?;
+
+    Ok(subscriber.map(|s| (s.id, s.name)))
+}
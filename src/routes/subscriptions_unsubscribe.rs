@@ -0,0 +1,242 @@
+use {
+    crate::{
+        domain::{SubscriberToken, TokenPurpose},
+        routes::subscription_page,
+        startup::HmacSecret,
+    },
+    actix_web::{http::StatusCode, web, HttpResponse},
+    htmlescape::encode_minimal,
+    secrecy::Secret,
+    serde::Deserialize,
+    sqlx::PgPool,
+    uuid::Uuid,
+};
+
+#[derive(Deserialize)]
+pub struct Parameters {
+    token: String,
+}
+
+/// What an unsubscribe link stops
+enum Unsubscription {
+    /// The list the issue was sent on behalf of
+    List { subscriber_id: Uuid, list_id: Uuid },
+    /// Every list, as links sent before issues went out on behalf of lists did
+    Everything { subscriber_id: Uuid },
+}
+
+impl Unsubscription {
+    fn verify(token: &str, secret: &Secret<String>) -> Option<Self> {
+        if let Ok((subscriber_id, list_id)) =
+            SubscriberToken::verify_for_list(TokenPurpose::Unsubscribe, token, secret)
+        {
+            return Some(Unsubscription::List {
+                subscriber_id,
+                list_id,
+            });
+        }
+        SubscriberToken::verify(TokenPurpose::Unsubscribe, token, secret)
+            .ok()
+            .map(|subscriber_id| Unsubscription::Everything { subscriber_id })
+    }
+
+    fn subscriber_id(&self) -> Uuid {
+        match self {
+            Unsubscription::List { subscriber_id, .. } => *subscriber_id,
+            Unsubscription::Everything { subscriber_id } => *subscriber_id,
+        }
+    }
+}
+
+/// Ask for a confirmation first, so that link scanners
+/// opening every link of an email don't unsubscribe anyone.
+/// Links for a list also offer to unsubscribe from everything.
+#[tracing::instrument(
+    name = "Show the unsubscribe confirmation page",
+    skip(parameters, pool, secret)
+)]
+pub async fn unsubscribe_form(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    let unsubscription = match Unsubscription::verify(&parameters.token, &secret.0) {
+        Some(unsubscription) => unsubscription,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+    let token = urlencoding::encode(&parameters.token);
+    let body = match unsubscription {
+        Unsubscription::List { list_id, .. } => {
+            let list_name = match get_list_name(&pool, list_id).await {
+                Ok(list_name) => list_name,
+                Err(_) => return HttpResponse::InternalServerError().finish(),
+            };
+            format!(
+                r#"<p>Do you really want to stop receiving {}?</p>
+    <form action="/subscriptions/unsubscribe?token={token}" method="post">
+        <button type="submit">Unsubscribe</button>
+    </form>
+    <form action="/subscriptions/unsubscribe/all?token={token}" method="post">
+        <button type="submit">Unsubscribe from all our newsletters</button>
+    </form>"#,
+                encode_minimal(&list_name)
+            )
+        }
+        Unsubscription::Everything { .. } => format!(
+            r#"<p>Do you really want to stop receiving our newsletter?</p>
+    <form action="/subscriptions/unsubscribe?token={token}" method="post">
+        <button type="submit">Unsubscribe</button>
+    </form>"#
+        ),
+    };
+
+    subscription_page(StatusCode::OK, "Unsubscribe", &body)
+}
+
+/// Unsubscribe from the confirmation page, or in one click from the mail client
+/// through `List-Unsubscribe-Post` (RFC 8058).
+/// Links for a list only unsubscribe from that list.
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(parameters, pool, secret))]
+pub async fn unsubscribe(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    let unsubscription = match Unsubscription::verify(&parameters.token, &secret.0) {
+        Some(unsubscription) => unsubscription,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+    let body = match unsubscription {
+        Unsubscription::List {
+            subscriber_id,
+            list_id,
+        } => {
+            let list_name = match get_list_name(&pool, list_id).await {
+                Ok(list_name) => list_name,
+                Err(_) => return HttpResponse::InternalServerError().finish(),
+            };
+            if unsubscribe_from_list(&pool, subscriber_id, list_id)
+                .await
+                .is_err()
+            {
+                return HttpResponse::InternalServerError().finish();
+            }
+            format!(
+                "<p>You have been unsubscribed, you won't receive {} anymore.</p>",
+                encode_minimal(&list_name)
+            )
+        }
+        Unsubscription::Everything { subscriber_id } => {
+            if unsubscribe_subscriber(&pool, subscriber_id).await.is_err() {
+                return HttpResponse::InternalServerError().finish();
+            }
+            "<p>You have been unsubscribed, you won't receive our newsletter anymore.</p>".into()
+        }
+    };
+
+    subscription_page(StatusCode::OK, "Unsubscribed", &body)
+}
+
+/// Unsubscribe from every list, whichever list the link was sent for
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber from everything",
+    skip(parameters, pool, secret)
+)]
+pub async fn unsubscribe_from_everything(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    let subscriber_id = match Unsubscription::verify(&parameters.token, &secret.0) {
+        Some(unsubscription) => unsubscription.subscriber_id(),
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+    if unsubscribe_subscriber(&pool, subscriber_id).await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    subscription_page(
+        StatusCode::OK,
+        "Unsubscribed",
+        "<p>You have been unsubscribed, you won't receive any of our newsletters anymore.</p>",
+    )
+}
+
+/// Name of the list, or a generic one if the list was deleted since
+#[tracing::instrument(skip(pool))]
+async fn get_list_name(pool: &PgPool, list_id: Uuid) -> Result<String, sqlx::Error> {
+    let list = sqlx::query!(
+        r#"
+        SELECT name
+        FROM lists
+        WHERE list_id = $1
+        "#,
+        list_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok(list.map_or_else(|| "this newsletter".into(), |l| l.name))
+}
+
+/// Drop the subscriber from a single list, which is a no-op if they already left it
+#[tracing::instrument(name = "Remove subscriber from a list", skip(pool))]
+async fn unsubscribe_from_list(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM list_subscriptions
+        WHERE
+            subscriber_id = $1 AND
+            list_id = $2
+        "#,
+        subscriber_id,
+        list_id
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok(())
+}
+
+/// Mark the subscriber as unsubscribed and drop them from every list,
+/// which is a no-op if they already are
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(pool))]
+pub async fn unsubscribe_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        WITH unsubscribed AS (
+            UPDATE subscriptions
+            SET
+                status = 'unsubscribed',
+                unsubscribed_at = now()
+            WHERE
+                id = $1 AND
+                status <> 'unsubscribed'
+            RETURNING id
+        )
+        DELETE FROM list_subscriptions
+        WHERE subscriber_id IN (SELECT id FROM unsubscribed)
+        "#,
+        subscriber_id
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok(())
+}
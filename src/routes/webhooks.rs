@@ -0,0 +1,200 @@
+use {
+    crate::{
+        authentication::basic_authentication,
+        bounces::{record_bounce, Bounce, BounceKind},
+        configuration::WebhookSettings,
+        routes::error_chain_fmt,
+    },
+    actix_web::{
+        http::header::{self, HeaderValue},
+        web, HttpRequest, HttpResponse, ResponseError,
+    },
+    chrono::{DateTime, Utc},
+    hmac::{Hmac, Mac},
+    reqwest::StatusCode,
+    secrecy::ExposeSecret,
+    sha2::Sha256,
+    sqlx::PgPool,
+};
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("{0}")]
+    InvalidPayload(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for WebhookError {
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        match self {
+            WebhookError::InvalidPayload(_) => HttpResponse::new(StatusCode::BAD_REQUEST),
+            WebhookError::UnexpectedError(_) => {
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+            WebhookError::AuthError(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                let header_value = HeaderValue::from_str(r#"Basic realm="webhooks""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, header_value);
+
+                response
+            }
+        }
+    }
+}
+
+/// The fields we use of Postmark's bounce and spam complaint webhooks,
+/// which share the same payload
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkBounce {
+    #[serde(rename = "ID")]
+    id: u64,
+    #[serde(rename = "Type")]
+    bounce_type: String,
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+    email: String,
+    #[serde(default)]
+    description: String,
+    bounced_at: Option<String>,
+}
+
+impl PostmarkBounce {
+    /// How the report is handled, `None` for reports that aren't about undeliverable
+    /// emails, like auto-responders
+    fn kind(&self) -> Option<BounceKind> {
+        match self.bounce_type.as_str() {
+            "HardBounce" | "BadEmailAddress" | "ManuallyDeactivated" => Some(BounceKind::Hard),
+            "SoftBounce" | "Transient" | "DnsError" => Some(BounceKind::Soft),
+            "SpamComplaint" => Some(BounceKind::SpamComplaint),
+            _ => None,
+        }
+    }
+
+    fn into_bounce(self, kind: BounceKind) -> Bounce {
+        // An odd date isn't worth losing the report over
+        let bounced_at = self
+            .bounced_at
+            .and_then(|bounced_at| DateTime::parse_from_rfc3339(&bounced_at).ok())
+            .map(|bounced_at| bounced_at.with_timezone(&Utc))
+            .unwrap_or_else(Utc::now);
+
+        Bounce {
+            provider_bounce_id: self.id.to_string(),
+            email: self.email,
+            kind,
+            provider_message_id: self.message_id,
+            description: self.description,
+            bounced_at,
+        }
+    }
+}
+
+/// Receive Postmark's bounce webhook
+#[tracing::instrument(
+    name = "Receive a bounce webhook",
+    skip(body, request, pool, webhook_config)
+)]
+pub async fn postmark_bounce(
+    body: web::Bytes,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    webhook_config: web::Data<WebhookSettings>,
+) -> Result<HttpResponse, WebhookError> {
+    let payload = authenticate_and_parse(&body, &request, &webhook_config)?;
+    match payload.kind() {
+        Some(kind) => handle_bounce(payload.into_bounce(kind), &pool, &webhook_config).await?,
+        None => tracing::info!(
+            bounce_type = %payload.bounce_type,
+            "Ignoring a report that isn't about an undeliverable email."
+        ),
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Receive Postmark's spam complaint webhook
+#[tracing::instrument(
+    name = "Receive a spam complaint webhook",
+    skip(body, request, pool, webhook_config)
+)]
+pub async fn postmark_complaint(
+    body: web::Bytes,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    webhook_config: web::Data<WebhookSettings>,
+) -> Result<HttpResponse, WebhookError> {
+    let payload = authenticate_and_parse(&body, &request, &webhook_config)?;
+    handle_bounce(
+        payload.into_bounce(BounceKind::SpamComplaint),
+        &pool,
+        &webhook_config,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Check the credentials before looking at the body,
+/// so that anonymous requests learn nothing about the payload we expect
+fn authenticate_and_parse(
+    body: &[u8],
+    request: &HttpRequest,
+    webhook_config: &WebhookSettings,
+) -> Result<PostmarkBounce, WebhookError> {
+    let credentials = basic_authentication(request.headers()).map_err(WebhookError::AuthError)?;
+    let username_matches = constant_time_eq(&credentials.username, &webhook_config.username);
+    let password_matches = constant_time_eq(
+        credentials.password.expose_secret(),
+        webhook_config.password.expose_secret(),
+    );
+    if !(username_matches && password_matches) {
+        return Err(WebhookError::AuthError(anyhow::anyhow!(
+            "Invalid webhook credentials."
+        )));
+    }
+
+    serde_json::from_slice(body).map_err(|e| WebhookError::InvalidPayload(e.to_string()))
+}
+
+async fn handle_bounce(
+    bounce: Bounce,
+    pool: &PgPool,
+    webhook_config: &WebhookSettings,
+) -> Result<(), anyhow::Error> {
+    let outcome = record_bounce(pool, &bounce, webhook_config.soft_bounce_threshold).await?;
+    tracing::info!(
+        kind = bounce.kind.as_str(),
+        outcome = ?outcome,
+        "Recorded a bounce."
+    );
+
+    Ok(())
+}
+
+/// Compare secrets in constant time, so that response times don't give them away
+fn constant_time_eq(candidate: &str, expected: &str) -> bool {
+    // Tags have the same length whatever the secrets are,
+    // and `verify_slice` compares them in constant time
+    let tag = |key: &str| {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())
+            .expect("HMAC can take a key of any size");
+        mac.update(b"webhook credentials");
+        mac
+    };
+
+    tag(expected)
+        .verify_slice(&tag(candidate).finalize().into_bytes())
+        .is_ok()
+}
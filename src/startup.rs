@@ -1,18 +1,26 @@
 use {
     crate::{
         authentication::reject_anonymous_users,
-        configuration::{DatabaseSettings, Settings},
+        configuration::{DatabaseSettings, Settings, SubscriptionSettings, WebhookSettings},
         email_client::EmailClient,
         routes::{
-            admin_dashboard, change_password, change_password_form, confirm, health_check, home,
-            log_out, login_form, login_submit, publish_issue, publish_issue_form,
-            publish_newsletter, subscribe,
+            add_list, add_topic, admin_dashboard, attributes_form, change_password,
+            change_password_form, confirm, data_page, delivery_failures, download_data, erase_data,
+            export_subscribers, health_check, home, import_form, import_subscribers,
+            issue_progress, lists_form, log_out, login_form, login_submit, postmark_bounce,
+            postmark_complaint, preferences_form, publish_issue, publish_issue_form,
+            publish_newsletter, request_data_link, requeue_delivery_failures, resend_confirmation,
+            set_attribute, subscribe, subscribers_form, suppressions_form, tag_subscriber,
+            tags_form, topics_form, unsubscribe, unsubscribe_form, unsubscribe_from_everything,
+            update_preferences, update_suppressions, IMPORT_FORM_LIMIT,
         },
+        utils::see_other,
     },
     actix_session::{storage::RedisSessionStore, SessionMiddleware},
-    actix_web::{cookie::Key, dev::Server, web, App, HttpServer},
+    actix_web::{cookie::Key, dev::Server, web, App, HttpResponse, HttpServer},
     actix_web_flash_messages::{storage::CookieMessageStore, FlashMessagesFramework},
     actix_web_lab::middleware::from_fn,
+    anyhow::Context,
     secrecy::{ExposeSecret, Secret},
     sqlx::{postgres::PgPoolOptions, PgPool},
     std::net::TcpListener,
@@ -38,6 +46,13 @@ impl Application {
         let port = listener.local_addr().unwrap().port();
         let hmac_secret = app_config.application.hmac_secret;
         let redis_uri = app_config.redis_uri;
+        let confirmation_redirect_url = app_config
+            .application
+            .confirmation_redirect_url
+            .as_deref()
+            .map(reqwest::Url::parse)
+            .transpose()
+            .context("Invalid confirmation redirect URL.")?;
         let server = run(
             listener,
             db_pool,
@@ -45,6 +60,9 @@ impl Application {
             base_url,
             hmac_secret,
             redis_uri,
+            app_config.subscriptions,
+            app_config.webhooks,
+            confirmation_redirect_url,
         )
         .await?;
 
@@ -62,7 +80,22 @@ impl Application {
 
 pub struct ApplicationBaseUrl(pub String);
 
+/// Optional page to send visitors to at the end of the subscription flows
+pub struct ConfirmationRedirectUrl(pub Option<reqwest::Url>);
+
+impl ConfirmationRedirectUrl {
+    /// Redirect to the configured page, if any, telling it the outcome of the flow
+    pub fn redirect(&self, status: &str) -> Option<HttpResponse> {
+        self.0.as_ref().map(|url| {
+            let mut url = url.clone();
+            url.query_pairs_mut().append_pair("status", status);
+            see_other(url.as_str())
+        })
+    }
+}
+
 /// Run http server with user settings
+#[allow(clippy::too_many_arguments)]
 async fn run(
     listener: TcpListener,
     db_pool: PgPool,
@@ -70,11 +103,18 @@ async fn run(
     base_url: String,
     hmac_secret: Secret<String>,
     redis_uri: Secret<String>,
+    subscription_config: SubscriptionSettings,
+    webhook_config: WebhookSettings,
+    confirmation_redirect_url: Option<reqwest::Url>,
 ) -> Result<Server, anyhow::Error> {
     // `web::Data` is basically an `Arc`, which will safely share the app state across threads
     let db_pool = web::Data::new(db_pool);
     let email_client = web::Data::new(email_client);
     let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let subscription_config = web::Data::new(subscription_config);
+    let webhook_config = web::Data::new(webhook_config);
+    let confirmation_redirect_url =
+        web::Data::new(ConfirmationRedirectUrl(confirmation_redirect_url));
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
     // Storing secured flash message
     let message_store = CookieMessageStore::builder(secret_key.clone()).build();
@@ -96,20 +136,71 @@ async fn run(
             .route("/health_check", web::get().to(health_check))
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route("/subscriptions/data", web::get().to(data_page))
+            .route("/subscriptions/data", web::post().to(request_data_link))
+            .route("/subscriptions/data/download", web::get().to(download_data))
+            .route("/subscriptions/data/erase", web::post().to(erase_data))
+            .route("/subscriptions/resend", web::post().to(resend_confirmation))
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route(
+                "/subscriptions/unsubscribe/all",
+                web::post().to(unsubscribe_from_everything),
+            )
+            .route("/preferences", web::get().to(preferences_form))
+            .route("/preferences", web::post().to(update_preferences))
             .service(
                 web::scope("/admin")
                     .wrap(from_fn(reject_anonymous_users))
                     .route("/dashboard", web::get().to(admin_dashboard))
+                    .route("/attributes", web::get().to(attributes_form))
+                    .route("/attributes", web::post().to(set_attribute))
+                    .service(
+                        web::resource("/import")
+                            .app_data(web::FormConfig::default().limit(IMPORT_FORM_LIMIT))
+                            .route(web::get().to(import_form))
+                            .route(web::post().to(import_subscribers)),
+                    )
+                    .route("/issues/{issue_id}", web::get().to(issue_progress))
                     .route("/newsletter", web::get().to(publish_issue_form))
                     .route("/newsletter", web::post().to(publish_issue))
+                    .route(
+                        "/newsletter/{issue_id}/failures",
+                        web::get().to(delivery_failures),
+                    )
+                    .route(
+                        "/newsletter/{issue_id}/failures/requeue",
+                        web::post().to(requeue_delivery_failures),
+                    )
+                    .route("/lists", web::get().to(lists_form))
+                    .route("/lists", web::post().to(add_list))
+                    .route("/subscribers", web::get().to(subscribers_form))
+                    .route("/subscribers/export", web::get().to(export_subscribers))
+                    .route("/suppressions", web::get().to(suppressions_form))
+                    .route("/suppressions", web::post().to(update_suppressions))
+                    .route("/tags", web::get().to(tags_form))
+                    .route("/tags", web::post().to(tag_subscriber))
+                    .route("/topics", web::get().to(topics_form))
+                    .route("/topics", web::post().to(add_topic))
                     .route("/password", web::get().to(change_password_form))
                     .route("/password", web::post().to(change_password))
                     .route("/logout", web::post().to(log_out)),
             )
             .route("/newsletters", web::post().to(publish_newsletter))
+            .route("/webhooks/postmark/bounce", web::post().to(postmark_bounce))
+            .route(
+                "/webhooks/postmark/complaint",
+                web::post().to(postmark_complaint),
+            )
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(subscription_config.clone())
+            .app_data(webhook_config.clone())
+            .app_data(confirmation_redirect_url.clone())
             .app_data(web::Data::new(HmacSecret(hmac_secret.clone())))
     })
     .listen(listener)?
@@ -0,0 +1,503 @@
+use {
+    crate::domain::normalise_email,
+    anyhow::Context,
+    chrono::{DateTime, SecondsFormat, Utc},
+    hmac::{Hmac, Mac},
+    secrecy::{ExposeSecret, Secret},
+    serde_json::{json, Value},
+    sha2::Sha256,
+    sqlx::{PgPool, Postgres, Transaction},
+    uuid::Uuid,
+};
+
+/// Keyed hash of an email address, which is all that is kept of erased subscribers.
+///
+/// Addresses are compared in their normalised form. Hashes depend on the application's
+/// secret, changing it means erased subscribers can be imported again.
+pub fn email_hash(email: &str, secret: &Secret<String>) -> String {
+    hash(&normalise_email(email), secret)
+}
+
+/// Every hash an erased address may be stored under, to look it up with.
+///
+/// Addresses erased before internationalised domains were converted to punycode
+/// were hashed lower-cased only, and a hash can't be converted afterwards.
+pub fn email_hashes(email: &str, secret: &Secret<String>) -> Vec<String> {
+    let legacy_form = email.trim().to_lowercase();
+    if legacy_form == normalise_email(email) {
+        vec![email_hash(email, secret)]
+    } else {
+        vec![email_hash(email, secret), hash(&legacy_form, secret)]
+    }
+}
+
+fn hash(normalised_email: &str, secret: &Secret<String>) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(b"erased:");
+    mac.update(normalised_email.as_bytes());
+
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Whether the subscriber with this address asked for their data to be erased
+#[tracing::instrument(skip(transaction, secret))]
+pub async fn is_erased(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+    secret: &Secret<String>,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT erased_at
+        FROM erased_subscribers
+        WHERE email_hash = ANY($1)
+        "#,
+        &email_hashes(email, secret)
+    )
+    .fetch_optional(transaction)
+    .await?;
+
+    Ok(row.is_some())
+}
+
+fn timestamp(timestamp: DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+/// Everything stored about a subscriber, as a JSON document they can download,
+/// or `None` if there is no such subscriber.
+///
+/// Confirmation tokens are listed without their value, which only matters to us.
+#[tracing::instrument(skip(pool))]
+pub async fn collect_subscriber_data(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<Value>, anyhow::Error> {
+    let subscriber = match sqlx::query!(
+        r#"
+        SELECT
+            id,
+            email,
+            email_normalised,
+            name,
+            status,
+            subscribed_at,
+            unsubscribed_at,
+            attributes
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the subscriber.")?
+    {
+        Some(subscriber) => subscriber,
+        None => return Ok(None),
+    };
+
+    let tokens: Vec<_> = sqlx::query!(
+        r#"
+        SELECT created_at, expires_at, consumed_at, new_email
+        FROM subscription_tokens
+        WHERE subscriber_id = $1
+        ORDER BY created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the subscriber's tokens.")?
+    .into_iter()
+    .map(|r| {
+        json!({
+            "created_at": timestamp(r.created_at),
+            "expires_at": timestamp(r.expires_at),
+            "consumed_at": r.consumed_at.map(timestamp),
+            "new_email": r.new_email,
+        })
+    })
+    .collect();
+
+    let lists: Vec<_> = sqlx::query!(
+        r#"
+        SELECT lists.slug, lists.name, list_subscriptions.status, list_subscriptions.subscribed_at
+        FROM list_subscriptions
+        JOIN lists ON lists.list_id = list_subscriptions.list_id
+        WHERE list_subscriptions.subscriber_id = $1
+        ORDER BY list_subscriptions.subscribed_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the subscriber's lists.")?
+    .into_iter()
+    .map(|r| {
+        json!({
+            "slug": r.slug,
+            "name": r.name,
+            "status": r.status,
+            "subscribed_at": timestamp(r.subscribed_at),
+        })
+    })
+    .collect();
+
+    let tags: Vec<_> = sqlx::query!(
+        r#"
+        SELECT tag, tagged_at
+        FROM subscriber_tags
+        WHERE subscriber_id = $1
+        ORDER BY tag
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the subscriber's tags.")?
+    .into_iter()
+    .map(|r| json!({"tag": r.tag, "tagged_at": timestamp(r.tagged_at)}))
+    .collect();
+
+    let topic_opt_outs: Vec<_> = sqlx::query!(
+        r#"
+        SELECT topic
+        FROM topic_opt_outs
+        WHERE subscriber_id = $1
+        ORDER BY topic
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the topics the subscriber opted out of.")?
+    .into_iter()
+    .map(|r| r.topic)
+    .collect();
+
+    let preference_changes: Vec<_> = sqlx::query!(
+        r#"
+        SELECT field, old_value, new_value, changed_at
+        FROM subscriber_preference_changes
+        WHERE subscriber_id = $1
+        ORDER BY changed_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the subscriber's preference changes.")?
+    .into_iter()
+    .map(|r| {
+        json!({
+            "field": r.field,
+            "old_value": r.old_value,
+            "new_value": r.new_value,
+            "changed_at": timestamp(r.changed_at),
+        })
+    })
+    .collect();
+
+    let deliveries: Vec<_> = sqlx::query!(
+        r#"
+        SELECT
+            newsletter_issues.title,
+            newsletter_issue_deliveries.status,
+            newsletter_issue_deliveries.provider_message_id,
+            newsletter_issue_deliveries.queued_at,
+            newsletter_issue_deliveries.updated_at
+        FROM newsletter_issue_deliveries
+        JOIN newsletter_issues ON
+            newsletter_issues.newsletter_issue_id = newsletter_issue_deliveries.newsletter_issue_id
+        WHERE newsletter_issue_deliveries.subscriber_email = $1
+        ORDER BY newsletter_issue_deliveries.queued_at
+        "#,
+        subscriber.email
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the subscriber's deliveries.")?
+    .into_iter()
+    .map(|r| {
+        json!({
+            "issue": r.title,
+            "status": r.status,
+            "provider_message_id": r.provider_message_id,
+            "queued_at": timestamp(r.queued_at),
+            "updated_at": timestamp(r.updated_at),
+        })
+    })
+    .collect();
+
+    let pending_deliveries: Vec<_> = sqlx::query!(
+        r#"
+        SELECT newsletter_issues.title, issue_delivery_queue.n_attempts, issue_delivery_queue.execute_after
+        FROM issue_delivery_queue
+        JOIN newsletter_issues ON
+            newsletter_issues.newsletter_issue_id = issue_delivery_queue.newsletter_issue_id
+        WHERE issue_delivery_queue.subscriber_email = $1
+        ORDER BY issue_delivery_queue.execute_after
+        "#,
+        subscriber.email
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the subscriber's pending deliveries.")?
+    .into_iter()
+    .map(|r| {
+        json!({
+            "issue": r.title,
+            "n_attempts": r.n_attempts,
+            "execute_after": timestamp(r.execute_after),
+        })
+    })
+    .collect();
+
+    let failed_deliveries: Vec<_> = sqlx::query!(
+        r#"
+        SELECT
+            newsletter_issues.title,
+            issue_delivery_failures.n_attempts,
+            issue_delivery_failures.last_error,
+            issue_delivery_failures.http_status,
+            issue_delivery_failures.failed_at
+        FROM issue_delivery_failures
+        JOIN newsletter_issues ON
+            newsletter_issues.newsletter_issue_id = issue_delivery_failures.newsletter_issue_id
+        WHERE issue_delivery_failures.subscriber_email = $1
+        ORDER BY issue_delivery_failures.failed_at
+        "#,
+        subscriber.email
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the subscriber's failed deliveries.")?
+    .into_iter()
+    .map(|r| {
+        json!({
+            "issue": r.title,
+            "n_attempts": r.n_attempts,
+            "last_error": r.last_error,
+            "http_status": r.http_status,
+            "failed_at": timestamp(r.failed_at),
+        })
+    })
+    .collect();
+
+    let resend_attempts: Vec<_> = sqlx::query!(
+        r#"
+        SELECT ip, requested_at
+        FROM subscription_resend_attempts
+        WHERE email = $1
+        ORDER BY requested_at
+        "#,
+        subscriber.email_normalised
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the subscriber's resend attempts.")?
+    .into_iter()
+    .map(|r| json!({"ip": r.ip, "requested_at": timestamp(r.requested_at)}))
+    .collect();
+
+    let bounces: Vec<_> = sqlx::query!(
+        r#"
+        SELECT kind, provider_message_id, description, bounced_at
+        FROM email_bounces
+        WHERE email = $1
+        ORDER BY bounced_at
+        "#,
+        subscriber.email_normalised
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the subscriber's bounces.")?
+    .into_iter()
+    .map(|r| {
+        json!({
+            "kind": r.kind,
+            "provider_message_id": r.provider_message_id,
+            "description": r.description,
+            "bounced_at": timestamp(r.bounced_at),
+        })
+    })
+    .collect();
+
+    Ok(Some(json!({
+        "subscription": {
+            "id": subscriber.id.to_string(),
+            "email": subscriber.email,
+            "email_normalised": subscriber.email_normalised,
+            "name": subscriber.name,
+            "status": subscriber.status,
+            "subscribed_at": timestamp(subscriber.subscribed_at),
+            "unsubscribed_at": subscriber.unsubscribed_at.map(timestamp),
+            "attributes": subscriber.attributes,
+        },
+        "confirmation_tokens": tokens,
+        "lists": lists,
+        "tags": tags,
+        "topic_opt_outs": topic_opt_outs,
+        "preference_changes": preference_changes,
+        "deliveries": deliveries,
+        "pending_deliveries": pending_deliveries,
+        "failed_deliveries": failed_deliveries,
+        "confirmation_resend_attempts": resend_attempts,
+        "bounces": bounces,
+    })))
+}
+
+/// Erase a subscriber, leaving only a hash of their address behind.
+/// Returns whether there was such a subscriber.
+///
+/// Everything referencing them is deleted, except for the delivery ledger,
+/// where their address is replaced with a placeholder so that the figures
+/// of past issues stay the same.
+#[tracing::instrument(skip(pool, secret))]
+pub async fn erase_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    secret: &Secret<String>,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let (email, email_normalised) = match sqlx::query!(
+        r#"
+        SELECT email, email_normalised
+        FROM subscriptions
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to retrieve the subscriber to erase.")?
+    {
+        Some(row) => (row.email, row.email_normalised),
+        None => return Ok(false),
+    };
+
+    sqlx::query!(
+        r#"
+        WITH deleted_tokens AS (
+            DELETE FROM subscription_tokens
+            WHERE subscriber_id = $1
+        ),
+        deleted_opt_outs AS (
+            DELETE FROM topic_opt_outs
+            WHERE subscriber_id = $1
+        ),
+        deleted_changes AS (
+            DELETE FROM subscriber_preference_changes
+            WHERE subscriber_id = $1
+        ),
+        deleted_memberships AS (
+            DELETE FROM list_subscriptions
+            WHERE subscriber_id = $1
+        ),
+        deleted_tags AS (
+            DELETE FROM subscriber_tags
+            WHERE subscriber_id = $1
+        ),
+        deleted_queue AS (
+            DELETE FROM issue_delivery_queue
+            WHERE subscriber_email = $2
+        ),
+        deleted_failures AS (
+            DELETE FROM issue_delivery_failures
+            WHERE subscriber_email = $2
+        ),
+        deleted_attempts AS (
+            DELETE FROM subscription_resend_attempts
+            WHERE email = $4
+        ),
+        deleted_bounces AS (
+            DELETE FROM email_bounces
+            WHERE email = $4
+        ),
+        anonymised_deliveries AS (
+            UPDATE newsletter_issue_deliveries
+            SET subscriber_email = $3
+            WHERE subscriber_email = $2
+        )
+        DELETE FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        email,
+        format!("erased:{}", subscriber_id),
+        email_normalised
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to erase the subscriber.")?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO erased_subscribers (email_hash, erased_at)
+        VALUES ($1, now())
+        ON CONFLICT (email_hash) DO UPDATE
+        SET erased_at = EXCLUDED.erased_at
+        "#,
+        email_hash(&email, secret)
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to record the erasure of the subscriber.")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase a subscriber.")?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{email_hash, email_hashes},
+        secrecy::Secret,
+    };
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-secret-key".into())
+    }
+
+    #[test]
+    fn email_hashes_ignore_case_and_surrounding_spaces() {
+        assert_eq!(
+            email_hash("ursula@example.com", &secret()),
+            email_hash(" Ursula@Example.COM ", &secret())
+        );
+        assert_ne!(
+            email_hash("ursula@example.com", &secret()),
+            email_hash("ursula@example.org", &secret())
+        );
+    }
+
+    #[test]
+    fn internationalised_addresses_are_also_looked_up_by_their_legacy_hash() {
+        let hashes = email_hashes("Ursula@Bücher.Example", &secret());
+        assert_eq!(hashes.len(), 2);
+        assert_eq!(
+            hashes[0],
+            email_hash("ursula@xn--bcher-kva.example", &secret())
+        );
+        assert_eq!(email_hashes("Ursula@Example.com", &secret()).len(), 1);
+    }
+
+    #[test]
+    fn email_hashes_depend_on_the_secret() {
+        let other_secret = Secret::new("another-secret-key".into());
+        assert_ne!(
+            email_hash("ursula@example.com", &secret()),
+            email_hash("ursula@example.com", &other_secret)
+        );
+    }
+}
@@ -0,0 +1,574 @@
+use {
+    crate::{
+        domain::{is_identifier, NewSubscriber, SubscriberEmail, SubscriberName},
+        email_client::EmailClient,
+        routes::{generate_subscription_token, send_confirmation_email, store_token, List},
+        subscriber_data::is_erased,
+        suppressions::is_suppressed,
+    },
+    anyhow::Context,
+    chrono::{DateTime, Utc},
+    secrecy::Secret,
+    serde_json::{Map, Value},
+    sqlx::{PgPool, Postgres, Transaction},
+    std::collections::HashMap,
+    uuid::Uuid,
+};
+
+/// Most rows accepted in a single import
+pub const MAX_ROWS: usize = 100_000;
+
+/// Status imported subscribers start with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportStatus {
+    /// Already opted in elsewhere, e.g. on the platform they are migrated from
+    Confirmed,
+    /// Sent a confirmation email, like people subscribing through the form
+    PendingConfirmation,
+}
+
+impl ImportStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportStatus::Confirmed => "confirmed",
+            ImportStatus::PendingConfirmation => "pending_confirmation",
+        }
+    }
+}
+
+/// Which CSV columns hold what, by header
+#[derive(Debug)]
+pub struct ColumnMapping {
+    email: String,
+    /// Joined with spaces, e.g. first and last names
+    name: Vec<String>,
+    /// Attribute keys and the column they are read from
+    attributes: Vec<(String, String)>,
+}
+
+impl ColumnMapping {
+    /// `name` lists headers separated by commas, `attributes` pairs such as
+    /// `company=Company Name`, separated by commas.
+    pub fn parse(email: &str, name: &str, attributes: &str) -> Result<ColumnMapping, String> {
+        let email = email.trim().to_string();
+        if email.is_empty() {
+            return Err("Enter the header of the email column.".into());
+        }
+        let name: Vec<_> = split_list(name).map(String::from).collect();
+        if name.is_empty() {
+            return Err("Enter the headers of the name columns.".into());
+        }
+        let attributes = split_list(attributes)
+            .map(|pair| match pair.split_once('=') {
+                Some((key, header)) if is_identifier(key.trim()) && !header.trim().is_empty() => {
+                    Ok((key.trim().to_string(), header.trim().to_string()))
+                }
+                _ => Err(format!(
+                    "`{}` doesn't map an attribute to a column, as in `company=Company Name`.",
+                    pair
+                )),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(ColumnMapping {
+            email,
+            name,
+            attributes,
+        })
+    }
+}
+
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+/// What happened to a row that wasn't imported, or not entirely
+#[derive(Debug, PartialEq, Eq)]
+pub struct RowError {
+    /// Line of the CSV the row starts on
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub n_imported: usize,
+    /// Rows of people who are already subscribed, appear earlier in the CSV,
+    /// had their data erased or are suppressed
+    pub skipped: Vec<RowError>,
+    /// Rows that are invalid or failed to import
+    pub errors: Vec<RowError>,
+}
+
+struct ImportRow {
+    line: usize,
+    subscriber: NewSubscriber,
+    attributes: Map<String, Value>,
+}
+
+/// Subscribers read from a CSV export, ready to be imported
+pub struct SubscriberImport {
+    rows: Vec<ImportRow>,
+    report: ImportReport,
+}
+
+impl SubscriberImport {
+    /// Read and validate every row. Only problems with the file as a whole are errors,
+    /// invalid rows are reported and left out.
+    pub fn parse(csv: &str, mapping: &ColumnMapping) -> Result<SubscriberImport, String> {
+        let mut records = parse_csv(csv)?.into_iter();
+        let (_, headers) = records.next().ok_or("The CSV is empty.")?;
+        let column = |header: &str| {
+            headers
+                .iter()
+                .position(|h| h.trim().eq_ignore_ascii_case(header))
+                .ok_or_else(|| format!("There is no column `{}` in the CSV.", header))
+        };
+        let email_column = column(&mapping.email)?;
+        let name_columns: Vec<_> = mapping
+            .name
+            .iter()
+            .map(|header| column(header))
+            .collect::<Result<_, _>>()?;
+        let attribute_columns: Vec<_> = mapping
+            .attributes
+            .iter()
+            .map(|(key, header)| Ok((key, column(header)?)))
+            .collect::<Result<_, String>>()?;
+
+        let mut rows = Vec::new();
+        let mut report = ImportReport::default();
+        let mut seen = HashMap::new();
+        for (n_rows, (line, record)) in records.enumerate() {
+            if n_rows == MAX_ROWS {
+                return Err(format!("Imports can't have more than {} rows.", MAX_ROWS));
+            }
+            let field = |column: usize| record.get(column).map_or("", |f| f.trim());
+            let name = name_columns
+                .iter()
+                .map(|&column| field(column))
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ");
+            let subscriber = SubscriberEmail::parse(field(email_column).to_string())
+                .and_then(|email| Ok((email, SubscriberName::parse(name)?)));
+            let (email, name) = match subscriber {
+                Ok(subscriber) => subscriber,
+                Err(message) => {
+                    report.errors.push(RowError { line, message });
+                    continue;
+                }
+            };
+            if let Some(first_line) = seen.get(email.normalised()) {
+                report.skipped.push(RowError {
+                    line,
+                    message: format!("{} already appears on line {}.", email, first_line),
+                });
+                continue;
+            }
+            seen.insert(email.normalised().to_string(), line);
+            let attributes = attribute_columns
+                .iter()
+                .filter(|(_, column)| !field(*column).is_empty())
+                .map(|(key, column)| (key.to_string(), Value::from(field(*column))))
+                .collect();
+            rows.push(ImportRow {
+                line,
+                subscriber: NewSubscriber { email, name },
+                attributes,
+            });
+        }
+
+        Ok(SubscriberImport { rows, report })
+    }
+
+    /// Add the subscribers to `list`, leaving out people who are already subscribed.
+    /// Pending subscribers are sent a confirmation email each.
+    #[tracing::instrument(
+        name = "Import subscribers",
+        skip_all,
+        fields(n_rows = self.rows.len(), list = %list.slug, status = status.as_str())
+    )]
+    #[allow(clippy::too_many_arguments)]
+    pub async fn run(
+        self,
+        pool: &PgPool,
+        email_client: &EmailClient,
+        base_url: &str,
+        token_expires_at: DateTime<Utc>,
+        list: &List,
+        status: ImportStatus,
+        secret: &Secret<String>,
+    ) -> Result<ImportReport, anyhow::Error> {
+        let SubscriberImport { rows, mut report } = self;
+        for row in rows {
+            let mut transaction = pool
+                .begin()
+                .await
+                .context("Failed to acquire a Postgres connection from the pool")?;
+            if is_erased(&mut transaction, row.subscriber.email.as_ref(), secret)
+                .await
+                .context("Failed to check whether an imported subscriber was erased.")?
+            {
+                report.skipped.push(RowError {
+                    line: row.line,
+                    message: format!(
+                        "{} asked for their data to be erased.",
+                        row.subscriber.email
+                    ),
+                });
+                continue;
+            }
+            if is_suppressed(&mut transaction, row.subscriber.email.as_ref(), secret)
+                .await
+                .context("Failed to check whether an imported subscriber is suppressed.")?
+            {
+                report.skipped.push(RowError {
+                    line: row.line,
+                    message: format!("{} is on the suppression list.", row.subscriber.email),
+                });
+                continue;
+            }
+            let subscriber_id = match insert_imported_subscriber(&mut transaction, &row, status)
+                .await
+                .context("Failed to insert an imported subscriber.")?
+            {
+                Some(subscriber_id) => subscriber_id,
+                None => {
+                    report.skipped.push(RowError {
+                        line: row.line,
+                        message: format!("{} is already subscribed.", row.subscriber.email),
+                    });
+                    continue;
+                }
+            };
+            add_imported_member(&mut transaction, list.list_id, subscriber_id, status)
+                .await
+                .context("Failed to add an imported subscriber to the list.")?;
+            let subscription_token = match status {
+                ImportStatus::Confirmed => None,
+                ImportStatus::PendingConfirmation => {
+                    let subscription_token = generate_subscription_token();
+                    store_token(
+                        &mut transaction,
+                        subscriber_id,
+                        &subscription_token,
+                        token_expires_at,
+                    )
+                    .await
+                    .context("Failed to store the confirmation token of an imported subscriber.")?;
+                    Some(subscription_token)
+                }
+            };
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to import a subscriber.")?;
+            report.n_imported += 1;
+
+            if let Some(subscription_token) = subscription_token {
+                let email = row.subscriber.email.to_string();
+                if let Err(e) = send_confirmation_email(
+                    email_client,
+                    pool,
+                    secret,
+                    row.subscriber,
+                    base_url,
+                    &subscription_token,
+                )
+                .await
+                {
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to send a confirmation email to an imported subscriber."
+                    );
+                    report.errors.push(RowError {
+                        line: row.line,
+                        message: format!(
+                            "{} was imported, but the confirmation email couldn't be sent.",
+                            email
+                        ),
+                    });
+                }
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// Insert the subscriber, returning their id,
+/// or `None` if someone already subscribed with the same email address
+#[tracing::instrument(skip_all)]
+async fn insert_imported_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    row: &ImportRow,
+    status: ImportStatus,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (
+            id,
+            email,
+            email_normalised,
+            name,
+            subscribed_at,
+            status,
+            attributes
+        )
+        VALUES ($1, $2, $3, $4, now(), $5, $6)
+        ON CONFLICT (email_normalised) DO NOTHING
+        RETURNING id
+        "#,
+        Uuid::new_v4(),
+        row.subscriber.email.as_ref(),
+        row.subscriber.email.normalised(),
+        row.subscriber.name.as_ref(),
+        status.as_str(),
+        Value::Object(row.attributes.clone())
+    )
+    .fetch_optional(transaction)
+    .await?;
+
+    Ok(inserted.map(|r| r.id))
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn add_imported_member(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+    status: ImportStatus,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+        VALUES ($1, $2, $3, now())
+        "#,
+        list_id,
+        subscriber_id,
+        status.as_str()
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Parse CSV as described in RFC 4180, returning every record along with the line it starts on.
+/// Records may end with `\r\n` or `\n`, and blank lines are skipped.
+fn parse_csv(csv: &str) -> Result<Vec<(usize, Vec<String>)>, String> {
+    let csv = csv.strip_prefix('\u{feff}').unwrap_or(csv);
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    // Whether the current field is quoted, and whether we are still inside the quotes
+    let mut quoted = false;
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut chars = csv.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                c => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.push(c)
+                }
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() && !quoted => {
+                quoted = true;
+                in_quotes = true;
+            }
+            ',' => {
+                record.push(std::mem::take(&mut field));
+                quoted = false;
+            }
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                if quoted || !field.is_empty() || !record.is_empty() {
+                    record.push(std::mem::take(&mut field));
+                    records.push((record_line, std::mem::take(&mut record)));
+                }
+                quoted = false;
+                line += 1;
+                record_line = line;
+            }
+            _ if quoted => {
+                return Err(format!(
+                    "Unexpected text after a closing quote on line {}.",
+                    line
+                ))
+            }
+            '"' => return Err(format!("Unexpected quote on line {}.", line)),
+            c => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(format!(
+            "The quoted field on line {} is never closed.",
+            record_line
+        ));
+    }
+    if quoted || !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((record_line, record));
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{parse_csv, ColumnMapping, RowError, SubscriberImport},
+        claim::{assert_err, assert_ok},
+        serde_json::json,
+    };
+
+    fn record(line: usize, fields: &[&str]) -> (usize, Vec<String>) {
+        (line, fields.iter().map(|f| f.to_string()).collect())
+    }
+
+    #[test]
+    fn plain_records_are_split_on_commas_and_newlines() {
+        assert_eq!(
+            parse_csv("email,name\r\na@example.com,Ann\nb@example.com,\n").unwrap(),
+            vec![
+                record(1, &["email", "name"]),
+                record(2, &["a@example.com", "Ann"]),
+                record(3, &["b@example.com", ""]),
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_fields_may_hold_commas_quotes_and_newlines() {
+        assert_eq!(
+            parse_csv("\u{feff}name,note\n\"Doe, Jane\",\"said \"\"hi\"\"\ntwice\"\nJohn,\"\"\n")
+                .unwrap(),
+            vec![
+                record(1, &["name", "note"]),
+                record(2, &["Doe, Jane", "said \"hi\"\ntwice"]),
+                record(4, &["John", ""]),
+            ]
+        );
+    }
+
+    #[test]
+    fn blank_lines_are_skipped() {
+        assert_eq!(
+            parse_csv("a\n\nb").unwrap(),
+            vec![record(1, &["a"]), record(3, &["b"])]
+        );
+    }
+
+    #[test]
+    fn malformed_quotes_are_rejected() {
+        assert_err!(parse_csv("a,\"b\nc"));
+        assert_err!(parse_csv("a,\"b\"c\n"));
+        assert_err!(parse_csv("a,b\"c\"\n"));
+    }
+
+    #[test]
+    fn attribute_mappings_need_a_valid_key_and_a_column() {
+        assert_ok!(ColumnMapping::parse(
+            "Email",
+            "Name",
+            "company=Company Name, plan=Plan"
+        ));
+        assert_err!(ColumnMapping::parse("Email", "Name", "Company Name"));
+        assert_err!(ColumnMapping::parse(
+            "Email",
+            "Name",
+            "company name=Company"
+        ));
+        assert_err!(ColumnMapping::parse("Email", "Name", "company="));
+        assert_err!(ColumnMapping::parse("", "Name", ""));
+        assert_err!(ColumnMapping::parse("Email", " , ", ""));
+    }
+
+    #[test]
+    fn rows_are_mapped_validated_and_deduplicated() {
+        let mapping =
+            ColumnMapping::parse("Email Address", "First Name, Last Name", "company=Company")
+                .unwrap();
+        let csv = "Email Address,First Name,Last Name,Company\n\
+                   ann@example.com,Ann,Lee,Acme\n\
+                   not-an-email,Bob,,\n\
+                   carl@example.com,,,\n\
+                   ann@example.com,Ann,,\n\
+                   dee@example.com,Dee,,\n";
+
+        let import = SubscriberImport::parse(csv, &mapping).unwrap();
+
+        let rows: Vec<_> = import
+            .rows
+            .iter()
+            .map(|row| {
+                (
+                    row.line,
+                    row.subscriber.email.as_ref(),
+                    row.subscriber.name.as_ref(),
+                    json!(row.attributes),
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (2, "ann@example.com", "Ann Lee", json!({"company": "Acme"})),
+                (6, "dee@example.com", "Dee", json!({})),
+            ]
+        );
+        let error_lines: Vec<_> = import.report.errors.iter().map(|e| e.line).collect();
+        assert_eq!(error_lines, vec![3, 4]);
+        assert_eq!(
+            import.report.skipped,
+            vec![RowError {
+                line: 5,
+                message: "ann@example.com already appears on line 2.".into()
+            }]
+        );
+    }
+
+    #[test]
+    fn addresses_are_deduplicated_regardless_of_case() {
+        let mapping = ColumnMapping::parse("email", "name", "").unwrap();
+        let csv = "email,name\n\
+                   ann@example.com,Ann\n\
+                   Ann@Example.COM,Ann\n";
+
+        let import = SubscriberImport::parse(csv, &mapping).unwrap();
+
+        assert_eq!(import.rows.len(), 1);
+        assert_eq!(
+            import.report.skipped,
+            vec![RowError {
+                line: 3,
+                message: "Ann@Example.COM already appears on line 2.".into()
+            }]
+        );
+    }
+
+    #[test]
+    fn missing_columns_are_rejected() {
+        let mapping = ColumnMapping::parse("email", "name", "company=Company").unwrap();
+        assert!(SubscriberImport::parse("email,name\n", &mapping).is_err());
+        assert!(SubscriberImport::parse("", &mapping).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+use {
+    crate::{configuration::Settings, startup::get_db_pool},
+    chrono::Utc,
+    sqlx::PgPool,
+    std::time::Duration,
+};
+
+/// Periodically purge subscribers who never confirmed their subscription,
+/// and attempts to resend confirmation emails that no longer count towards the limit
+pub async fn run_sweeper_until_stopped(app_config: Settings) -> Result<(), anyhow::Error> {
+    let db_pool = get_db_pool(&app_config.database);
+    let subscription_config = app_config.subscriptions;
+    // `interval` panics on a zero period
+    let mut interval = tokio::time::interval(
+        subscription_config
+            .sweep_interval()
+            .max(Duration::from_secs(1)),
+    );
+
+    loop {
+        interval.tick().await;
+        match purge_stale_subscribers(&db_pool, subscription_config.pending_retention()).await {
+            Ok(0) => {}
+            Ok(n_purged) => tracing::info!(n_purged, "Purged stale pending subscribers."),
+            Err(e) => tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to purge stale pending subscribers."
+            ),
+        }
+        if let Err(e) = purge_resend_attempts(&db_pool, subscription_config.resend_window()).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to purge old resend attempts."
+            );
+        }
+    }
+}
+
+/// Delete attempts to resend confirmation emails older than `window`
+#[tracing::instrument(skip(pool))]
+pub async fn purge_resend_attempts(pool: &PgPool, window: Duration) -> Result<(), anyhow::Error> {
+    let cutoff = Utc::now() - chrono::Duration::from_std(window)?;
+    sqlx::query!(
+        r#"
+        DELETE FROM subscription_resend_attempts
+        WHERE requested_at <= $1
+        "#,
+        cutoff
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Delete `pending_confirmation` subscribers whose last confirmation link
+/// was sent more than `retention` ago, along with everything referencing them.
+/// Returns how many subscribers were deleted.
+#[tracing::instrument(skip(pool))]
+pub async fn purge_stale_subscribers(
+    pool: &PgPool,
+    retention: Duration,
+) -> Result<u64, anyhow::Error> {
+    let cutoff = Utc::now() - chrono::Duration::from_std(retention)?;
+    let result = sqlx::query!(
+        r#"
+        WITH stale AS (
+            SELECT id
+            FROM subscriptions
+            WHERE
+                status = 'pending_confirmation' AND
+                NOT EXISTS (
+                    SELECT 1
+                    FROM subscription_tokens
+                    WHERE
+                        subscription_tokens.subscriber_id = subscriptions.id AND
+                        subscription_tokens.created_at > $1
+                )
+            FOR UPDATE SKIP LOCKED
+        ),
+        deleted_tokens AS (
+            DELETE FROM subscription_tokens
+            WHERE subscriber_id IN (SELECT id FROM stale)
+        ),
+        deleted_opt_outs AS (
+            DELETE FROM topic_opt_outs
+            WHERE subscriber_id IN (SELECT id FROM stale)
+        ),
+        deleted_changes AS (
+            DELETE FROM subscriber_preference_changes
+            WHERE subscriber_id IN (SELECT id FROM stale)
+        ),
+        deleted_memberships AS (
+            DELETE FROM list_subscriptions
+            WHERE subscriber_id IN (SELECT id FROM stale)
+        ),
+        deleted_tags AS (
+            DELETE FROM subscriber_tags
+            WHERE subscriber_id IN (SELECT id FROM stale)
+        )
+        DELETE FROM subscriptions
+        WHERE id IN (SELECT id FROM stale)
+        "#,
+        cutoff
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,121 @@
+use {
+    crate::{domain::normalise_email, subscriber_data::email_hashes},
+    secrecy::Secret,
+    sqlx::PgExecutor,
+};
+
+/// Why an address is suppressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuppressionReason {
+    /// The address doesn't exist or permanently rejects our emails
+    HardBounce,
+    /// The recipient marked one of our emails as spam
+    SpamComplaint,
+    /// Soft bounces of the address reached the configured threshold
+    SoftBounces,
+    /// Added by an administrator
+    Manual,
+}
+
+impl SuppressionReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SuppressionReason::HardBounce => "hard_bounce",
+            SuppressionReason::SpamComplaint => "spam_complaint",
+            SuppressionReason::SoftBounces => "soft_bounces",
+            SuppressionReason::Manual => "manual",
+        }
+    }
+}
+
+/// Whether the address must not be emailed, because it is on the suppression list
+/// or its subscriber had their data erased
+#[tracing::instrument(skip(executor, secret))]
+pub async fn is_suppressed(
+    executor: impl PgExecutor<'_>,
+    email: &str,
+    secret: &Secret<String>,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT
+            EXISTS (
+                SELECT 1
+                FROM suppressions
+                WHERE email = $1
+            ) OR EXISTS (
+                SELECT 1
+                FROM erased_subscribers
+                WHERE email_hash = ANY($2)
+            ) AS "suppressed!"
+        "#,
+        normalise_email(email),
+        &email_hashes(email, secret)
+    )
+    .fetch_one(executor)
+    .await?;
+
+    Ok(row.suppressed)
+}
+
+/// Add the address to the suppression list. An address that is already suppressed
+/// keeps its first reason. Returns whether the address was added.
+#[tracing::instrument(skip(executor))]
+pub async fn suppress(
+    executor: impl PgExecutor<'_>,
+    email: &str,
+    reason: SuppressionReason,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO suppressions (email, reason, suppressed_at)
+        VALUES ($1, $2, now())
+        ON CONFLICT (email) DO NOTHING
+        "#,
+        normalise_email(email),
+        reason.as_str()
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Let the address be emailed again, which also lifts the suppression
+/// of an erased subscriber and forgets its bounces. Returns whether the address
+/// was suppressed.
+#[tracing::instrument(skip(executor, secret))]
+pub async fn unsuppress(
+    executor: impl PgExecutor<'_>,
+    email: &str,
+    secret: &Secret<String>,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        WITH deleted_suppressions AS (
+            DELETE FROM suppressions
+            WHERE email = $1
+            RETURNING email
+        ),
+        deleted_tombstones AS (
+            DELETE FROM erased_subscribers
+            WHERE email_hash = ANY($2)
+            RETURNING email_hash
+        ),
+        deleted_bounces AS (
+            DELETE FROM email_bounces
+            WHERE email = $1
+        )
+        SELECT
+            (SELECT COUNT(*) FROM deleted_suppressions) +
+            (SELECT COUNT(*) FROM deleted_tombstones) AS "n_deleted!"
+        "#,
+        normalise_email(email),
+        &email_hashes(email, secret)
+    )
+    .fetch_one(executor)
+    .await?;
+
+    Ok(row.n_deleted > 0)
+}
@@ -0,0 +1,227 @@
+use {
+    crate::helpers::{assert_is_redirect_to, spawn_app, when_sending_an_email, TestApp},
+    uuid::Uuid,
+    wiremock::ResponseTemplate,
+};
+
+/// Subscribe with the name and address and follow the confirmation link
+async fn create_subscriber(test_app: &TestApp, name: &str, email: &str) {
+    let n_sent = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .len();
+    let body = serde_urlencoded::to_string([("name", name), ("email", email)]).unwrap();
+    test_app
+        .post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &test_app.wait_for_emails(n_sent + 1).await[n_sent];
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+async fn set_attribute(test_app: &TestApp, email: &str, key: &str, value: &str) {
+    let response = test_app
+        .post_attributes(&[("email", email), ("key", key), ("value", value)])
+        .await;
+    assert_is_redirect_to(&response, "/admin/attributes");
+}
+
+async fn get_attributes_html(test_app: &TestApp) -> String {
+    test_app
+        .api_client
+        .get(format!("{}/admin/attributes", test_app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn attributes_in_use_are_counted_on_the_attributes_page() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    create_subscriber(&test_app, "Ursula", "first@example.com").await;
+    create_subscriber(&test_app, "Terry", "second@example.com").await;
+
+    // Act
+    set_attribute(&test_app, "first@example.com", "company", "Acme").await;
+    set_attribute(&test_app, "first@example.com", "plan", "pro").await;
+    set_attribute(&test_app, "second@example.com", "company", "Initech").await;
+    set_attribute(&test_app, "second@example.com", "plan", "pro").await;
+    set_attribute(&test_app, "second@example.com", "plan", "").await;
+
+    // Assert
+    let html_page = get_attributes_html(&test_app).await;
+    assert!(html_page.contains("<li><b>company</b>: 2 subscribers</li>"));
+    assert!(html_page.contains("<li><b>plan</b>: 1 subscribers</li>"));
+}
+
+#[tokio::test]
+async fn invalid_attribute_keys_are_rejected() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    create_subscriber(&test_app, "Ursula", "first@example.com").await;
+
+    for key in ["", "1st", "company.name", "{{ name }}"] {
+        // Act
+        set_attribute(&test_app, "first@example.com", key, "Acme").await;
+
+        // Assert
+        let html_page = get_attributes_html(&test_app).await;
+        assert!(
+            html_page.contains("Attributes are named with up to 64 letters"),
+            "Key {:?} was accepted",
+            key
+        );
+    }
+}
+
+#[tokio::test]
+async fn setting_an_attribute_of_an_unknown_subscriber_is_reported() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+
+    // Act
+    set_attribute(&test_app, "nobody@example.com", "company", "Acme").await;
+
+    // Assert
+    let html_page = get_attributes_html(&test_app).await;
+    assert!(html_page.contains("There is no subscriber nobody@example.com."));
+}
+
+#[tokio::test]
+async fn issues_are_personalised_for_every_recipient() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    create_subscriber(&test_app, "Ursula", "ursula@example.com").await;
+    create_subscriber(&test_app, "Terry", "terry@example.com").await;
+    set_attribute(&test_app, "ursula@example.com", "company", "Acme & Co").await;
+    let n_sent = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .len();
+
+    // Act
+    let response = test_app
+        .post_publish_issue(&serde_json::json!({
+            "title": "News for {{ name }}",
+            "text_content": r#"Hello {{ attributes.company | default: "there" }}"#,
+            "html_content": "<p>Hello {% if attributes.company %}\
+                             <b>{{ attributes.company }}</b>{% else %}there{% endif %}</p>",
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletter");
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let mut received: Vec<serde_json::Value> = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .skip(n_sent)
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .collect();
+    received.sort_by_key(|body| body["To"].as_str().unwrap().to_owned());
+    assert_eq!(received.len(), 2);
+
+    let terry = &received[0];
+    assert_eq!(terry["Subject"], "News for Terry");
+    assert!(terry["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("Hello there\n"));
+    assert!(terry["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("<p>Hello there</p>"));
+
+    let ursula = &received[1];
+    assert_eq!(ursula["Subject"], "News for Ursula");
+    assert!(ursula["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("Hello Acme & Co\n"));
+    assert!(ursula["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("<p>Hello <b>Acme &amp; Co</b></p>"));
+}
+
+#[tokio::test]
+async fn templates_referring_to_unknown_fields_are_rejected() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    create_subscriber(&test_app, "Ursula", "ursula@example.com").await;
+    set_attribute(&test_app, "ursula@example.com", "company", "Acme").await;
+
+    for (html_content, error) in [
+        (
+            "<p>Hi {{ attributes.compnay }}</p>",
+            "refers to `attributes.compnay`",
+        ),
+        ("<p>Hi {{ nickname }}</p>", "refers to `nickname`"),
+        ("<p>Hi {% if name %}</p>", "is never closed"),
+    ] {
+        // Act
+        let response = test_app
+            .post_publish_issue(&serde_json::json!({
+                "title": "Title",
+                "text_content": "Text Content",
+                "html_content": html_content,
+                "idempotency_key": Uuid::new_v4().to_string(),
+            }))
+            .await;
+
+        // Assert
+        assert_is_redirect_to(&response, "/admin/newsletter");
+        let html_page = test_app.get_admin_newsletter_html().await;
+        assert!(
+            html_page.contains("Invalid HTML content"),
+            "{}",
+            html_content
+        );
+        assert!(html_page.contains(error), "{}", html_content);
+    }
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
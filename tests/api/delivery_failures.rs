@@ -0,0 +1,185 @@
+use {
+    crate::helpers::{
+        assert_is_redirect_to, create_confirmed_subscriber, publish_issue, spawn_app,
+        when_sending_an_email,
+    },
+    uuid::Uuid,
+    wiremock::ResponseTemplate,
+};
+
+#[tokio::test]
+async fn exhausted_deliveries_are_moved_to_the_dead_letter_table() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    test_app.test_user.login(&test_app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(500))
+        .expect(test_app.app_config.worker.max_attempts as u64)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let issue_id = publish_issue(&test_app).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let failure = sqlx::query!(
+        "SELECT newsletter_issue_id, n_attempts, http_status FROM issue_delivery_failures"
+    )
+    .fetch_one(&test_app.db_pool)
+    .await
+    .expect("Failed to fetch the failed delivery.");
+    assert_eq!(failure.newsletter_issue_id, issue_id);
+    assert_eq!(failure.n_attempts, test_app.app_config.worker.max_attempts);
+    assert_eq!(failure.http_status, Some(500));
+}
+
+#[tokio::test]
+async fn invalid_stored_addresses_are_moved_to_the_dead_letter_table() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    let subscriber_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, email_normalised, name, subscribed_at, status)
+        VALUES (
+            $1,
+            'definitely-not-an-email',
+            'definitely-not-an-email',
+            'name',
+            now(),
+            'confirmed'
+        )
+        "#,
+        subscriber_id
+    )
+    .execute(&test_app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+        SELECT list_id, $1, 'confirmed', now() FROM lists WHERE slug = 'default'
+        "#,
+        subscriber_id
+    )
+    .execute(&test_app.db_pool)
+    .await
+    .unwrap();
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    publish_issue(&test_app).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let failure = sqlx::query!("SELECT subscriber_email, http_status FROM issue_delivery_failures")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch the failed delivery.");
+    assert_eq!(failure.subscriber_email, "definitely-not-an-email");
+    assert_eq!(failure.http_status, None);
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_listed_on_the_failures_page() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    test_app.test_user.login(&test_app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&test_app.email_server)
+        .await;
+    let issue_id = publish_issue(&test_app).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Act
+    let html_page = test_app.get_delivery_failures_html(issue_id).await;
+
+    // Assert
+    let email = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .email;
+    assert!(html_page.contains(&email));
+    assert!(html_page.contains("1 deliveries have been given up on."));
+}
+
+#[tokio::test]
+async fn requeued_failures_are_delivered_again() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    test_app.test_user.login(&test_app).await;
+
+    let outage = when_sending_an_email()
+        .respond_with(ResponseTemplate::new(500))
+        .mount_as_scoped(&test_app.email_server)
+        .await;
+    let issue_id = publish_issue(&test_app).await;
+    test_app.dispatch_all_pending_emails().await;
+    drop(outage);
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act - Part 1 - Requeue
+    let response = test_app.post_requeue_delivery_failures(issue_id).await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletter/{issue_id}/failures"));
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = test_app.get_delivery_failures_html(issue_id).await;
+    assert!(html_page.contains("<p><i>1 failed deliveries have been requeued.</i></p>"));
+    assert!(html_page.contains("0 deliveries have been given up on."));
+
+    // Act - Part 3 - Deliver
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_failures = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_failures"#)
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_failures, 0);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_failed_deliveries() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app.get_delivery_failures(Uuid::new_v4()).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_requeue_failed_deliveries() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app
+        .post_requeue_delivery_failures(Uuid::new_v4())
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,157 @@
+use {
+    crate::helpers::{
+        assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber,
+        spawn_app, when_sending_an_email, TestApp,
+    },
+    chrono::{Duration, Utc},
+    wiremock::ResponseTemplate,
+};
+
+async fn get_export(test_app: &TestApp, query: &str) -> reqwest::Response {
+    test_app
+        .api_client
+        .get(format!(
+            "{}/admin/subscribers/export?{}",
+            test_app.address, query
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get_email(test_app: &TestApp, status: &str) -> String {
+    sqlx::query!("SELECT email FROM subscriptions WHERE status = $1", status)
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .email
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_export_subscribers() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = get_export(&test_app, "format=csv").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn subscribers_are_exported_as_csv_filtered_by_status() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    create_confirmed_subscriber(&test_app).await;
+    create_unconfirmed_subscriber(&test_app).await;
+    let confirmed_email = get_email(&test_app, "confirmed").await;
+
+    // Act
+    let response = get_export(&test_app, "format=csv&status=confirmed&from=&list=").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    assert_eq!(
+        response.headers()["Content-Disposition"],
+        "attachment; filename=\"subscribers.csv\""
+    );
+    let body = response.text().await.unwrap();
+    let lines: Vec<_> = body.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], "id,email,name,status,subscribed_at,attributes");
+    assert!(lines[1].contains(&format!(",{},", confirmed_email)));
+    assert!(lines[1].contains(",confirmed,"));
+}
+
+#[tokio::test]
+async fn subscribers_are_exported_as_json_lines_filtered_by_list() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    create_confirmed_subscriber(&test_app).await;
+    let response = test_app
+        .post_lists(&[
+            ("slug", "rust-weekly"),
+            ("name", "Rust Weekly"),
+            ("sender_name", ""),
+            ("sender_email", ""),
+        ])
+        .await;
+    assert_is_redirect_to(&response, "/admin/lists");
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    test_app
+        .post_subscriptions("name=Ann&email=ann%40example.com&list=rust-weekly".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    test_app.wait_for_emails(1).await;
+
+    // Act
+    let response = get_export(&test_app, "format=ndjson&list=rust-weekly").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "application/x-ndjson");
+    let body = response.text().await.unwrap();
+    let subscribers: Vec<serde_json::Value> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(subscribers.len(), 1);
+    assert_eq!(subscribers[0]["email"], "ann@example.com");
+    assert_eq!(subscribers[0]["name"], "Ann");
+    assert_eq!(subscribers[0]["status"], "pending_confirmation");
+    assert_eq!(subscribers[0]["attributes"], serde_json::json!({}));
+}
+
+#[tokio::test]
+async fn subscribers_are_exported_filtered_by_subscription_date() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    create_confirmed_subscriber(&test_app).await;
+    let today = Utc::today().naive_utc();
+    let yesterday = today - Duration::days(1);
+
+    for (query, n_exported) in [
+        (format!("until={}", yesterday), 0),
+        (format!("from={}&until={}", yesterday, today), 1),
+        (format!("from={}", today + Duration::days(1)), 0),
+    ] {
+        // Act
+        let response = get_export(&test_app, &format!("format=ndjson&{}", query)).await;
+
+        // Assert
+        let body = response.text().await.unwrap();
+        assert_eq!(body.lines().count(), n_exported, "{}", query);
+    }
+}
+
+#[tokio::test]
+async fn invalid_filters_are_rejected() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+
+    for query in [
+        "format=xml",
+        "status=deleted",
+        "from=yesterday",
+        "list=no-such-list",
+    ] {
+        // Act
+        let response = get_export(&test_app, query).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400, "{}", query);
+    }
+}
@@ -1,16 +1,26 @@
 use {
     argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version},
+    chrono::{Duration, Utc},
+    fake::{
+        faker::{internet::en::SafeEmail, name::en::Name},
+        Fake,
+    },
     mailcrab::{
-        configuration::{get_config, DatabaseSettings},
+        configuration::{get_config, DatabaseSettings, Settings},
+        domain::{SubscriberToken, TokenPurpose},
         email_client::EmailClient,
         issue_delivery_worker::{try_execute_task, ExecutionOutcome},
         startup::{get_db_pool, Application},
         telemetry::{get_subscriber, init_subscriber},
     },
     once_cell::sync::Lazy,
+    secrecy::ExposeSecret,
     sqlx::{Connection, Executor, PgConnection, PgPool},
     uuid::Uuid,
-    wiremock::MockServer,
+    wiremock::{
+        matchers::{method, path},
+        Mock, MockBuilder, MockServer, ResponseTemplate,
+    },
 };
 
 // Subscriber should be created once ()
@@ -39,22 +49,45 @@ pub struct TestApp {
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
     pub email_client: EmailClient,
+    pub app_config: Settings,
 }
 
 impl TestApp {
     /// Consume all the messages in queue
     pub async fn dispatch_all_pending_emails(&self) {
         loop {
-            if let ExecutionOutcome::EmptyQueue =
-                try_execute_task(&self.db_pool, &self.email_client)
-                    .await
-                    .unwrap()
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.app_config.application,
+                &self.app_config.worker,
+            )
+            .await
+            .unwrap()
             {
                 break;
             }
         }
     }
 
+    /// Wait for emails sent in the background, until `n_emails` were received overall
+    pub async fn wait_for_emails(&self, n_emails: usize) -> Vec<wiremock::Request> {
+        let started_at = std::time::Instant::now();
+        loop {
+            let requests = self.email_server.received_requests().await.unwrap();
+            if requests.len() >= n_emails {
+                return requests;
+            }
+            assert!(
+                started_at.elapsed() < std::time::Duration::from_secs(5),
+                "Only {} of {} emails were sent.",
+                requests.len(),
+                n_emails
+            );
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+    }
+
     /// Return given route prepended with test app's address
     fn app_route(&self, route: &str) -> String {
         format!("{}/{}", self.address, route)
@@ -71,6 +104,16 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    /// Ask for a new confirmation email
+    pub async fn post_resend_confirmation(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(self.app_route("subscriptions/resend"))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
@@ -97,6 +140,27 @@ impl TestApp {
         ConfirmationLinks { html, plain_text }
     }
 
+    /// Extract the unsubscribe link from the `List-Unsubscribe` header of an issue
+    pub fn get_unsubscribe_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let header = body["Headers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|header| header["Name"] == "List-Unsubscribe")
+            .expect("The issue has no `List-Unsubscribe` header.");
+        let raw_link = header["Value"]
+            .as_str()
+            .unwrap()
+            .trim_start_matches('<')
+            .trim_end_matches('>');
+
+        let mut unsubscribe_link = reqwest::Url::parse(raw_link).unwrap();
+        assert_eq!(unsubscribe_link.host_str().unwrap(), "127.0.0.1");
+        unsubscribe_link.set_port(Some(self.port)).unwrap();
+        unsubscribe_link
+    }
+
     /// Post newsletters to subscribed user
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
         self.api_client
@@ -108,6 +172,22 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    /// Post one of Postmark's webhooks, authenticated with the configured credentials
+    pub async fn post_postmark_webhook(
+        &self,
+        webhook: &str,
+        payload: &serde_json::Value,
+    ) -> reqwest::Response {
+        let webhooks = &self.app_config.webhooks;
+        self.api_client
+            .post(self.app_route(&format!("webhooks/postmark/{}", webhook)))
+            .basic_auth(&webhooks.username, Some(webhooks.password.expose_secret()))
+            .json(payload)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     /// Post login request
     pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
     where
@@ -175,6 +255,163 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    /// Sign a token letting a subscriber manage their preferences
+    pub fn preferences_token(&self, subscriber_id: Uuid) -> String {
+        SubscriberToken::generate(
+            TokenPurpose::ManagePreferences,
+            subscriber_id,
+            &self.app_config.application.hmac_secret,
+        )
+        .as_ref()
+        .to_owned()
+    }
+
+    /// Get the preferences page of the subscriber the token was signed for
+    pub async fn get_preferences(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .get(self.app_route("preferences"))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Get HTML string from the preferences page
+    pub async fn get_preferences_html(&self, token: &str) -> String {
+        self.get_preferences(token).await.text().await.unwrap()
+    }
+
+    /// Submit the preferences form
+    pub async fn post_preferences<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(self.app_route("preferences"))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Sign a token letting a subscriber download or erase their data, valid for an hour
+    pub fn data_token(&self, subscriber_id: Uuid) -> String {
+        SubscriberToken::generate_expiring(
+            TokenPurpose::ManageData,
+            subscriber_id,
+            Utc::now() + Duration::hours(1),
+            &self.app_config.application.hmac_secret,
+        )
+        .as_ref()
+        .to_owned()
+    }
+
+    /// Ask for a link to the data stored about a subscriber
+    pub async fn post_data_request(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(self.app_route("subscriptions/data"))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_data_download(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .get(self.app_route("subscriptions/data/download"))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_data_erase(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .post(self.app_route("subscriptions/data/erase"))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Import subscribers through the admin form
+    pub async fn post_import<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(self.app_route("admin/import"))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Add a mailing list through the admin form
+    pub async fn post_lists<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(self.app_route("admin/lists"))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Tag or untag a subscriber through the admin form
+    pub async fn post_tags<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(self.app_route("admin/tags"))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Suppress an address, or remove it from the suppression list, through the admin form
+    pub async fn post_suppressions<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(self.app_route("admin/suppressions"))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Set or remove a subscriber attribute through the admin form
+    pub async fn post_attributes<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(self.app_route("admin/attributes"))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Add a topic through the admin form
+    pub async fn post_topics<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(self.app_route("admin/topics"))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     /// Post request for logging user out
     pub async fn post_logout(&self) -> reqwest::Response {
         self.api_client
@@ -210,6 +447,51 @@ impl TestApp {
             .await
             .expect("Failed to execute request.")
     }
+
+    /// Get the delivery progress page of an issue
+    pub async fn get_issue_progress(&self, issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(self.app_route(&format!("admin/issues/{issue_id}")))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Get HTML string from the delivery progress page of an issue
+    pub async fn get_issue_progress_html(&self, issue_id: Uuid) -> String {
+        self.get_issue_progress(issue_id)
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// Get the failed deliveries page of an issue
+    pub async fn get_delivery_failures(&self, issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(self.app_route(&format!("admin/newsletter/{issue_id}/failures")))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Get HTML string from the failed deliveries page of an issue
+    pub async fn get_delivery_failures_html(&self, issue_id: Uuid) -> String {
+        self.get_delivery_failures(issue_id)
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// Push failed deliveries of an issue back into the queue
+    pub async fn post_requeue_delivery_failures(&self, issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(self.app_route(&format!("admin/newsletter/{issue_id}/failures/requeue")))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
 }
 
 pub struct TestUser {
@@ -260,6 +542,11 @@ impl TestUser {
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawn the app, adjusting its configuration first
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     // Init tracing subscriber
     Lazy::force(&TRACING);
 
@@ -269,7 +556,10 @@ pub async fn spawn_app() -> TestApp {
         let mut c = get_config().expect("Failed to read configuration.");
         c.database.database_name = Uuid::new_v4().to_string();
         c.application.port = 0;
-        c.email_client.base_url = email_server.uri();
+        c.email_client.provider.base_url = Some(email_server.uri());
+        // Failed deliveries are retried right away, so tests don't have to wait
+        c.worker.backoff_base_milliseconds = 0;
+        configure(&mut c);
         c
     };
 
@@ -296,7 +586,8 @@ pub async fn spawn_app() -> TestApp {
         email_server,
         test_user: TestUser::generate(),
         api_client: client,
-        email_client: app_config.email_client.client(),
+        email_client: app_config.email_client.clone().client(),
+        app_config,
     };
 
     test_app.test_user.store(&test_app.db_pool).await;
@@ -327,6 +618,77 @@ async fn configure_database(config: &DatabaseSettings) -> PgPool {
     db_pool
 }
 
+pub async fn create_unconfirmed_subscriber(test_app: &TestApp) -> ConfirmationLinks {
+    // We are working with multiple subscribers now,
+    // their details must be randomised to avoid conflicts
+    let name: String = Name().fake();
+    let email: String = SafeEmail().fake();
+    let body = serde_urlencoded::to_string(serde_json::json!({
+        "name": name,
+        "email": email,
+    }))
+    .unwrap();
+
+    let _mock_guard = when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&test_app.email_server)
+        .await;
+    let n_sent = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .len();
+
+    test_app
+        .post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = test_app.wait_for_emails(n_sent + 1).await.pop().unwrap();
+
+    test_app.get_confirmation_links(&email_request)
+}
+
+pub async fn create_confirmed_subscriber(test_app: &TestApp) {
+    let confirmation_link = create_unconfirmed_subscriber(test_app).await;
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+/// Publish an issue through the admin form and return its id
+pub async fn publish_issue(test_app: &TestApp) -> Uuid {
+    let response = test_app
+        .post_publish_issue(&serde_json::json!({
+            "title": "Title",
+            "text_content": "Text Content",
+            "html_content": "<p>Html Content</p>",
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletter");
+
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+pub fn when_sending_an_email() -> MockBuilder {
+    Mock::given(path("/email")).and(method("POST"))
+}
+
+pub fn when_sending_a_batch() -> MockBuilder {
+    Mock::given(path("/email/batch")).and(method("POST"))
+}
+
 /// Commonly used assert test for redirection
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
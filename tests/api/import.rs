@@ -0,0 +1,193 @@
+use {
+    crate::helpers::{assert_is_redirect_to, spawn_app, when_sending_an_email, TestApp},
+    wiremock::ResponseTemplate,
+};
+
+const CSV: &str = "\
+Email Address,First Name,Last Name,Company
+ann@example.com,Ann,Lee,\"Acme, Inc.\"
+not-an-email,Bob,,
+bea@example.com,Bea,,
+ann@example.com,Ann,,
+existing@example.com,Eve,,
+";
+
+async fn import(test_app: &TestApp, status: &str) -> reqwest::Response {
+    test_app
+        .post_import(&[
+            ("csv", CSV),
+            ("email_column", "Email Address"),
+            ("name_columns", "First Name, Last Name"),
+            ("attribute_columns", "company=Company"),
+            ("list", "default"),
+            ("status", status),
+        ])
+        .await
+}
+
+/// Subscribe someone who is then already in the database when importing
+async fn create_existing_subscriber(test_app: &TestApp) {
+    let _mock_guard = when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&test_app.email_server)
+        .await;
+    let body = "name=Eve&email=existing%40example.com".to_string();
+    test_app
+        .post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    test_app.wait_for_emails(1).await;
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_import_subscribers() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = import(&test_app, "confirmed").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn confirmed_imports_add_valid_new_subscribers_without_sending_emails() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    create_existing_subscriber(&test_app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = import(&test_app, "confirmed").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Imported 2 subscribers."));
+    assert!(html_page.contains("Skipped 2 rows:"));
+    assert!(html_page.contains("<li>Line 5: ann@example.com already appears on line 2.</li>"));
+    assert!(html_page.contains("<li>Line 6: existing@example.com is already subscribed.</li>"));
+    assert!(html_page.contains("1 rows have errors:"));
+    assert!(html_page.contains("<li>Line 3: "));
+
+    let mut imported = sqlx::query!(
+        r#"
+        SELECT
+            email,
+            name,
+            subscriptions.status,
+            list_subscriptions.status AS membership_status,
+            attributes->>'company' AS company
+        FROM subscriptions
+        JOIN list_subscriptions ON list_subscriptions.subscriber_id = subscriptions.id
+        WHERE email <> 'existing@example.com'
+        ORDER BY email
+        "#
+    )
+    .fetch_all(&test_app.db_pool)
+    .await
+    .unwrap()
+    .into_iter();
+    let ann = imported.next().unwrap();
+    assert_eq!(ann.email, "ann@example.com");
+    assert_eq!(ann.name, "Ann Lee");
+    assert_eq!(ann.status, "confirmed");
+    assert_eq!(ann.membership_status, "confirmed");
+    assert_eq!(ann.company.as_deref(), Some("Acme, Inc."));
+    let bea = imported.next().unwrap();
+    assert_eq!(bea.email, "bea@example.com");
+    assert_eq!(bea.company, None);
+    assert!(imported.next().is_none());
+}
+
+#[tokio::test]
+async fn pending_imports_go_through_double_opt_in() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    create_existing_subscriber(&test_app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = import(&test_app, "pending_confirmation").await;
+
+    // Assert
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Imported 2 subscribers."));
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[1];
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let statuses: Vec<_> = sqlx::query!(
+        r#"
+        SELECT email, status
+        FROM subscriptions
+        WHERE email <> 'existing@example.com'
+        ORDER BY email
+        "#
+    )
+    .fetch_all(&test_app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| (r.email, r.status))
+    .collect();
+    assert_eq!(
+        statuses,
+        vec![
+            ("ann@example.com".to_string(), "confirmed".to_string()),
+            (
+                "bea@example.com".to_string(),
+                "pending_confirmation".to_string()
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn imports_with_unknown_columns_are_rejected() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+
+    // Act
+    let response = test_app
+        .post_import(&[
+            ("csv", CSV),
+            ("email_column", "Email"),
+            ("name_columns", "First Name"),
+            ("attribute_columns", ""),
+            ("list", "default"),
+            ("status", "confirmed"),
+        ])
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/import");
+    let html_page = test_app
+        .api_client
+        .get(format!("{}/admin/import", test_app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("There is no column `Email` in the CSV."));
+}
@@ -0,0 +1,148 @@
+use {
+    crate::helpers::{
+        assert_is_redirect_to, create_confirmed_subscriber, publish_issue, spawn_app,
+        when_sending_an_email,
+    },
+    uuid::Uuid,
+    wiremock::ResponseTemplate,
+};
+
+#[tokio::test]
+async fn published_issues_are_recorded_as_queued() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    create_confirmed_subscriber(&test_app).await;
+    test_app.test_user.login(&test_app).await;
+
+    // Act
+    let issue_id = publish_issue(&test_app).await;
+
+    // Assert
+    let html_page = test_app.get_issue_progress_html(issue_id).await;
+    assert!(html_page.contains("0 of 2 deliveries processed."));
+    assert!(html_page.contains("<li>Queued: 2</li>"));
+}
+
+#[tokio::test]
+async fn sent_deliveries_are_recorded_with_the_provider_message_id() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    test_app.test_user.login(&test_app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+            "ErrorCode": 0,
+            "Message": "OK"
+        })))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let issue_id = publish_issue(&test_app).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let delivery = sqlx::query!(
+        "SELECT status, provider_message_id FROM newsletter_issue_deliveries \
+         WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&test_app.db_pool)
+    .await
+    .expect("Failed to fetch the delivery.");
+    assert_eq!(delivery.status, "sent");
+    assert_eq!(
+        delivery.provider_message_id.as_deref(),
+        Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817")
+    );
+
+    let html_page = test_app.get_issue_progress_html(issue_id).await;
+    assert!(html_page.contains("1 of 1 deliveries processed."));
+    assert!(html_page.contains("<li>Sent: 1</li>"));
+}
+
+#[tokio::test]
+async fn given_up_deliveries_are_recorded_as_failed() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    test_app.test_user.login(&test_app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let issue_id = publish_issue(&test_app).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let html_page = test_app.get_issue_progress_html(issue_id).await;
+    assert!(html_page.contains("1 of 1 deliveries processed."));
+    assert!(html_page.contains("<li>Failed: 1</li>"));
+}
+
+#[tokio::test]
+async fn the_delivery_to_a_subscriber_can_be_looked_up() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    test_app.test_user.login(&test_app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    let issue_id = publish_issue(&test_app).await;
+    test_app.dispatch_all_pending_emails().await;
+    let email = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .email;
+
+    // Act
+    let html_page = test_app
+        .api_client
+        .get(format!("{}/admin/issues/{issue_id}", test_app.address))
+        .query(&[("subscriber_email", &email)])
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html_page.contains(&format!("<p>Delivery to {email}: sent")));
+}
+
+#[tokio::test]
+async fn unknown_issues_return_a_404() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+
+    // Act
+    let response = test_app.get_issue_progress(Uuid::new_v4()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_issue_progress() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app.get_issue_progress(Uuid::new_v4()).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,364 @@
+use {
+    crate::helpers::{
+        assert_is_redirect_to, create_confirmed_subscriber, spawn_app, when_sending_an_email,
+        TestApp,
+    },
+    uuid::Uuid,
+    wiremock::ResponseTemplate,
+};
+
+async fn add_list(test_app: &TestApp, slug: &str, sender_name: &str, sender_email: &str) {
+    let response = test_app
+        .post_lists(&[
+            ("slug", slug),
+            ("name", "Rust Weekly"),
+            ("sender_name", sender_name),
+            ("sender_email", sender_email),
+        ])
+        .await;
+    assert_is_redirect_to(&response, "/admin/lists");
+}
+
+/// Subscribe to the list and follow the confirmation link
+async fn subscribe_to_list(test_app: &TestApp, email: &str, list: &str) {
+    let n_sent = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .len();
+    let body =
+        serde_urlencoded::to_string([("name", "Hoon Wee"), ("email", email), ("list", list)])
+            .unwrap();
+    test_app
+        .post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &test_app.wait_for_emails(n_sent + 1).await[n_sent];
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+async fn publish_to_lists(test_app: &TestApp, lists: &[&str]) {
+    let idempotency_key = Uuid::new_v4().to_string();
+    let mut form = vec![
+        ("title", "Title"),
+        ("text_content", "Text Content"),
+        ("html_content", "<p>Html Content</p>"),
+        ("idempotency_key", idempotency_key.as_str()),
+    ];
+    form.extend(lists.iter().map(|list| ("list", *list)));
+    let response = test_app.post_publish_issue(&form).await;
+    assert_is_redirect_to(&response, "/admin/newsletter");
+}
+
+/// Recipients of the emails sent after the first `skip` ones
+async fn recipients(test_app: &TestApp, skip: usize) -> Vec<serde_json::Value> {
+    test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .skip(skip)
+        .map(|r| serde_json::from_slice::<serde_json::Value>(&r.body).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn added_lists_are_shown_on_the_lists_page() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+
+    // Act
+    add_list(&test_app, "rust-weekly", "", "").await;
+
+    // Assert
+    let html_page = test_app
+        .api_client
+        .get(format!("{}/admin/lists", test_app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("The list rust-weekly has been added."));
+    assert!(html_page.contains("<b>Rust Weekly</b> (rust-weekly)"));
+}
+
+#[tokio::test]
+async fn invalid_list_slugs_are_rejected() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+
+    // Act
+    add_list(&test_app, "Not A Slug", "", "").await;
+
+    // Assert
+    let n_lists = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM lists"#)
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_lists, 1);
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_is_rejected_with_a_400() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let body = "name=hoon%20wee&email=mrgravity817%40gmail.com&list=does-not-exist";
+
+    // Act
+    let response = test_app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn issues_only_reach_subscribers_of_the_target_lists() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    add_list(&test_app, "rust-weekly", "", "").await;
+    create_confirmed_subscriber(&test_app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    subscribe_to_list(&test_app, "rustacean@example.com", "rust-weekly").await;
+    let n_sent = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .len();
+
+    // Act
+    publish_to_lists(&test_app, &["rust-weekly"]).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let received = recipients(&test_app, n_sent).await;
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0]["To"], "rustacean@example.com");
+}
+
+#[tokio::test]
+async fn subscribers_of_several_target_lists_receive_a_single_email() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    add_list(&test_app, "rust-weekly", "", "").await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    subscribe_to_list(&test_app, "rustacean@example.com", "default").await;
+    subscribe_to_list(&test_app, "rustacean@example.com", "rust-weekly").await;
+    let n_sent = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .len();
+
+    // Act
+    publish_to_lists(&test_app, &["default", "rust-weekly"]).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(recipients(&test_app, n_sent).await.len(), 1);
+}
+
+#[tokio::test]
+async fn issues_are_sent_on_behalf_of_the_list() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    add_list(&test_app, "rust-weekly", "Rust Weekly", "rust@example.com").await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    subscribe_to_list(&test_app, "rustacean@example.com", "rust-weekly").await;
+    let n_sent = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .len();
+
+    // Act
+    publish_to_lists(&test_app, &["rust-weekly"]).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let received = recipients(&test_app, n_sent).await;
+    let from = received[0]["From"].as_str().unwrap();
+    assert!(from.contains("Rust Weekly"));
+    assert!(from.ends_with("<rust@example.com>"));
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_confirm_again_to_join_another_list() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    add_list(&test_app, "rust-weekly", "", "").await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    subscribe_to_list(&test_app, "rustacean@example.com", "default").await;
+    let n_sent = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .len();
+
+    // Act
+    let body = "name=hoon%20wee&email=rustacean%40example.com&list=rust-weekly";
+    test_app.post_subscriptions(body.into()).await;
+    test_app.wait_for_emails(n_sent + 1).await;
+    publish_to_lists(&test_app, &["rust-weekly"]).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert - Only the confirmation email went out
+    let received = recipients(&test_app, n_sent).await;
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0]["Subject"], "Welcome");
+}
+
+#[tokio::test]
+async fn unsubscribing_from_a_list_keeps_the_other_lists() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    add_list(&test_app, "rust-weekly", "", "").await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    subscribe_to_list(&test_app, "rustacean@example.com", "default").await;
+    subscribe_to_list(&test_app, "rustacean@example.com", "rust-weekly").await;
+    publish_to_lists(&test_app, &["rust-weekly"]).await;
+    test_app.dispatch_all_pending_emails().await;
+    let email_request = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let unsubscribe_link = test_app.get_unsubscribe_link(&email_request);
+
+    // Act
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let n_sent = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .len();
+    publish_to_lists(&test_app, &["rust-weekly"]).await;
+    publish_to_lists(&test_app, &["default"]).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert - Only the issue of the list they stayed on went out
+    assert_eq!(recipients(&test_app, n_sent).await.len(), 1);
+}
+
+#[tokio::test]
+async fn expired_links_to_join_a_list_offer_to_join_that_list_again() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    add_list(&test_app, "rust-weekly", "", "").await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    subscribe_to_list(&test_app, "rustacean@example.com", "default").await;
+    let n_sent = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .len();
+    let body = "name=hoon%20wee&email=rustacean%40example.com&list=rust-weekly";
+    test_app.post_subscriptions(body.into()).await;
+    let email_request = &test_app.wait_for_emails(n_sent + 1).await[n_sent];
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&test_app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"<input hidden type="text" name="list" value="rust-weekly">"#));
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_waiting_to_join_another_list_can_get_a_new_link() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    add_list(&test_app, "rust-weekly", "", "").await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    subscribe_to_list(&test_app, "rustacean@example.com", "default").await;
+    let body = "name=hoon%20wee&email=rustacean%40example.com&list=rust-weekly";
+    test_app.post_subscriptions(body.into()).await;
+    let n_sent = test_app.wait_for_emails(2).await.len();
+
+    // Act
+    let response = test_app
+        .post_resend_confirmation("rustacean@example.com")
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = &test_app.wait_for_emails(n_sent + 1).await[n_sent];
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let n_pending = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM list_subscriptions
+        WHERE status = 'pending_confirmation'
+        "#
+    )
+    .fetch_one(&test_app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(n_pending, 0);
+}
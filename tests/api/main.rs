@@ -1,8 +1,22 @@
 mod admin_dashboard;
+mod attributes;
 mod change_password;
+mod delivery_failures;
+mod export;
 mod health_check;
 mod helpers;
+mod import;
+mod issue_progress;
+mod lists;
 mod login;
 mod newsletter;
+mod preferences;
+mod subscriber_data;
+mod subscription_sweeper;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_resend;
+mod suppressions;
+mod tags;
+mod unsubscribe;
+mod webhooks;
@@ -1,15 +1,12 @@
 use {
-    crate::helpers::{assert_is_redirect_to, spawn_app, ConfirmationLinks, TestApp},
-    fake::{
-        faker::{internet::en::SafeEmail, name::en::Name},
-        Fake,
+    crate::helpers::{
+        assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber,
+        publish_issue, spawn_app, when_sending_a_batch, when_sending_an_email,
     },
-    std::time::Duration,
+    mailcrab::issue_delivery_worker::run_worker_until_stopped,
+    std::time::{Duration, Instant},
     uuid::Uuid,
-    wiremock::{
-        matchers::{any, method, path},
-        Mock, MockBuilder, ResponseTemplate,
-    },
+    wiremock::{matchers::any, Mock, ResponseTemplate},
 };
 
 #[tokio::test]
@@ -102,41 +99,6 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     test_app.dispatch_all_pending_emails().await;
 }
 
-async fn create_unconfirmed_subscriber(test_app: &TestApp) -> ConfirmationLinks {
-    // We are working with multiple subscribers now,
-    // their details must be randomised to avoid conflicts
-    let name: String = Name().fake();
-    let email: String = SafeEmail().fake();
-    let body = serde_urlencoded::to_string(&serde_json::json!({
-        "name": name,
-        "email": email,
-    }))
-    .unwrap();
-
-    let _mock_guard = when_sending_an_email()
-        .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
-        .expect(1)
-        .mount_as_scoped(&test_app.email_server)
-        .await;
-
-    test_app
-        .post_subscriptions(body)
-        .await
-        .error_for_status()
-        .unwrap();
-
-    let email_request = &test_app
-        .email_server
-        .received_requests()
-        .await
-        .unwrap()
-        .pop()
-        .unwrap();
-
-    test_app.get_confirmation_links(email_request)
-}
-
 #[tokio::test]
 async fn requests_missing_authorization_are_rejected() {
     // Arrange
@@ -163,15 +125,6 @@ async fn requests_missing_authorization_are_rejected() {
     );
 }
 
-async fn create_confirmed_subscriber(test_app: &TestApp) {
-    let confirmation_link = create_unconfirmed_subscriber(test_app).await;
-    reqwest::get(confirmation_link.html)
-        .await
-        .unwrap()
-        .error_for_status()
-        .unwrap();
-}
-
 #[tokio::test]
 async fn non_existing_user_is_rejected() {
     // Arrange
@@ -385,6 +338,282 @@ async fn concurrent_form_submission_is_handled_gracefully() {
     test_app.dispatch_all_pending_emails().await;
 }
 
-fn when_sending_an_email() -> MockBuilder {
-    Mock::given(path("/email")).and(method("POST"))
+#[tokio::test]
+async fn failed_deliveries_are_retried_until_they_succeed() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    test_app.test_user.login(&test_app).await;
+
+    // The email provider fails twice before it accepts the email
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(2)
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_publish_issue(&serde_json::json!({
+            "title": "Title",
+            "text_content": "Text Content",
+            "html_content": "<p>Html Content</p>",
+            "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletter");
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_pending = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_pending, 0);
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_given_up_after_max_attempts() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    test_app.test_user.login(&test_app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(500))
+        .expect(test_app.app_config.worker.max_attempts as u64)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_publish_issue(&serde_json::json!({
+            "title": "Title",
+            "text_content": "Text Content",
+            "html_content": "<p>Html Content</p>",
+            "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletter");
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_pending = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_pending, 0);
+}
+
+#[tokio::test]
+async fn idle_workers_are_woken_up_as_soon_as_an_issue_is_published() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    test_app.test_user.login(&test_app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Polling alone would not deliver the issue before the test gives up
+    let mut app_config = test_app.app_config.clone();
+    app_config.worker.fallback_poll_interval_milliseconds = 60_000;
+    tokio::spawn(run_worker_until_stopped(app_config));
+    // Give the worker the time to find an empty queue and go idle
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    // Act
+    publish_issue(&test_app).await;
+
+    // Assert
+    let published_at = Instant::now();
+    while test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .len()
+        < 2
+    {
+        assert!(
+            published_at.elapsed() < Duration::from_secs(5),
+            "The worker did not pick up the issue."
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[tokio::test]
+async fn concurrent_workers_deliver_an_issue_in_parallel() {
+    // Arrange
+    let test_app = spawn_app().await;
+    for _ in 0..4 {
+        create_confirmed_subscriber(&test_app).await;
+    }
+    test_app.test_user.login(&test_app).await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+        .expect(4)
+        .mount(&test_app.email_server)
+        .await;
+
+    let mut app_config = test_app.app_config.clone();
+    app_config.worker.concurrency = 4;
+    tokio::spawn(run_worker_until_stopped(app_config));
+
+    // Act
+    publish_issue(&test_app).await;
+
+    // Assert
+    // A single worker would need 6 seconds before sending the last email
+    let published_at = Instant::now();
+    while test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .len()
+        < 8
+    {
+        assert!(
+            published_at.elapsed() < Duration::from_secs(3),
+            "The issue was not delivered concurrently."
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[tokio::test]
+async fn batched_deliveries_are_sent_in_a_single_request() {
+    // Arrange
+    let mut test_app = spawn_app().await;
+    test_app.app_config.worker.batch_size = 10;
+    for _ in 0..3 {
+        create_confirmed_subscriber(&test_app).await;
+    }
+    test_app.test_user.login(&test_app).await;
+
+    when_sending_a_batch()
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "ErrorCode": 0, "Message": "OK", "MessageID": Uuid::new_v4() },
+            { "ErrorCode": 0, "Message": "OK", "MessageID": Uuid::new_v4() },
+            { "ErrorCode": 0, "Message": "OK", "MessageID": Uuid::new_v4() }
+        ])))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    publish_issue(&test_app).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_sent = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM newsletter_issue_deliveries WHERE status = 'sent'"#
+    )
+    .fetch_one(&test_app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(n_sent, 3);
+}
+
+#[tokio::test]
+async fn batches_turned_down_as_a_whole_are_given_up_on_at_once() {
+    // Arrange
+    let mut test_app = spawn_app().await;
+    test_app.app_config.worker.batch_size = 10;
+    for _ in 0..2 {
+        create_confirmed_subscriber(&test_app).await;
+    }
+    test_app.test_user.login(&test_app).await;
+
+    // Sending the same request again would be turned down just the same
+    when_sending_a_batch()
+        .respond_with(ResponseTemplate::new(401).set_body_json(serde_json::json!({
+            "ErrorCode": 10,
+            "Message": "Bad or missing Server API token"
+        })))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    publish_issue(&test_app).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let failures = sqlx::query!("SELECT n_attempts, http_status FROM issue_delivery_failures")
+        .fetch_all(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(failures.len(), 2);
+    for failure in failures {
+        assert_eq!(failure.n_attempts, 1);
+        assert_eq!(failure.http_status, Some(401));
+    }
+}
+
+#[tokio::test]
+async fn rejected_emails_of_a_batch_are_retried_or_given_up_on_individually() {
+    // Arrange
+    let mut test_app = spawn_app().await;
+    test_app.app_config.worker.batch_size = 10;
+    for _ in 0..3 {
+        create_confirmed_subscriber(&test_app).await;
+    }
+    test_app.test_user.login(&test_app).await;
+
+    when_sending_a_batch()
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "ErrorCode": 0, "Message": "OK", "MessageID": Uuid::new_v4() },
+            { "ErrorCode": 406, "Message": "Inactive recipient" },
+            { "ErrorCode": 429, "Message": "Rate limit exceeded" }
+        ])))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+    // The email that hit the rate limit is retried on its own
+    when_sending_a_batch()
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "ErrorCode": 0, "Message": "OK", "MessageID": Uuid::new_v4() }
+        ])))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    publish_issue(&test_app).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let statuses: Vec<String> =
+        sqlx::query!("SELECT status FROM newsletter_issue_deliveries ORDER BY status")
+            .fetch_all(&test_app.db_pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.status)
+            .collect();
+    assert_eq!(statuses, vec!["failed", "sent", "sent"]);
+
+    let failure = sqlx::query!("SELECT n_attempts, last_error FROM issue_delivery_failures")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(failure.n_attempts, 1);
+    assert!(failure.last_error.contains("Inactive recipient"));
 }
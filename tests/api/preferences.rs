@@ -0,0 +1,412 @@
+use {
+    crate::helpers::{
+        assert_is_redirect_to, create_confirmed_subscriber, publish_issue, spawn_app,
+        when_sending_an_email, TestApp,
+    },
+    uuid::Uuid,
+    wiremock::ResponseTemplate,
+};
+
+struct Subscriber {
+    id: Uuid,
+    name: String,
+    email: String,
+}
+
+async fn get_subscriber(test_app: &TestApp) -> Subscriber {
+    let row = sqlx::query!("SELECT id, name, email FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    Subscriber {
+        id: row.id,
+        name: row.name,
+        email: row.email,
+    }
+}
+
+async fn preference_changes(test_app: &TestApp) -> Vec<(String, String, String)> {
+    sqlx::query!(
+        "SELECT field, old_value, new_value FROM subscriber_preference_changes ORDER BY changed_at"
+    )
+    .fetch_all(&test_app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| (r.field, r.old_value, r.new_value))
+    .collect()
+}
+
+/// Redirect expected after submitting the preferences form
+fn preferences_page(token: &str) -> String {
+    format!("/preferences?token={}", urlencoding::encode(token))
+}
+
+#[tokio::test]
+async fn issues_carry_a_link_to_the_preferences_page() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    test_app.test_user.login(&test_app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    publish_issue(&test_app).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let subscriber = get_subscriber(&test_app).await;
+    let email_request = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let token = urlencoding::encode(&test_app.preferences_token(subscriber.id)).into_owned();
+    let link = format!("/preferences?token={}", token);
+    assert!(body["HtmlBody"].as_str().unwrap().contains(&link));
+    assert!(body["TextBody"].as_str().unwrap().contains(&link));
+}
+
+#[tokio::test]
+async fn the_preferences_page_shows_the_subscriber_details() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    let subscriber = get_subscriber(&test_app).await;
+    let token = test_app.preferences_token(subscriber.id);
+
+    // Act
+    let html_page = test_app.get_preferences_html(&token).await;
+
+    // Assert
+    assert!(html_page.contains(&format!(r#"value="{}""#, subscriber.email)));
+}
+
+#[tokio::test]
+async fn invalid_preferences_tokens_are_rejected_with_a_401() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    let subscriber = get_subscriber(&test_app).await;
+    // A valid token, but for another purpose
+    let unsubscribe_token = mailcrab::domain::SubscriberToken::generate(
+        mailcrab::domain::TokenPurpose::Unsubscribe,
+        subscriber.id,
+        &test_app.app_config.application.hmac_secret,
+    );
+
+    for token in ["not-a-token", unsubscribe_token.as_ref()] {
+        // Act
+        let get_response = test_app.get_preferences(token).await;
+        let post_response = test_app
+            .post_preferences(&[
+                ("token", token),
+                ("name", "New Name"),
+                ("email", &subscriber.email),
+            ])
+            .await;
+
+        // Assert
+        assert_eq!(get_response.status().as_u16(), 401);
+        assert_eq!(post_response.status().as_u16(), 401);
+    }
+    assert_eq!(get_subscriber(&test_app).await.name, subscriber.name);
+}
+
+#[tokio::test]
+async fn subscribers_can_change_their_name() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    let subscriber = get_subscriber(&test_app).await;
+    let token = test_app.preferences_token(subscriber.id);
+
+    // Act - Part 1 - Submit the form
+    let response = test_app
+        .post_preferences(&[
+            ("token", token.as_str()),
+            ("name", "Hoon Wee"),
+            ("email", &subscriber.email),
+        ])
+        .await;
+    assert_is_redirect_to(&response, &preferences_page(&token));
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = test_app.get_preferences_html(&token).await;
+    assert!(html_page.contains("<p><i>Your preferences have been updated.</i></p>"));
+
+    // Assert
+    assert_eq!(get_subscriber(&test_app).await.name, "Hoon Wee");
+    assert_eq!(
+        preference_changes(&test_app).await,
+        vec![("name".into(), subscriber.name, "Hoon Wee".into())]
+    );
+}
+
+#[tokio::test]
+async fn invalid_names_are_rejected() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    let subscriber = get_subscriber(&test_app).await;
+    let token = test_app.preferences_token(subscriber.id);
+
+    // Act
+    let response = test_app
+        .post_preferences(&[
+            ("token", token.as_str()),
+            ("name", "  "),
+            ("email", &subscriber.email),
+        ])
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, &preferences_page(&token));
+    let html_page = test_app.get_preferences_html(&token).await;
+    assert!(html_page.contains("is not a valid subscriber name."));
+    assert_eq!(get_subscriber(&test_app).await.name, subscriber.name);
+    assert!(preference_changes(&test_app).await.is_empty());
+}
+
+#[tokio::test]
+async fn a_new_email_address_is_used_once_confirmed() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    let subscriber = get_subscriber(&test_app).await;
+    let token = test_app.preferences_token(subscriber.id);
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act - Part 1 - Ask for the change
+    test_app
+        .post_preferences(&[
+            ("token", token.as_str()),
+            ("name", &subscriber.name),
+            ("email", "new-address@example.com"),
+        ])
+        .await;
+    assert_eq!(get_subscriber(&test_app).await.email, subscriber.email);
+
+    // Act - Part 2 - Confirm the new address
+    // The confirmation email of the subscriber went out first
+    let email_request = test_app.wait_for_emails(2).await.pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "new-address@example.com");
+    let confirmation_links = test_app.get_confirmation_links(&email_request);
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let changed = get_subscriber(&test_app).await;
+    assert_eq!(changed.email, "new-address@example.com");
+    assert_eq!(
+        preference_changes(&test_app).await,
+        vec![(
+            "email".into(),
+            subscriber.email,
+            "new-address@example.com".into()
+        )]
+    );
+}
+
+#[tokio::test]
+async fn confirming_an_address_taken_in_the_meantime_leaves_the_email_unchanged() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    let subscriber = get_subscriber(&test_app).await;
+    let token = test_app.preferences_token(subscriber.id);
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+    test_app
+        .post_preferences(&[
+            ("token", token.as_str()),
+            ("name", &subscriber.name),
+            ("email", "new-address@example.com"),
+        ])
+        .await;
+    // The confirmation email of the subscriber went out first
+    let email_request = test_app.wait_for_emails(2).await.pop().unwrap();
+    let confirmation_links = test_app.get_confirmation_links(&email_request);
+    // Someone else subscribes with the address before the link is clicked
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, email_normalised, name, subscribed_at, status)
+        VALUES ($1, 'New-Address@example.com', 'new-address@example.com', 'Someone Else', now(), 'confirmed')",
+        Uuid::new_v4()
+    )
+    .execute(&test_app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("That address is already in use by another subscription"));
+    let email = sqlx::query!(
+        "SELECT email FROM subscriptions WHERE id = $1",
+        subscriber.id
+    )
+    .fetch_one(&test_app.db_pool)
+    .await
+    .unwrap()
+    .email;
+    assert_eq!(email, subscriber.email);
+    assert!(preference_changes(&test_app).await.is_empty());
+}
+
+#[tokio::test]
+async fn an_email_address_used_by_another_subscriber_is_not_given_away() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    let subscriber = get_subscriber(&test_app).await;
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, email_normalised, name, subscribed_at, status)
+        VALUES ($1, 'taken@example.com', 'taken@example.com', 'Someone Else', now(), 'confirmed')",
+        Uuid::new_v4()
+    )
+    .execute(&test_app.db_pool)
+    .await
+    .unwrap();
+    let token = test_app.preferences_token(subscriber.id);
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    test_app
+        .post_preferences(&[
+            ("token", token.as_str()),
+            ("name", &subscriber.name),
+            ("email", "taken@example.com"),
+        ])
+        .await;
+
+    // Assert
+    // The page reads as if the address was free
+    let html_page = test_app.get_preferences_html(&token).await;
+    assert!(html_page.contains("We sent a confirmation link to taken@example.com."));
+    assert!(!html_page.contains("already subscribed"));
+    // Its owner is told instead, without a link to take it over
+    // The confirmation email of the subscriber went out first
+    let email_request = test_app.wait_for_emails(2).await.pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "taken@example.com");
+    assert!(!body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("subscription_token"));
+    let email = sqlx::query!(
+        "SELECT email FROM subscriptions WHERE id = $1",
+        subscriber.id
+    )
+    .fetch_one(&test_app.db_pool)
+    .await
+    .unwrap()
+    .email;
+    assert_eq!(email, subscriber.email);
+    assert!(preference_changes(&test_app).await.is_empty());
+}
+
+#[tokio::test]
+async fn a_failing_email_provider_does_not_fail_a_change_of_email_address() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    let subscriber = get_subscriber(&test_app).await;
+    let token = test_app.preferences_token(subscriber.id);
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_preferences(&[
+            ("token", token.as_str()),
+            ("name", &subscriber.name),
+            ("email", "new-address@example.com"),
+        ])
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, &preferences_page(&token));
+    let html_page = test_app.get_preferences_html(&token).await;
+    assert!(html_page.contains("We sent a confirmation link to new-address@example.com."));
+    // Keep the email from reaching the mock server once it's handed over to another test
+    test_app.wait_for_emails(2).await;
+}
+
+#[tokio::test]
+async fn subscribers_do_not_receive_issues_of_topics_they_opted_out_of() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    let subscriber = get_subscriber(&test_app).await;
+    let token = test_app.preferences_token(subscriber.id);
+    test_app.test_user.login(&test_app).await;
+    for topic in ["rust", "postgres"] {
+        test_app
+            .post_topics(&[("name", topic), ("description", "All about it")])
+            .await;
+    }
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act - Part 1 - Opt out of "postgres"
+    test_app
+        .post_preferences(&[
+            ("token", token.as_str()),
+            ("name", &subscriber.name),
+            ("email", &subscriber.email),
+            ("topic", "rust"),
+        ])
+        .await;
+
+    // Act - Part 2 - Publish an issue for each topic
+    for topic in ["rust", "postgres"] {
+        test_app
+            .post_publish_issue(&serde_json::json!({
+                "title": "Title",
+                "text_content": "Text Content",
+                "html_content": "<p>Html Content</p>",
+                "topic": topic,
+                "idempotency_key": Uuid::new_v4().to_string(),
+            }))
+            .await;
+    }
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(
+        preference_changes(&test_app).await,
+        vec![("topics".into(), "postgres,rust".into(), "rust".into())]
+    );
+    let html_page = test_app.get_preferences_html(&token).await;
+    assert!(html_page.contains(r#"value="rust" checked>"#));
+    assert!(html_page.contains(r#"value="postgres" >"#));
+}
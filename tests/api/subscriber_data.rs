@@ -0,0 +1,246 @@
+use {
+    crate::helpers::{
+        create_confirmed_subscriber, publish_issue, spawn_app, when_sending_an_email, TestApp,
+    },
+    chrono::{Duration, Utc},
+    mailcrab::domain::{SubscriberToken, TokenPurpose},
+    std::time::Instant,
+    uuid::Uuid,
+    wiremock::ResponseTemplate,
+};
+
+/// Send an issue to a single confirmed subscriber and return their id and address
+async fn deliver_an_issue(test_app: &TestApp) -> (Uuid, String) {
+    create_confirmed_subscriber(test_app).await;
+    test_app.test_user.login(test_app).await;
+    let _mock_guard = when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&test_app.email_server)
+        .await;
+    publish_issue(test_app).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    let subscriber = sqlx::query!("SELECT id, email FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    (subscriber.id, subscriber.email)
+}
+
+#[tokio::test]
+async fn subscribers_are_emailed_a_link_to_their_data() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    let email = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .email;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app.post_data_request(&email).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = test_app.wait_for_emails(2).await.pop().unwrap();
+    let links = test_app.get_confirmation_links(&email_request);
+    assert_eq!(links.html.path(), "/subscriptions/data");
+    let html_page = reqwest::get(links.html)
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("Download your data"));
+    assert!(html_page.contains(r#"<form action="/subscriptions/data/erase?token="#));
+}
+
+#[tokio::test]
+async fn asking_for_the_data_of_an_unknown_address_sends_no_email() {
+    // Arrange
+    let test_app = spawn_app().await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app.post_data_request("nobody@example.com").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("If this address is subscribed"));
+}
+
+#[tokio::test]
+async fn asking_for_a_data_link_does_not_wait_for_the_email_provider() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    let email = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .email;
+    // Waiting on the provider would tell subscribed addresses apart from unknown ones
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(10)))
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let requested_at = Instant::now();
+    let response = test_app.post_data_request(&email).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(requested_at.elapsed() < std::time::Duration::from_secs(5));
+    // The email still goes out. Waiting for it also keeps it from reaching
+    // the mock server once it's handed over to another test.
+    test_app.wait_for_emails(2).await;
+}
+
+#[tokio::test]
+async fn the_download_holds_the_subscription_and_its_delivery_history() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let (subscriber_id, email) = deliver_an_issue(&test_app).await;
+
+    // Act
+    let response = test_app
+        .get_data_download(&test_app.data_token(subscriber_id))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["subscription"]["email"], email.as_str());
+    assert_eq!(data["subscription"]["status"], "confirmed");
+    assert_eq!(data["confirmation_tokens"].as_array().unwrap().len(), 1);
+    assert_eq!(data["lists"][0]["slug"], "default");
+    assert_eq!(data["deliveries"][0]["issue"], "Title");
+    assert_eq!(data["deliveries"][0]["status"], "sent");
+}
+
+#[tokio::test]
+async fn links_that_expired_or_were_signed_for_another_purpose_are_rejected() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let (subscriber_id, _) = deliver_an_issue(&test_app).await;
+    let secret = &test_app.app_config.application.hmac_secret;
+    let expired = SubscriberToken::generate_expiring(
+        TokenPurpose::ManageData,
+        subscriber_id,
+        Utc::now() - Duration::minutes(1),
+        secret,
+    );
+    let preferences = test_app.preferences_token(subscriber_id);
+
+    for token in [expired.as_ref(), preferences.as_str()] {
+        // Act
+        let download = test_app.get_data_download(token).await;
+        let erase = test_app.post_data_erase(token).await;
+
+        // Assert
+        assert_eq!(download.status().as_u16(), 401);
+        assert_eq!(erase.status().as_u16(), 401);
+    }
+    let n_subscribers = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 1);
+}
+
+#[tokio::test]
+async fn erasing_deletes_the_subscriber_and_anonymises_their_deliveries() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let (subscriber_id, _) = deliver_an_issue(&test_app).await;
+    let token = test_app.data_token(subscriber_id);
+
+    // Act
+    let response = test_app.post_data_erase(&token).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let remaining = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM subscriptions) AS "subscriptions!",
+            (SELECT COUNT(*) FROM subscription_tokens) AS "tokens!",
+            (SELECT COUNT(*) FROM list_subscriptions) AS "memberships!",
+            (SELECT COUNT(*) FROM issue_delivery_queue) AS "queue!",
+            (SELECT COUNT(*) FROM erased_subscribers) AS "tombstones!"
+        "#
+    )
+    .fetch_one(&test_app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(remaining.subscriptions, 0);
+    assert_eq!(remaining.tokens, 0);
+    assert_eq!(remaining.memberships, 0);
+    assert_eq!(remaining.queue, 0);
+    assert_eq!(remaining.tombstones, 1);
+    let delivery = sqlx::query!("SELECT subscriber_email, status FROM newsletter_issue_deliveries")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        delivery.subscriber_email,
+        format!("erased:{}", subscriber_id)
+    );
+    assert_eq!(delivery.status, "sent");
+
+    // The link doesn't work anymore
+    let response = test_app.get_data_download(&token).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn erased_subscribers_are_not_imported_again() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let (subscriber_id, email) = deliver_an_issue(&test_app).await;
+    test_app
+        .post_data_erase(&test_app.data_token(subscriber_id))
+        .await
+        .error_for_status()
+        .unwrap();
+    let csv = format!("Email,Name\n{},Ursula\n", email.to_uppercase());
+
+    // Act
+    let response = test_app
+        .post_import(&[
+            ("csv", csv.as_str()),
+            ("email_column", "Email"),
+            ("name_columns", "Name"),
+            ("attribute_columns", ""),
+            ("list", "default"),
+            ("status", "confirmed"),
+        ])
+        .await;
+
+    // Assert
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("asked for their data to be erased"));
+    let n_subscribers = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 0);
+}
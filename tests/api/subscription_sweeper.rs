@@ -0,0 +1,56 @@
+use {
+    crate::helpers::{create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app},
+    mailcrab::subscription_sweeper::purge_stale_subscribers,
+    std::time::Duration,
+};
+
+#[tokio::test]
+async fn stale_pending_subscribers_are_purged() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_unconfirmed_subscriber(&test_app).await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '2 hours'")
+        .execute(&test_app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let n_purged = purge_stale_subscribers(&test_app.db_pool, Duration::from_secs(3600))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(n_purged, 1);
+    let n_subscribers = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 0);
+}
+
+#[tokio::test]
+async fn recent_pending_and_confirmed_subscribers_are_kept() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '2 hours'")
+        .execute(&test_app.db_pool)
+        .await
+        .unwrap();
+    create_unconfirmed_subscriber(&test_app).await;
+
+    // Act
+    let n_purged = purge_stale_subscribers(&test_app.db_pool, Duration::from_secs(3600))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(n_purged, 0);
+    let n_subscribers = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 2);
+}
@@ -1,5 +1,5 @@
 use {
-    crate::helpers::spawn_app,
+    crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with},
     wiremock::{
         matchers::{method, path},
         Mock, ResponseTemplate,
@@ -23,6 +23,7 @@ async fn subscribe_returns_a_200_for_valid_form_data() {
 
     // Assert
     assert_eq!(200, response.status().as_u16());
+    test_app.wait_for_emails(1).await;
 }
 
 #[tokio::test]
@@ -49,6 +50,7 @@ async fn subscribe_persists_the_new_subscriber() {
     assert_eq!(saved.email, "mrgravity817@gmail.com");
     assert_eq!(saved.name, "hoon wee");
     assert_eq!(saved.status, "pending_confirmation");
+    test_app.wait_for_emails(1).await;
 }
 
 #[tokio::test]
@@ -131,7 +133,7 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
     test_app.post_subscriptions(body.into()).await;
 
     // Assert
-    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let email_request = &test_app.wait_for_emails(1).await[0];
     let confirmation_links = test_app.get_confirmation_links(email_request);
     assert_eq!(confirmation_links.html, confirmation_links.plain_text);
 }
@@ -152,7 +154,7 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
     test_app.post_subscriptions(body.into()).await;
 
     // Assert
-    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let email_request = &test_app.wait_for_emails(1).await[0];
     let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
     let get_link = |s: &str| -> String {
         let links = linkify::LinkFinder::new()
@@ -187,3 +189,219 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Assert
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribing_twice_while_pending_sends_a_fresh_confirmation_email() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let body = "name=hoon%20wee&email=mrgravity817%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let first_response = test_app.post_subscriptions(body.into()).await;
+    let second_response = test_app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(first_response.status().as_u16(), 200);
+    assert_eq!(second_response.status().as_u16(), 200);
+    let email_requests = test_app.wait_for_emails(2).await;
+    let first_links = test_app.get_confirmation_links(&email_requests[0]);
+    let second_links = test_app.get_confirmation_links(&email_requests[1]);
+    assert_ne!(first_links.html, second_links.html);
+    // The first token was rotated out
+    let response = reqwest::get(first_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    let response = reqwest::get(second_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribing_with_another_case_is_the_same_subscriber() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    test_app
+        .post_subscriptions("name=hoon%20wee&email=MrGravity817%40Gmail.com".into())
+        .await;
+    let response = test_app
+        .post_subscriptions("name=hoon%20wee&email=mrgravity817%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email, email_normalised FROM subscriptions")
+        .fetch_all(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(saved.len(), 1);
+    // The address is sent to as it was last typed
+    assert_eq!(saved[0].email, "mrgravity817@gmail.com");
+    assert_eq!(saved[0].email_normalised, "mrgravity817@gmail.com");
+    test_app.wait_for_emails(2).await;
+}
+
+#[tokio::test]
+async fn subscribing_again_once_confirmed_returns_a_200_without_sending_an_email() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let body = "name=hoon%20wee&email=mrgravity817%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+    let first_page = test_app
+        .post_subscriptions(body.into())
+        .await
+        .text()
+        .await
+        .unwrap();
+    let email_request = &test_app.wait_for_emails(1).await[0];
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = test_app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    // Nothing tells a confirmed address apart from a new one
+    assert_eq!(response.text().await.unwrap(), first_page);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_again_after_unsubscribing_requires_a_new_confirmation() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let body = "name=hoon%20wee&email=mrgravity817%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+    test_app.post_subscriptions(body.into()).await;
+    let email_request = &test_app.wait_for_emails(1).await[0];
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed', unsubscribed_at = now()")
+        .execute(&test_app.db_pool)
+        .await
+        .unwrap();
+
+    // Act - Part 1 - Subscribe again
+    let response = test_app.post_subscriptions(body.into()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(saved.status, "pending_confirmation");
+
+    // Act - Part 2 - Confirm again
+    let email_request = &test_app.wait_for_emails(2).await[1];
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribe_renders_a_page_asking_to_check_the_inbox() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let body = "name=hoon%20wee&email=mrgravity817%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Check your inbox"));
+    test_app.wait_for_emails(1).await;
+}
+
+#[tokio::test]
+async fn subscribe_renders_validation_errors_in_a_page() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let body = "name=hoon%20wee&email=%3Cb%3Enot-an-email%3C%2Fb%3E";
+
+    // Act
+    let response = test_app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("&lt;b&gt;not-an-email&lt;/b&gt;"));
+}
+
+#[tokio::test]
+async fn subscribe_redirects_to_the_configured_page() {
+    // Arrange
+    let test_app = spawn_app_with(|c| {
+        c.application.confirmation_redirect_url = Some("https://example.com/welcome".into())
+    })
+    .await;
+    let body = "name=hoon%20wee&email=mrgravity817%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_is_redirect_to(
+        &response,
+        "https://example.com/welcome?status=pending_confirmation",
+    );
+    test_app.wait_for_emails(1).await;
+}
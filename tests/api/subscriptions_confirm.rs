@@ -1,5 +1,7 @@
 use {
-    crate::helpers::spawn_app,
+    crate::helpers::{
+        assert_is_redirect_to, create_unconfirmed_subscriber, spawn_app, spawn_app_with,
+    },
     wiremock::{
         matchers::{method, path},
         Mock, ResponseTemplate,
@@ -19,7 +21,7 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
         .await;
 
     test_app.post_subscriptions(body.into()).await;
-    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let email_request = &test_app.wait_for_emails(1).await[0];
     let confirmation_links = test_app.get_confirmation_links(email_request);
 
     // Act
@@ -56,7 +58,7 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
         .await;
 
     test_app.post_subscriptions(body.into()).await;
-    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let email_request = &test_app.wait_for_emails(1).await[0];
     let confirmation_links = test_app.get_confirmation_links(email_request);
 
     // Act
@@ -76,3 +78,116 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     assert_eq!(saved.email, "mrgravity817@gmail.com");
     assert_eq!(saved.status, "confirmed");
 }
+
+#[tokio::test]
+async fn confirmation_links_can_only_be_used_once() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&test_app).await;
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn expired_confirmation_links_offer_to_send_a_new_one() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&test_app).await;
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&test_app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("This confirmation link has expired."));
+    assert!(html_page.contains(r#"<form action="/subscriptions" method="post">"#));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn a_confirmed_subscriber_is_welcomed_with_a_page() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&test_app).await;
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Thanks for confirming your subscription"));
+}
+
+#[tokio::test]
+async fn invalid_confirmation_links_offer_to_send_a_new_one() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(&format!(
+        "{}/subscriptions/confirm?subscription_token=not-a-token",
+        test_app.address
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("This confirmation link is invalid"));
+    assert!(html_page.contains(r#"<form action="/subscriptions/resend" method="post">"#));
+}
+
+#[tokio::test]
+async fn confirmations_redirect_to_the_configured_page() {
+    // Arrange
+    let test_app = spawn_app_with(|c| {
+        c.application.confirmation_redirect_url = Some("https://example.com/welcome".into())
+    })
+    .await;
+    let confirmation_links = create_unconfirmed_subscriber(&test_app).await;
+
+    // Act - Part 1 - Confirm
+    let response = test_app
+        .api_client
+        .get(confirmation_links.html.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "https://example.com/welcome?status=confirmed");
+
+    // Act - Part 2 - Use the link again
+    let response = test_app
+        .api_client
+        .get(confirmation_links.html)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_is_redirect_to(&response, "https://example.com/welcome?status=invalid");
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
@@ -0,0 +1,188 @@
+use {
+    crate::helpers::{spawn_app, when_sending_an_email, TestApp},
+    std::time::{Duration, Instant},
+    wiremock::ResponseTemplate,
+};
+
+const EMAIL: &str = "mrgravity817@gmail.com";
+
+async fn subscribe(test_app: &TestApp) {
+    let body = "name=hoon%20wee&email=mrgravity817%40gmail.com";
+    let n_sent = n_sent_emails(test_app).await;
+    test_app
+        .post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    // The confirmation email goes out in the background
+    test_app.wait_for_emails(n_sent + 1).await;
+}
+
+async fn n_sent_emails(test_app: &TestApp) -> usize {
+    test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .len()
+}
+
+#[tokio::test]
+async fn resending_sends_a_new_link_and_revokes_the_previous_one() {
+    // Arrange
+    let test_app = spawn_app().await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+    subscribe(&test_app).await;
+
+    // Act
+    let response = test_app.post_resend_confirmation(EMAIL).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_requests = test_app.wait_for_emails(2).await;
+    let first_links = test_app.get_confirmation_links(&email_requests[0]);
+    let second_links = test_app.get_confirmation_links(&email_requests[1]);
+    let response = reqwest::get(first_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    let response = reqwest::get(second_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn resending_does_not_wait_for_the_email_provider() {
+    // Arrange
+    let test_app = spawn_app().await;
+    {
+        let _mock_guard = when_sending_an_email()
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount_as_scoped(&test_app.email_server)
+            .await;
+        subscribe(&test_app).await;
+    }
+    // Waiting on the provider would tell pending addresses apart from unknown ones
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(10)))
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let requested_at = Instant::now();
+    let response = test_app.post_resend_confirmation(EMAIL).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(requested_at.elapsed() < Duration::from_secs(5));
+    // The email still goes out. Waiting for it also keeps it from reaching
+    // the mock server once it's handed over to another test.
+    test_app.wait_for_emails(2).await;
+}
+
+#[tokio::test]
+async fn resending_to_an_unknown_address_returns_a_200_without_sending_an_email() {
+    // Arrange
+    let test_app = spawn_app().await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app.post_resend_confirmation(EMAIL).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn resending_to_a_confirmed_address_returns_a_200_without_sending_an_email() {
+    // Arrange
+    let test_app = spawn_app().await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+    subscribe(&test_app).await;
+    let email_request = &test_app.wait_for_emails(1).await[0];
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = test_app.post_resend_confirmation(EMAIL).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn resending_an_invalid_address_is_rejected_with_a_400() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app
+        .post_resend_confirmation("definitely-not-an-email")
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn resending_too_often_to_the_same_address_is_rejected_with_a_429() {
+    // Arrange
+    let test_app = spawn_app().await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    subscribe(&test_app).await;
+    let max_attempts = test_app.app_config.subscriptions.resend_max_attempts;
+    for _ in 0..max_attempts {
+        let response = test_app.post_resend_confirmation(EMAIL).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    // The confirmation email and every resent one
+    let n_sent = test_app
+        .wait_for_emails(max_attempts as usize + 1)
+        .await
+        .len();
+
+    // Act
+    let response = test_app.post_resend_confirmation(EMAIL).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    assert_eq!(n_sent_emails(&test_app).await, n_sent);
+}
+
+#[tokio::test]
+async fn resending_too_often_from_the_same_ip_is_rejected_with_a_429() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let max_attempts = test_app.app_config.subscriptions.resend_max_attempts;
+    for i in 0..max_attempts {
+        let response = test_app
+            .post_resend_confirmation(&format!("someone-{}@example.com", i))
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    // Act
+    let response = test_app
+        .post_resend_confirmation("someone-else@example.com")
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+}
@@ -0,0 +1,356 @@
+use {
+    crate::helpers::{
+        assert_is_redirect_to, create_confirmed_subscriber, publish_issue, spawn_app,
+        when_sending_a_batch, when_sending_an_email, TestApp,
+    },
+    wiremock::ResponseTemplate,
+};
+
+async fn suppress(test_app: &TestApp, email: &str) {
+    let response = test_app
+        .post_suppressions(&[
+            ("email", email),
+            ("reason", "hard_bounce"),
+            ("action", "add"),
+        ])
+        .await;
+    assert_is_redirect_to(&response, "/admin/suppressions");
+}
+
+async fn confirmed_subscriber_email(test_app: &TestApp) -> String {
+    create_confirmed_subscriber(test_app).await;
+    sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .email
+}
+
+async fn n_subscribers(test_app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_suppressions() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app
+        .post_suppressions(&[
+            ("email", "ursula@example.com"),
+            ("reason", "manual"),
+            ("action", "add"),
+        ])
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn suppressed_addresses_are_listed_and_can_be_removed() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+
+    // Act - Part 1 - Suppress
+    suppress(&test_app, "Ursula@Example.com").await;
+
+    // Assert - Part 1
+    let html_page = test_app
+        .api_client
+        .get(format!("{}/admin/suppressions", test_app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<b>ursula@example.com</b>: hard_bounce"));
+
+    // Act - Part 2 - Remove
+    let response = test_app
+        .post_suppressions(&[
+            ("email", "ursula@example.com"),
+            ("reason", "manual"),
+            ("action", "remove"),
+        ])
+        .await;
+
+    // Assert - Part 2
+    assert_is_redirect_to(&response, "/admin/suppressions");
+    let n_suppressions = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM suppressions"#)
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_suppressions, 0);
+}
+
+#[tokio::test]
+async fn suppressed_addresses_cannot_subscribe() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    suppress(&test_app, "ursula@example.com").await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_subscriptions("name=Ursula&email=URSULA%40example.com".into())
+        .await;
+
+    // Assert
+    // The answer doesn't give the suppression away
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(n_subscribers(&test_app).await, 0);
+}
+
+#[tokio::test]
+async fn suppressed_addresses_are_not_sent_email_change_confirmations() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    let subscriber = sqlx::query!("SELECT id, name FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    let token = test_app.preferences_token(subscriber.id);
+    test_app.test_user.login(&test_app).await;
+    suppress(&test_app, "ursula@example.com").await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    test_app
+        .post_preferences(&[
+            ("token", token.as_str()),
+            ("name", &subscriber.name),
+            ("email", "ursula@example.com"),
+        ])
+        .await;
+
+    // Assert
+    // The page doesn't give the suppression away
+    let html_page = test_app.get_preferences_html(&token).await;
+    assert!(html_page.contains("We sent a confirmation link to ursula@example.com."));
+}
+
+#[tokio::test]
+async fn suppressed_addresses_are_not_sent_data_links() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let email = confirmed_subscriber_email(&test_app).await;
+    test_app.test_user.login(&test_app).await;
+    suppress(&test_app, &email).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app.post_data_request(&email).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("If this address is subscribed, we sent it a link to your data."));
+}
+
+#[tokio::test]
+async fn suppressed_addresses_are_skipped_by_imports() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    suppress(&test_app, "ursula@example.com").await;
+
+    // Act
+    let response = test_app
+        .post_import(&[
+            ("csv", "Email,Name\nursula@example.com,Ursula\n"),
+            ("email_column", "Email"),
+            ("name_columns", "Name"),
+            ("attribute_columns", ""),
+            ("list", "default"),
+            ("status", "confirmed"),
+        ])
+        .await;
+
+    // Assert
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("ursula@example.com is on the suppression list."));
+    assert_eq!(n_subscribers(&test_app).await, 0);
+}
+
+#[tokio::test]
+async fn issues_are_not_queued_for_suppressed_subscribers() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let email = confirmed_subscriber_email(&test_app).await;
+    test_app.test_user.login(&test_app).await;
+    suppress(&test_app, &email.to_uppercase()).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    publish_issue(&test_app).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_deliveries =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issue_deliveries"#)
+            .fetch_one(&test_app.db_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(n_deliveries, 0);
+}
+
+#[tokio::test]
+async fn deliveries_queued_before_a_suppression_are_not_sent() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let email = confirmed_subscriber_email(&test_app).await;
+    test_app.test_user.login(&test_app).await;
+    publish_issue(&test_app).await;
+    suppress(&test_app, &email).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let delivery = sqlx::query!("SELECT status FROM newsletter_issue_deliveries")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "suppressed");
+}
+
+#[tokio::test]
+async fn batches_of_deliveries_that_are_all_suppressed_are_not_sent() {
+    // Arrange
+    let mut test_app = spawn_app().await;
+    test_app.app_config.worker.batch_size = 10;
+    let email = confirmed_subscriber_email(&test_app).await;
+    test_app.test_user.login(&test_app).await;
+    publish_issue(&test_app).await;
+    suppress(&test_app, &email).await;
+    when_sending_a_batch()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let delivery = sqlx::query!("SELECT status FROM newsletter_issue_deliveries")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "suppressed");
+}
+
+#[tokio::test]
+async fn the_legacy_endpoint_does_not_publish_to_suppressed_subscribers() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let email = confirmed_subscriber_email(&test_app).await;
+    test_app.test_user.login(&test_app).await;
+    suppress(&test_app, &email).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn erased_subscribers_can_subscribe_again_once_their_suppression_is_removed() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    let subscriber = sqlx::query!("SELECT id, email FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    test_app
+        .post_data_erase(&test_app.data_token(subscriber.id))
+        .await
+        .error_for_status()
+        .unwrap();
+    let body =
+        serde_urlencoded::to_string([("name", "Ursula"), ("email", subscriber.email.as_str())])
+            .unwrap();
+    test_app.test_user.login(&test_app).await;
+
+    // Act - Part 1 - Subscribe while suppressed
+    test_app.post_subscriptions(body.clone()).await;
+
+    // Assert - Part 1
+    assert_eq!(n_subscribers(&test_app).await, 0);
+
+    // Act - Part 2 - Lift the suppression and subscribe
+    test_app
+        .post_suppressions(&[
+            ("email", subscriber.email.as_str()),
+            ("reason", "manual"),
+            ("action", "remove"),
+        ])
+        .await;
+    let _mock_guard = when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&test_app.email_server)
+        .await;
+    let n_sent = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .len();
+    test_app.post_subscriptions(body).await;
+
+    // Assert - Part 2
+    assert_eq!(n_subscribers(&test_app).await, 1);
+    test_app.wait_for_emails(n_sent + 1).await;
+}
@@ -0,0 +1,188 @@
+use {
+    crate::helpers::{assert_is_redirect_to, spawn_app, when_sending_an_email, TestApp},
+    uuid::Uuid,
+    wiremock::ResponseTemplate,
+};
+
+/// Subscribe with the address and follow the confirmation link
+async fn create_subscriber(test_app: &TestApp, email: &str) {
+    let n_sent = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .len();
+    let body = serde_urlencoded::to_string([("name", "Hoon Wee"), ("email", email)]).unwrap();
+    test_app
+        .post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &test_app.wait_for_emails(n_sent + 1).await[n_sent];
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+async fn tag(test_app: &TestApp, email: &str, tags: &str) {
+    let response = test_app
+        .post_tags(&[("email", email), ("tags", tags), ("action", "add")])
+        .await;
+    assert_is_redirect_to(&response, "/admin/tags");
+}
+
+async fn get_tags_html(test_app: &TestApp) -> String {
+    test_app
+        .api_client
+        .get(format!("{}/admin/tags", test_app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap()
+}
+
+async fn publish_to_segment(test_app: &TestApp, segment: &str) -> reqwest::Response {
+    test_app
+        .post_publish_issue(&serde_json::json!({
+            "title": "Title",
+            "text_content": "Text Content",
+            "html_content": "<p>Html Content</p>",
+            "segment": segment,
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await
+}
+
+#[tokio::test]
+async fn tagged_subscribers_are_counted_on_the_tags_page() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    create_subscriber(&test_app, "first@example.com").await;
+    create_subscriber(&test_app, "second@example.com").await;
+
+    // Act
+    tag(&test_app, "first@example.com", "beta, Region:EU").await;
+    tag(&test_app, "second@example.com", "beta").await;
+
+    // Assert
+    let html_page = get_tags_html(&test_app).await;
+    assert!(html_page.contains("<li><b>beta</b>: 2 subscribers</li>"));
+    assert!(html_page.contains("<li><b>region:eu</b>: 1 subscribers</li>"));
+}
+
+#[tokio::test]
+async fn tags_can_be_removed() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    create_subscriber(&test_app, "first@example.com").await;
+    tag(&test_app, "first@example.com", "beta paid").await;
+
+    // Act
+    test_app
+        .post_tags(&[
+            ("email", "first@example.com"),
+            ("tags", "beta"),
+            ("action", "remove"),
+        ])
+        .await;
+
+    // Assert
+    let html_page = get_tags_html(&test_app).await;
+    assert!(!html_page.contains("<b>beta</b>"));
+    assert!(html_page.contains("<li><b>paid</b>: 1 subscribers</li>"));
+}
+
+#[tokio::test]
+async fn tagging_an_unknown_subscriber_is_reported() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+
+    // Act
+    tag(&test_app, "nobody@example.com", "beta").await;
+
+    // Assert
+    let html_page = get_tags_html(&test_app).await;
+    assert!(html_page.contains("There is no subscriber nobody@example.com."));
+}
+
+#[tokio::test]
+async fn issues_only_reach_subscribers_in_the_segment() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    create_subscriber(&test_app, "beta@example.com").await;
+    create_subscriber(&test_app, "paid-beta@example.com").await;
+    create_subscriber(&test_app, "eu@example.com").await;
+    create_subscriber(&test_app, "untagged@example.com").await;
+    tag(&test_app, "beta@example.com", "beta").await;
+    tag(&test_app, "paid-beta@example.com", "beta paid").await;
+    tag(&test_app, "eu@example.com", "region:eu").await;
+    let n_sent = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .len();
+
+    // Act
+    let response = publish_to_segment(&test_app, "(beta and not paid) or region:eu").await;
+    assert_is_redirect_to(&response, "/admin/newsletter");
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let mut recipients: Vec<String> = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .skip(n_sent)
+        .map(|r| {
+            let body: serde_json::Value = serde_json::from_slice(&r.body).unwrap();
+            body["To"].as_str().unwrap().to_owned()
+        })
+        .collect();
+    recipients.sort();
+    assert_eq!(recipients, vec!["beta@example.com", "eu@example.com"]);
+}
+
+#[tokio::test]
+async fn invalid_segments_are_rejected() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+
+    // Act
+    let response = publish_to_segment(&test_app, "beta and (paid").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletter");
+    let html_page = test_app.get_admin_newsletter_html().await;
+    assert!(html_page.contains("in segment, found end."));
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
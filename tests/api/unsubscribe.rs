@@ -0,0 +1,211 @@
+use {
+    crate::helpers::{
+        create_confirmed_subscriber, publish_issue, spawn_app, when_sending_an_email, TestApp,
+    },
+    wiremock::ResponseTemplate,
+};
+
+/// Send an issue to a single confirmed subscriber and return the request made to the email provider
+async fn deliver_an_issue(test_app: &TestApp) -> wiremock::Request {
+    create_confirmed_subscriber(test_app).await;
+    test_app.test_user.login(test_app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    publish_issue(test_app).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // The confirmation email came first, the issue is the last one
+    test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap()
+}
+
+async fn subscriber_status(test_app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+async fn n_list_subscriptions(test_app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM list_subscriptions"#)
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn issues_carry_a_one_click_unsubscribe_link() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let email_request = deliver_an_issue(&test_app).await;
+
+    // Assert
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let headers = body["Headers"].as_array().unwrap();
+    assert!(headers.iter().any(|header| {
+        header["Name"] == "List-Unsubscribe-Post" && header["Value"] == "List-Unsubscribe=One-Click"
+    }));
+    let unsubscribe_link = test_app.get_unsubscribe_link(&email_request);
+    assert_eq!(unsubscribe_link.path(), "/subscriptions/unsubscribe");
+    let token = unsubscribe_link.query_pairs().next().unwrap().1;
+    assert!(body["HtmlBody"].as_str().unwrap().contains(token.as_ref()));
+    assert!(body["TextBody"].as_str().unwrap().contains(token.as_ref()));
+}
+
+#[tokio::test]
+async fn the_unsubscribe_link_asks_for_a_confirmation() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let email_request = deliver_an_issue(&test_app).await;
+    let unsubscribe_link = test_app.get_unsubscribe_link(&email_request);
+
+    // Act
+    let response = reqwest::get(unsubscribe_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"<form action="/subscriptions/unsubscribe?token="#));
+    assert!(html_page.contains(r#"<form action="/subscriptions/unsubscribe/all?token="#));
+    assert_eq!(subscriber_status(&test_app).await, "confirmed");
+    assert_eq!(n_list_subscriptions(&test_app).await, 1);
+}
+
+#[tokio::test]
+async fn posting_to_the_unsubscribe_link_leaves_the_list_in_one_click() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let email_request = deliver_an_issue(&test_app).await;
+    let unsubscribe_link = test_app.get_unsubscribe_link(&email_request);
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .form(&[("List-Unsubscribe", "One-Click")])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(n_list_subscriptions(&test_app).await, 0);
+    assert_eq!(subscriber_status(&test_app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn the_unsubscribe_link_can_also_unsubscribe_from_everything() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let email_request = deliver_an_issue(&test_app).await;
+    let mut unsubscribe_link = test_app.get_unsubscribe_link(&email_request);
+    unsubscribe_link.set_path("/subscriptions/unsubscribe/all");
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriber = sqlx::query!("SELECT status, unsubscribed_at FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.status, "unsubscribed");
+    assert!(subscriber.unsubscribed_at.is_some());
+    assert_eq!(n_list_subscriptions(&test_app).await, 0);
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_do_not_receive_later_issues() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let email_request = deliver_an_issue(&test_app).await;
+    let unsubscribe_link = test_app.get_unsubscribe_link(&email_request);
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let n_sent = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .len();
+
+    // Act
+    publish_issue(&test_app).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_received = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .len();
+    assert_eq!(n_received, n_sent);
+}
+
+#[tokio::test]
+async fn tampered_unsubscribe_tokens_are_rejected_with_a_401() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let email_request = deliver_an_issue(&test_app).await;
+    let mut unsubscribe_link = test_app.get_unsubscribe_link(&email_request);
+    let token = unsubscribe_link
+        .query_pairs()
+        .next()
+        .unwrap()
+        .1
+        .into_owned();
+    let (scope, signature) = token.rsplit_once('.').unwrap();
+    let tampered_token = format!("{}.{}", scope, "0".repeat(signature.len()));
+    unsubscribe_link.set_query(Some(&format!("token={}", tampered_token)));
+
+    // Act
+    let get_response = reqwest::get(unsubscribe_link.clone()).await.unwrap();
+    let post_response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(get_response.status().as_u16(), 401);
+    assert_eq!(post_response.status().as_u16(), 401);
+    assert_eq!(subscriber_status(&test_app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribing_without_token_is_rejected_with_a_400() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions/unsubscribe", test_app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
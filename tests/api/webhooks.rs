@@ -0,0 +1,257 @@
+use {
+    crate::helpers::{
+        create_confirmed_subscriber, publish_issue, spawn_app, spawn_app_with,
+        when_sending_an_email, TestApp,
+    },
+    wiremock::ResponseTemplate,
+};
+
+// Payloads recorded from Postmark, all about the same message
+const HARD_BOUNCE: &str = include_str!("fixtures/postmark/hard_bounce.json");
+const SOFT_BOUNCE: &str = include_str!("fixtures/postmark/soft_bounce.json");
+const AUTO_RESPONDER: &str = include_str!("fixtures/postmark/auto_responder.json");
+const SPAM_COMPLAINT: &str = include_str!("fixtures/postmark/spam_complaint.json");
+const MESSAGE_ID: &str = "883953f4-6105-42a2-a16a-77a8eac79483";
+
+/// A recorded payload, about `email` and the message we sent it
+fn recorded_payload(recorded: &str, email: &str) -> serde_json::Value {
+    let mut payload: serde_json::Value = serde_json::from_str(recorded).unwrap();
+    payload["Email"] = email.into();
+    payload["MessageID"] = MESSAGE_ID.into();
+    payload
+}
+
+/// Send an issue to a single confirmed subscriber and return their address
+async fn deliver_an_issue(test_app: &TestApp) -> String {
+    create_confirmed_subscriber(test_app).await;
+    test_app.test_user.login(test_app).await;
+    let _mock_guard = when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "MessageID": MESSAGE_ID,
+            "ErrorCode": 0,
+            "Message": "OK"
+        })))
+        .mount_as_scoped(&test_app.email_server)
+        .await;
+    publish_issue(test_app).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .email
+}
+
+async fn suppression_reason(test_app: &TestApp) -> Option<String> {
+    sqlx::query!("SELECT reason FROM suppressions")
+        .fetch_optional(&test_app.db_pool)
+        .await
+        .unwrap()
+        .map(|r| r.reason)
+}
+
+async fn delivery_status(test_app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM newsletter_issue_deliveries")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+#[tokio::test]
+async fn webhooks_without_valid_credentials_are_rejected() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let payload = recorded_payload(HARD_BOUNCE, "ursula@example.com");
+
+    for webhook in ["bounce", "complaint"] {
+        let url = format!("{}/webhooks/postmark/{}", test_app.address, webhook);
+
+        // Act
+        let anonymous = test_app
+            .api_client
+            .post(&url)
+            .json(&payload)
+            .send()
+            .await
+            .unwrap();
+        let wrong_password = test_app
+            .api_client
+            .post(&url)
+            .basic_auth(&test_app.app_config.webhooks.username, Some("wrong"))
+            .json(&payload)
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        for response in [anonymous, wrong_password] {
+            assert_eq!(response.status().as_u16(), 401);
+            assert_eq!(
+                r#"Basic realm="webhooks""#,
+                response.headers()["WWW-Authenticate"]
+            );
+        }
+    }
+    assert_eq!(suppression_reason(&test_app).await, None);
+}
+
+#[tokio::test]
+async fn malformed_payloads_are_rejected() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app
+        .post_postmark_webhook(
+            "bounce",
+            &serde_json::json!({"Email": "ursula@example.com"}),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn hard_bounces_suppress_the_address_and_update_the_delivery() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let email = deliver_an_issue(&test_app).await;
+
+    // Act
+    let response = test_app
+        .post_postmark_webhook("bounce", &recorded_payload(HARD_BOUNCE, &email))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        suppression_reason(&test_app).await.as_deref(),
+        Some("hard_bounce")
+    );
+    assert_eq!(delivery_status(&test_app).await, "bounced");
+}
+
+#[tokio::test]
+async fn spam_complaints_suppress_the_address_and_update_the_delivery() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let email = deliver_an_issue(&test_app).await;
+
+    // Act
+    let response = test_app
+        .post_postmark_webhook("complaint", &recorded_payload(SPAM_COMPLAINT, &email))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        suppression_reason(&test_app).await.as_deref(),
+        Some("spam_complaint")
+    );
+    assert_eq!(delivery_status(&test_app).await, "complained");
+}
+
+#[tokio::test]
+async fn soft_bounces_suppress_the_address_once_they_reach_the_threshold() {
+    // Arrange
+    let test_app = spawn_app_with(|c| c.webhooks.soft_bounce_threshold = 2).await;
+    let email = deliver_an_issue(&test_app).await;
+    let first_bounce = recorded_payload(SOFT_BOUNCE, &email);
+    let mut second_bounce = first_bounce.clone();
+    second_bounce["ID"] = 4323372036854775810_u64.into();
+
+    // Act - Part 1 - Below the threshold
+    let response = test_app
+        .post_postmark_webhook("bounce", &first_bounce)
+        .await;
+
+    // Assert - Part 1
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(suppression_reason(&test_app).await, None);
+    assert_eq!(delivery_status(&test_app).await, "soft_bounced");
+
+    // Act - Part 2 - Reaching the threshold
+    let response = test_app
+        .post_postmark_webhook("bounce", &second_bounce)
+        .await;
+
+    // Assert - Part 2
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        suppression_reason(&test_app).await.as_deref(),
+        Some("soft_bounces")
+    );
+}
+
+#[tokio::test]
+async fn replayed_webhooks_are_only_counted_once() {
+    // Arrange
+    let test_app = spawn_app_with(|c| c.webhooks.soft_bounce_threshold = 2).await;
+    let email = deliver_an_issue(&test_app).await;
+    let payload = recorded_payload(SOFT_BOUNCE, &email);
+
+    // Act
+    for _ in 0..2 {
+        let response = test_app.post_postmark_webhook("bounce", &payload).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    // Assert
+    assert_eq!(suppression_reason(&test_app).await, None);
+    let n_bounces = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM email_bounces"#)
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_bounces, 1);
+}
+
+#[tokio::test]
+async fn reports_that_are_not_bounces_are_ignored() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let email = deliver_an_issue(&test_app).await;
+
+    // Act
+    let response = test_app
+        .post_postmark_webhook("bounce", &recorded_payload(AUTO_RESPONDER, &email))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(suppression_reason(&test_app).await, None);
+    assert_eq!(delivery_status(&test_app).await, "sent");
+}
+
+#[tokio::test]
+async fn lifting_a_suppression_forgets_the_soft_bounces_of_the_address() {
+    // Arrange
+    let test_app = spawn_app_with(|c| c.webhooks.soft_bounce_threshold = 1).await;
+    let email = deliver_an_issue(&test_app).await;
+    test_app
+        .post_postmark_webhook("bounce", &recorded_payload(SOFT_BOUNCE, &email))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    test_app
+        .post_suppressions(&[
+            ("email", email.as_str()),
+            ("reason", "manual"),
+            ("action", "remove"),
+        ])
+        .await;
+
+    // Assert
+    assert_eq!(suppression_reason(&test_app).await, None);
+    let n_bounces = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM email_bounces"#)
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_bounces, 0);
+}